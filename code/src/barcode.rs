@@ -0,0 +1,97 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BarcodeError {
+    NotDigits(String),
+    BadLength(String), // Not 8, 12, 13 or 14 digits
+    BadCheckDigit { code: String, expected: u32 },
+}
+
+impl fmt::Display for BarcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BarcodeError::NotDigits(code) =>
+                write!(f, "barcode '{}' must contain only digits", code),
+            BarcodeError::BadLength(code) =>
+                write!(f, "barcode '{}' has {} digits; expected 8 (EAN-8), 12 (UPC-A), 13 (EAN-13) or 14 (GTIN-14)", code, code.len()),
+            BarcodeError::BadCheckDigit { code, expected } =>
+                write!(f, "barcode '{}' has a wrong check digit (expected {}); check for a mistyped digit", code, expected),
+        }
+    }
+}
+
+impl std::error::Error for BarcodeError {}
+
+// Validates a UPC or EAN barcode and returns its digits. Spaces and hyphens, as printed
+// under some barcodes, are ignored.
+pub fn parse_barcode(input: &str) -> Result<String, BarcodeError> {
+    let code: String = input.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+    if code.is_empty() || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(BarcodeError::NotDigits(input.trim().to_string()));
+    }
+    if ![8, 12, 13, 14].contains(&code.len()) {
+        return Err(BarcodeError::BadLength(code));
+    }
+    let (body, check) = code.split_at(code.len() - 1);
+    let expected = check_digit(body);
+    if check.parse::<u32>().ok() != Some(expected) {
+        return Err(BarcodeError::BadCheckDigit { code, expected });
+    }
+    Ok(code)
+}
+
+// GS1 check digit: digits are weighted 3, 1, 3, ... from the right
+fn check_digit(body: &str) -> u32 {
+    let sum: u32 = body.chars().rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { digit })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+// The code padded to 14 digits. A UPC-A code is the EAN-13 code with its leading zero
+// dropped, so padding makes the different forms of one product's code equal.
+pub fn gtin14(code: &str) -> String {
+    format!("{:0>14}", code)
+}
+
+pub fn same_product(a: &str, b: &str) -> bool {
+    gtin14(a) == gtin14(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_codes() {
+        assert_eq!(parse_barcode("73513537"), Ok("73513537".to_string())); // EAN-8
+        assert_eq!(parse_barcode("036000291452"), Ok("036000291452".to_string())); // UPC-A
+        assert_eq!(parse_barcode("4006381333931"), Ok("4006381333931".to_string())); // EAN-13
+        assert_eq!(parse_barcode("4 006381 333931"), Ok("4006381333931".to_string()));
+        assert_eq!(parse_barcode("0-36000-29145-2"), Ok("036000291452".to_string()));
+    }
+
+    #[test]
+    fn rejects_wrong_check_digit() {
+        assert_eq!(
+            parse_barcode("4006381333932"),
+            Err(BarcodeError::BadCheckDigit { code: "4006381333932".to_string(), expected: 1 })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(parse_barcode("40063813339a1"), Err(BarcodeError::NotDigits("40063813339a1".to_string())));
+        assert_eq!(parse_barcode(""), Err(BarcodeError::NotDigits(String::new())));
+        assert_eq!(parse_barcode("1234567"), Err(BarcodeError::BadLength("1234567".to_string())));
+    }
+
+    #[test]
+    fn upc_and_ean_forms_are_the_same_product() {
+        assert!(same_product("036000291452", "0036000291452"));
+        assert!(same_product("036000291452", "00036000291452"));
+        assert!(!same_product("036000291452", "4006381333931"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use colored::*;
+use crate::food::Food;
+use crate::log::{DailyLog, ReportMode, date_range, offset_date};
+use crate::profile::{Profile, TargetStatus};
+use crate::weight::TrendPoint;
+
+// Days the rolling average looks back over, including the day itself
+pub const ROLLING_WINDOW: usize = 7;
+pub const DEFAULT_CHART_DAYS: usize = 14;
+pub const MAX_CHART_DAYS: usize = 90;
+
+const BAR_WIDTH: usize = 40;
+const LINE_HEIGHT: usize = 12;
+
+// One day on a chart
+#[derive(Debug, Clone)]
+pub struct ChartPoint {
+    pub date: String,
+    pub consumed: Option<f32>, // None when nothing was logged that day
+    pub target: f32,
+    pub average: Option<f32>, // Mean of the logged days in the rolling window
+}
+
+// Consumed calories, targets and rolling averages for the `days` days ending on `end`
+pub fn calorie_series(log: &DailyLog, profile: &Profile, foods: &HashMap<String, Food>, mode: ReportMode, end: &str, days: usize) -> Option<Vec<ChartPoint>> {
+    // Start early enough that the first day's average has a full window behind it
+    let lookback = days.max(1) + ROLLING_WINDOW - 1;
+    let start = offset_date(end, 1 - lookback as i64)?;
+    let dates = date_range(&start, end)?;
+    let consumed: Vec<Option<f32>> = dates.iter()
+        .map(|date| log.has_entries_for_date(date).then(|| log.get_total_calories(date, foods, mode)))
+        .collect();
+
+    Some(dates.iter().enumerate().skip(ROLLING_WINDOW - 1).map(|(i, date)| {
+        let window: Vec<f32> = consumed[i + 1 - ROLLING_WINDOW..=i].iter().flatten().copied().collect();
+        ChartPoint {
+            date: date.clone(),
+            consumed: consumed[i],
+            target: profile.get_daily_target(date),
+            average: (!window.is_empty()).then(|| window.iter().sum::<f32>() / window.len() as f32),
+        }
+    }).collect())
+}
+
+fn chart_max(points: &[ChartPoint]) -> f32 {
+    points.iter()
+        .flat_map(|point| [point.consumed.unwrap_or(0.0), point.target, point.average.unwrap_or(0.0)])
+        .fold(0.0, f32::max)
+        .max(1.0)
+}
+
+fn status_color(text: &str, consumed: f32, target: f32) -> ColoredString {
+    match TargetStatus::evaluate(consumed, target) {
+        TargetStatus::OnTarget => text.green(),
+        TargetStatus::Under => text.blue(),
+        TargetStatus::Over => text.red(),
+    }
+}
+
+// One horizontal bar per day, with the target marked │ and the rolling average ◆
+pub fn bar_chart(points: &[ChartPoint]) -> Vec<String> {
+    let max = chart_max(points);
+    let position = |value: f32| (((value / max) * BAR_WIDTH as f32).round() as usize).min(BAR_WIDTH);
+    let mut lines = Vec::new();
+    for point in points {
+        let filled = point.consumed.map_or(0, position);
+        let target = position(point.target);
+        let average = point.average.map(position);
+        let bar: String = (0..=BAR_WIDTH).map(|cell| {
+            if Some(cell) == average && cell != target {
+                "◆".yellow().to_string()
+            } else if cell == target {
+                "│".bright_white().bold().to_string()
+            } else if cell < filled {
+                status_color("█", point.consumed.unwrap_or(0.0), point.target).to_string()
+            } else {
+                " ".to_string()
+            }
+        }).collect();
+        let label = match point.consumed {
+            Some(consumed) => format!("{:>6.0} / {:.0} kcal", consumed, point.target),
+            None => format!("{:>6} / {:.0} kcal", "-", point.target).bright_black().to_string(),
+        };
+        lines.push(format!("{} {}  {}", point.date.get(5..).unwrap_or(&point.date), bar, label));
+    }
+    lines.push(format!("      {} consumed  {} target  {} {}-day average",
+                       "█".green(), "│".bright_white().bold(), "◆".yellow(), ROLLING_WINDOW));
+    lines
+}
+
+// Days across, calories up: ● consumed, ── target, • rolling average
+pub fn line_chart(points: &[ChartPoint]) -> Vec<String> {
+    let max = chart_max(points);
+    let row_of = |value: f32| (((value / max) * LINE_HEIGHT as f32).ceil() as usize).clamp(1, LINE_HEIGHT);
+    let mut lines = Vec::new();
+    for row in (1..=LINE_HEIGHT).rev() {
+        let axis = if row == LINE_HEIGHT || row % 3 == 0 {
+            format!("{:>6.0} ┤", max * row as f32 / LINE_HEIGHT as f32)
+        } else {
+            format!("{:>6} │", "")
+        };
+        let cells: String = points.iter().map(|point| {
+            if point.consumed.is_some_and(|consumed| row_of(consumed) == row) {
+                status_color("● ", point.consumed.unwrap_or(0.0), point.target).to_string()
+            } else if point.average.is_some_and(|average| row_of(average) == row) {
+                "• ".yellow().to_string()
+            } else if row_of(point.target) == row {
+                "──".bright_white().to_string()
+            } else {
+                "  ".to_string()
+            }
+        }).collect();
+        lines.push(format!("{}{}", axis.bright_black(), cells));
+    }
+    lines.push(format!("{:>6} └{}", "0", "──".repeat(points.len())).bright_black().to_string());
+
+    // A MM-DD label under every week, where it fits
+    let mut labels = vec![' '; points.len() * 2 + 6];
+    for (i, point) in points.iter().enumerate().step_by(7) {
+        let label = point.date.get(5..).unwrap_or(&point.date);
+        if i * 2 + label.len() <= labels.len() {
+            for (j, c) in label.chars().enumerate() {
+                labels[i * 2 + j] = c;
+            }
+        }
+    }
+    lines.push(format!("{:>8}{}", "", labels.into_iter().collect::<String>().trim_end()));
+    lines.push(format!("        {} consumed  {} target  {} {}-day average",
+                       "●".green(), "──".bright_white(), "•".yellow(), ROLLING_WINDOW));
+    lines
+}
+
+// Weigh-ins across, weight up: ● weigh-in, • smoothed trend. The scale spans only the range
+// of the weights so small changes are visible.
+pub fn weight_chart(points: &[TrendPoint]) -> Vec<String> {
+    let values = points.iter().flat_map(|point| [point.weight_kg, point.trend_kg]);
+    let low = values.clone().fold(f32::MAX, f32::min);
+    let high = values.fold(f32::MIN, f32::max).max(low + 1.0);
+    let row_of = |value: f32| ((((value - low) / (high - low)) * (LINE_HEIGHT - 1) as f32).round() as usize).min(LINE_HEIGHT - 1);
+    let mut lines = Vec::new();
+    for row in (0..LINE_HEIGHT).rev() {
+        let axis = if row % 3 == 0 || row == LINE_HEIGHT - 1 {
+            format!("{:>6.1} ┤", low + (high - low) * row as f32 / (LINE_HEIGHT - 1) as f32)
+        } else {
+            format!("{:>6} │", "")
+        };
+        let cells: String = points.iter().map(|point| {
+            if row_of(point.weight_kg) == row {
+                "● ".bright_cyan().to_string()
+            } else if row_of(point.trend_kg) == row {
+                "• ".yellow().to_string()
+            } else {
+                "  ".to_string()
+            }
+        }).collect();
+        lines.push(format!("{}{}", axis.bright_black(), cells));
+    }
+    lines.push(format!("{:>6} └{}", "", "──".repeat(points.len())).bright_black().to_string());
+    lines.push(format!("        {} weigh-in  {} smoothed trend", "●".bright_cyan(), "•".yellow()));
+    lines
+}
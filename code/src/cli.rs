@@ -0,0 +1,1063 @@
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use serde_json::json;
+use crate::{AppState, MAX_COPY_DAYS, default_meal, describe_backup, print_range_report, print_weight_report};
+use crate::database::Database;
+use crate::command::{AddFoodCommand, AddScheduleRuleCommand, CopyEntriesCommand, EditFoodCommand, EditLogEntryCommand, ImportFoodsCommand, RecordWeightCommand, RemoveScheduleRuleCommand, SkipScheduledCommand, UndoableCommand, LogFoodCommand, RemoveLogEntryCommand, UpdateProfileCommand};
+use crate::food::{BasicFood, CompositeFood, Food, Nutrient, Nutrients, Quantity, RecipeYield, ServingSize, compute_nutrients};
+use crate::log::{ReportMode, date_range, offset_date};
+use crate::schedule::{ScheduleRule, parse_days};
+use crate::report::{RangeReport, WeightReport, DEFAULT_TOP_FOODS, WEIGHT_REPORT_DAYS, week_of, month_of};
+use crate::weight::{WeightEntry, WeightSource, parse_measurements};
+use crate::fdc::{Filter, OnConflict, plan_import, read_foods};
+use crate::barcode::parse_barcode;
+use crate::recipe::{match_ingredient, parse_recipe};
+use crate::search::MatchMode;
+use crate::chart::{DEFAULT_CHART_DAYS, MAX_CHART_DAYS, bar_chart, calorie_series, line_chart};
+use crate::profile::{ActivityLevel, Gender, ProfileSetting, TargetFormula, TargetStatus};
+
+const USAGE: &str = "Usage: diet_manager [--data-dir DIR] [--config FILE] [COMMAND]
+
+Runs the interactive menu when no command is given. Data is read from --data-dir,
+$DIET_MANAGER_DATA_DIR, the config file's data_dir, or ~/.local/share/diet_manager.
+The config file defaults to ~/.config/diet_manager/config.json ($DIET_MANAGER_CONFIG).
+
+Commands:
+  log add <food> [--servings N | --quantity \"150 g\"] [--meal NAME] [--date YYYY-MM-DD]
+  log add --barcode <UPC/EAN> [...]  log a packaged food by barcode, adding it from the
+                               Open Food Facts export in the config when it is not in the database
+  log remove <entry number> [--date YYYY-MM-DD]
+  log edit <entry number> [--servings N | --quantity \"150 g\"] [--meal NAME] [--date YYYY-MM-DD]
+                               change an entry's amount or meal; scheduled entries stay scheduled
+  log copy <from YYYY-MM-DD> [--meal NAME] [--date YYYY-MM-DD] [--until YYYY-MM-DD]
+                               copy a day's (or meal's) entries to --date, or each day up to --until
+  log repeat [--date YYYY-MM-DD]  log the previous day's entries again
+  log show [--date YYYY-MM-DD] [--mode historical|current] [--json]
+  food add-basic <id> --calories N [--protein N] [--carbs N] [--fat N] [--fiber N] [--sugar N]
+                 [--sodium N] [--keywords a,b] [--serving \"100 g\"] [--unit name=amount]...
+                 [--barcode UPC/EAN]...
+  food add-composite <id> --component <food>=<quantity>... [--keywords a,b] [--serving \"300 g\"]
+                 [--yield \"6 servings\" | --yield \"1.8 kg\"]
+                               the components are the whole batch; --yield divides it into servings
+  food add-recipe <id> (--file PATH | --text \"2 cups rice, 150 g salmon\") [--keywords a,b]
+                 [--serving \"300 g\"] [--yield \"6 servings\"] [--dry-run]
+                               build a composite food from an ingredient list; lines that don't
+                               clearly match one food must be reviewed in the interactive menu
+  food edit <id> [add-basic or add-composite options]
+                               replace a food's definition; past log entries keep their nutrition
+  food import-fdc <csv folder|json file> [--type foundation,sr_legacy] [--category fruit,...]
+                 [--on-conflict skip|replace|rename] [--limit N] [--dry-run]
+                               import foods from a USDA FoodData Central download
+  food list [--json]
+  food search <words...> [--all] [--json]
+                               foods ranked by how well their name and keywords match; typos
+                               and plurals are tolerated, --all requires every word to match
+  summary [--date YYYY-MM-DD] [--mode historical|current] [--json]
+  report week|month [--date YYYY-MM-DD] [--mode historical|current] [--top N] [--json]
+  report <from YYYY-MM-DD> <to YYYY-MM-DD> [--mode historical|current] [--top N] [--json]
+                               averages, days on/under/over target and top foods for a range
+  chart [bar|line] [--days N] [--date YYYY-MM-DD] [--mode historical|current]
+                               calories against target for the N days up to --date
+  weight add <kg> [--body-fat PCT] [--measure name=cm]... [--date YYYY-MM-DD]
+  weight remove [--date YYYY-MM-DD]
+  weight list [--json]
+  weight report [--from YYYY-MM-DD] [--date YYYY-MM-DD] [--json]
+                               weight trend and energy balance, by default for the 30 days to --date
+  profile show [--json]
+  profile set <gender|age|height|weight|activity|formula|weight-source> <value>
+  profile budget <meal> <calories|none>
+  schedule list [--json]
+  schedule add <food> --days daily|weekdays|weekends|mon,wed,fri [--servings N | --quantity \"250 ml\"]
+               [--meal NAME] [--date YYYY-MM-DD] [--until YYYY-MM-DD]
+                               log a food automatically on those days, from --date (default today)
+  schedule remove <rule number>
+  schedule skip <rule number> [--date YYYY-MM-DD]
+  materialize --until YYYY-MM-DD [--date YYYY-MM-DD]
+                               log scheduled entries for every day from --date up to --until
+  config show [--json]
+  undo                         undo the last change, even one from an earlier session
+  redo                         reapply the last undone change
+  history [--json]             list changes that can be undone and redone
+  restore [--json]             list backups, newest first
+  restore <backup number>      roll a data file back to that backup
+  help";
+
+// Options that never take a value
+const FLAGS: [&str; 4] = ["json", "help", "dry-run", "all"];
+
+// Parsed command line: positional words plus `--name value` options and bare `--flag`s
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, Vec<String>>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse(raw: &[String]) -> Result<Args, String> {
+        let mut args = Args {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            flags: Vec::new(),
+        };
+        let mut iter = raw.iter();
+        while let Some(arg) = iter.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    args.positional.push(arg.clone());
+                    continue;
+                }
+            };
+            if let Some((name, value)) = name.split_once('=') {
+                args.options.entry(name.to_string()).or_default().push(value.to_string());
+            } else if FLAGS.contains(&name) {
+                args.flags.push(name.to_string());
+            } else {
+                let value = iter.next().ok_or_else(|| format!("--{} needs a value", name))?;
+                args.options.entry(name.to_string()).or_default().push(value.clone());
+            }
+        }
+        Ok(args)
+    }
+
+    fn word(&self, index: usize) -> Option<&str> {
+        self.positional.get(index).map(|s| s.as_str())
+    }
+
+    fn required_word(&self, index: usize, what: &str) -> Result<&str, String> {
+        self.word(index).ok_or_else(|| format!("missing {}", what))
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|values| values.last()).map(|s| s.as_str())
+    }
+
+    fn all(&self, name: &str) -> &[String] {
+        self.options.get(name).map(|values| values.as_slice()).unwrap_or(&[])
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    fn number(&self, name: &str) -> Result<Option<f32>, String> {
+        self.option(name)
+            .map(|value| value.parse::<f32>().map_err(|_| format!("--{} expects a number, got '{}'", name, value)))
+            .transpose()
+    }
+
+    // The --date option, or the app's current date when it is absent
+    fn date(&self, state: &AppState) -> Result<String, String> {
+        match self.option("date") {
+            Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(|d| d.format("%Y-%m-%d").to_string())
+                .map_err(|_| format!("invalid date '{}' (expected YYYY-MM-DD)", date)),
+            None => Ok(state.current_date.clone()),
+        }
+    }
+
+    fn mode(&self, state: &AppState) -> Result<ReportMode, String> {
+        match self.option("mode") {
+            Some("historical") => Ok(ReportMode::Historical),
+            Some("current") => Ok(ReportMode::Current),
+            Some(other) => Err(format!("unknown mode '{}' (expected historical or current)", other)),
+            None => Ok(state.report_mode),
+        }
+    }
+}
+
+// Runs one non-interactive command and returns the process exit code
+pub fn run(raw_args: &[String], state: &mut AppState) -> i32 {
+    let result = Args::parse(raw_args).and_then(|args| {
+        if args.flag("help") {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        match args.word(0) {
+            Some("log") => run_log(&args, state),
+            Some("food") => run_food(&args, state),
+            Some("summary") => run_summary(&args, state),
+            Some("report") => run_report(&args, state),
+            Some("chart") => run_chart(&args, state),
+            Some("weight") => run_weight(&args, state),
+            Some("profile") => run_profile(&args, state),
+            Some("schedule") => run_schedule(&args, state),
+            Some("materialize") => run_materialize(&args, state),
+            Some("config") => run_config(&args, state),
+            Some("undo") => run_undo(state, false),
+            Some("redo") => run_undo(state, true),
+            Some("history") => run_history(&args, state),
+            Some("restore") => run_restore(&args, state),
+            Some("help") | None => {
+                println!("{}", USAGE);
+                Ok(())
+            },
+            Some(other) => Err(format!("unknown command '{}'", other)),
+        }
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            eprintln!("Run 'diet_manager help' for usage.");
+            1
+        }
+    }
+}
+
+// Finds a food by exact id, falling back to a case-insensitive match
+fn find_food<'a>(db: &'a Database, id: &str) -> Result<&'a Food, String> {
+    db.foods.get(id)
+        .or_else(|| db.foods.values().find(|food| food.id().eq_ignore_ascii_case(id)))
+        .ok_or_else(|| format!("no food named '{}'", id))
+}
+
+// --servings N or --quantity "150 g", defaulting to one serving
+fn quantity(args: &Args) -> Result<Quantity, String> {
+    match (args.number("servings")?, args.option("quantity")) {
+        (Some(_), Some(_)) => Err("use either --servings or --quantity, not both".to_string()),
+        (Some(servings), None) => Ok(Quantity::servings(servings)),
+        (None, Some(quantity)) => Quantity::parse(quantity)
+            .ok_or_else(|| format!("invalid quantity '{}'", quantity)),
+        (None, None) => Ok(Quantity::servings(1.0)),
+    }
+}
+
+// The profile's name for the --meal option, if given
+fn meal(args: &Args, state: &AppState) -> Result<Option<String>, String> {
+    match args.option("meal") {
+        Some(name) => Ok(Some(state.profile.find_meal(name)
+            .ok_or_else(|| format!("unknown meal '{}' (meals: {})", name, state.profile.meal_names().join(", ")))?
+            .name.clone())),
+        None => Ok(None),
+    }
+}
+
+fn run_log(args: &Args, state: &mut AppState) -> Result<(), String> {
+    let date = args.date(state)?;
+    state.apply_schedule(std::slice::from_ref(&date));
+    match args.word(1) {
+        Some("add") => {
+            let food_id = match args.option("barcode") {
+                Some(code) => {
+                    let (food_id, added) = state.food_for_barcode(code)?;
+                    if added {
+                        println!("Added '{}' from Open Food Facts", food_id);
+                    }
+                    food_id
+                },
+                None => args.required_word(2, "food id or --barcode")?.to_string(),
+            };
+            let food = find_food(&state.db, &food_id)?;
+            let quantity = quantity(args)?;
+            // Without --meal an entry for today goes to the meal for the current time of day
+            let meal = match meal(args, state)? {
+                Some(meal) => Some(meal),
+                None => default_meal(&state.profile, &date),
+            };
+            let command = LogFoodCommand::new(&date, food, quantity, meal, &state.db.foods)
+                .map_err(|e| e.to_string())?;
+            let servings = command.servings();
+            let food_id = food.id().to_string();
+            let calories = compute_nutrients(food, &state.db.foods).calories * servings;
+
+            if !state.execute_command(command) {
+                return Err("failed to log food".to_string());
+            }
+            println!("Logged {:.1} serving(s) of {} ({:.1} kcal) on {}", servings, food_id, calories, date);
+            Ok(())
+        },
+        Some("remove") => {
+            let number: usize = args.required_word(2, "entry number")?.parse()
+                .map_err(|_| "entry number must be a positive integer".to_string())?;
+            let entries = state.daily_log.get_log_entries(&date);
+            if number == 0 || number > entries.len() {
+                return Err(format!("{} has no entry #{}", date, number));
+            }
+            let entry = entries[number - 1].clone();
+            let food_id = entry.food_id.clone();
+            let command = RemoveLogEntryCommand::new(&date, entry);
+            if !state.execute_command(command) {
+                return Err("failed to remove entry".to_string());
+            }
+            println!("Removed entry #{} ({}) from {}", number, food_id, date);
+            Ok(())
+        },
+        Some("edit") => {
+            let number: usize = args.required_word(2, "entry number")?.parse()
+                .map_err(|_| "entry number must be a positive integer".to_string())?;
+            let entries = state.daily_log.get_log_entries(&date);
+            if number == 0 || number > entries.len() {
+                return Err(format!("{} has no entry #{}", date, number));
+            }
+            let entry = entries[number - 1].clone();
+            let food = find_food(&state.db, &entry.food_id)?;
+            // Options that aren't given keep the entry's current amount and meal
+            let quantity = if args.number("servings")?.is_some() || args.option("quantity").is_some() {
+                quantity(args)?
+            } else {
+                entry.quantity.clone().unwrap_or(Quantity::servings(entry.servings))
+            };
+            let meal = match meal(args, state)? {
+                Some(meal) => Some(meal),
+                None => entry.meal.clone(),
+            };
+            let command = EditLogEntryCommand::new(&date, &entry, food, quantity, meal)
+                .map_err(|e| e.to_string())?;
+            if !state.execute_command(command) {
+                return Err("failed to edit entry".to_string());
+            }
+            println!("Updated entry #{} ({}) on {}", number, entry.food_id, date);
+            Ok(())
+        },
+        Some("show") => {
+            let mode = args.mode(state)?;
+            let entries = state.daily_log.get_log_entries(&date);
+            if args.flag("json") {
+                let entries_json: Vec<_> = entries.iter().map(|entry| {
+                    let nutrients = entry.nutrients(&state.db.foods, mode);
+                    json!({
+                        "id": entry.id,
+                        "logged_at": entry.logged_at,
+                        "food_id": entry.food_id,
+                        "servings": entry.servings,
+                        "quantity": entry.quantity,
+                        "meal": entry.meal,
+                        "rule": entry.rule,
+                        "nutrients": nutrients,
+                    })
+                }).collect();
+                print_json(&json!({ "date": date, "mode": mode.name(), "entries": entries_json }))
+            } else {
+                if entries.is_empty() {
+                    println!("No food entries for {}", date);
+                }
+                for (meal, entries) in state.daily_log.entries_by_meal(&date, &state.profile.meal_names()) {
+                    let subtotal: f32 = entries.iter().map(|(_, entry)| entry.nutrients(&state.db.foods, mode).calories).sum();
+                    println!("{} ({:.1} kcal)", meal, subtotal);
+                    for (i, entry) in entries {
+                        let calories = entry.nutrients(&state.db.foods, mode).calories;
+                        let time = entry.logged_time().map(|time| format!("[{}] ", time)).unwrap_or_default();
+                        let scheduled = if entry.rule.is_some() { " (scheduled)" } else { "" };
+                        println!("  {}. {}{} - {:.1} serving(s), {:.1} kcal{}", i + 1, time, entry.food_id, entry.servings, calories, scheduled);
+                    }
+                }
+                Ok(())
+            }
+        },
+        Some("copy") | Some("repeat") => {
+            let source = match args.word(1) {
+                Some("repeat") => offset_date(&date, -1).ok_or_else(|| format!("invalid date '{}'", date))?,
+                _ => {
+                    let source = args.required_word(2, "date to copy from")?;
+                    NaiveDate::parse_from_str(source, "%Y-%m-%d")
+                        .map_err(|_| format!("invalid date '{}' (expected YYYY-MM-DD)", source))?;
+                    source.to_string()
+                },
+            };
+            let meal = meal(args, state)?;
+            let until = args.option("until").unwrap_or(&date);
+            let targets = date_range(&date, until)
+                .ok_or_else(|| format!("invalid range {} to {}", date, until))?;
+            if targets.len() > MAX_COPY_DAYS {
+                return Err(format!("copy to at most {} days at a time", MAX_COPY_DAYS));
+            }
+
+            let command = CopyEntriesCommand::new(&source, meal, targets);
+            let description = command.description();
+            if !state.execute_command(command) {
+                return Err(format!("nothing to copy from {}", source));
+            }
+            println!("{}", description);
+            Ok(())
+        },
+        _ => Err("expected 'log add', 'log remove', 'log edit', 'log copy', 'log repeat' or 'log show'".to_string()),
+    }
+}
+
+fn run_food(args: &Args, state: &mut AppState) -> Result<(), String> {
+    match args.word(1) {
+        Some("add-basic") => {
+            let id = args.required_word(2, "food id")?.to_string();
+            add_food(state, basic_food(args, id)?)
+        },
+        Some("add-composite") => {
+            let id = args.required_word(2, "food id")?.to_string();
+            let food = composite_food(args, state, id)?;
+            add_food(state, food)
+        },
+        Some("edit") => {
+            let id = args.required_word(2, "food id")?.to_string();
+            let food = match state.db.foods.get(&id) {
+                Some(Food::Basic(_)) => basic_food(args, id)?,
+                Some(Food::Composite(_)) => composite_food(args, state, id)?,
+                None => return Err(format!("no food named '{}'", id)),
+            };
+            edit_food(state, food)
+        },
+        Some("add-recipe") => {
+            let id = args.required_word(2, "food id")?.to_string();
+            let text = match (args.option("file"), args.option("text")) {
+                (Some(path), None) => std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?,
+                (None, Some(text)) => text.to_string(),
+                _ => return Err("give the ingredients with either --file or --text".to_string()),
+            };
+            let ingredients = parse_recipe(&text);
+            if ingredients.is_empty() {
+                return Err("no ingredients found".to_string());
+            }
+
+            let mut components = Vec::new();
+            let mut unclear = 0;
+            for found in ingredients.into_iter().map(|ingredient| match_ingredient(ingredient, &state.db.foods)) {
+                match (&found.food_id, found.servings) {
+                    (Some(food_id), Some(servings)) => {
+                        println!("{} -> {:.2} serving(s) of {}", found.ingredient.line, servings, food_id);
+                        components.push((food_id.clone(), servings));
+                    },
+                    (Some(food_id), None) => {
+                        unclear += 1;
+                        println!("{} -> {}, but the amount can't be converted", found.ingredient.line, food_id);
+                    },
+                    (None, _) if found.candidates.is_empty() => {
+                        unclear += 1;
+                        println!("{} -> no matching food", found.ingredient.line);
+                    },
+                    (None, _) => {
+                        unclear += 1;
+                        println!("{} -> unclear: {}", found.ingredient.line, found.candidates.join(", "));
+                    },
+                }
+            }
+            if args.flag("dry-run") {
+                return Ok(());
+            }
+            if unclear > 0 {
+                return Err(format!("{} ingredient(s) need review; name the foods exactly or add the recipe from the interactive menu", unclear));
+            }
+            let food = Food::Composite(CompositeFood {
+                keywords: keywords(args, &id),
+                serving: serving(args)?,
+                recipe_yield: recipe_yield(args)?,
+                id,
+                components,
+            });
+            add_food(state, food)
+        },
+        Some("import-fdc") => {
+            let path = args.required_word(2, "path to the download")?;
+            // Both options take comma-separated lists and may be repeated
+            let list = |name: &str| -> Vec<String> {
+                args.all(name).iter()
+                    .flat_map(|values| values.split(','))
+                    .map(|value| value.trim().to_string())
+                    .filter(|value| !value.is_empty())
+                    .collect()
+            };
+            let filter = Filter { data_types: list("type"), categories: list("category") };
+            let on_conflict = match args.option("on-conflict") {
+                Some(policy) => policy.parse::<OnConflict>()?,
+                None => OnConflict::Skip,
+            };
+            let limit = args.option("limit")
+                .map(|limit| limit.parse::<usize>().map_err(|_| format!("--limit expects a whole number, got '{}'", limit)))
+                .transpose()?;
+
+            let foods = read_foods(std::path::Path::new(path), &filter).map_err(|e| e.to_string())?;
+            let plan = plan_import(&foods, &state.db.foods, on_conflict, limit);
+            println!("Found {} matching food(s): {}", foods.len(), plan.summary());
+            if args.flag("dry-run") || (plan.add.is_empty() && plan.replace.is_empty()) {
+                return Ok(());
+            }
+            let command = ImportFoodsCommand::new(path, plan.add, plan.replace);
+            let description = command.description();
+            if !state.execute_command(command) {
+                return Err("import failed; nothing was changed".to_string());
+            }
+            println!("{}", description);
+            Ok(())
+        },
+        Some("list") => {
+            let mut foods: Vec<&Food> = state.db.foods.values().collect();
+            foods.sort_by(|a, b| a.id().cmp(b.id()));
+            print_foods(state, &foods, &[], args.flag("json"))
+        },
+        Some("search") => {
+            let query = args.positional.get(2..).unwrap_or_default().join(" ");
+            if query.trim().is_empty() {
+                return Err("missing search words".to_string());
+            }
+            let mode = if args.flag("all") { MatchMode::All } else { MatchMode::Any };
+            let hits = state.db.search(&query, mode);
+            let foods: Vec<&Food> = hits.iter().map(|hit| hit.food).collect();
+            let scores: Vec<f32> = hits.iter().map(|hit| hit.score).collect();
+            print_foods(state, &foods, &scores, args.flag("json"))
+        },
+        _ => Err("expected 'food add-basic', 'food add-composite', 'food add-recipe', 'food edit', 'food import-fdc', 'food list' or 'food search'".to_string()),
+    }
+}
+
+// A basic food from the add-basic options
+fn basic_food(args: &Args, id: String) -> Result<Food, String> {
+    let mut nutrients = Nutrients::default();
+    for nutrient in Nutrient::ALL {
+        let name = nutrient.name().to_lowercase();
+        match args.number(&name)? {
+            Some(value) => nutrients.set(nutrient, value),
+            None if nutrient == Nutrient::Calories => return Err("missing --calories".to_string()),
+            None => {},
+        }
+    }
+    let barcodes = args.all("barcode").iter()
+        .map(|code| parse_barcode(code).map_err(|e| e.to_string()))
+        .collect::<Result<Vec<String>, String>>()?;
+    Ok(Food::Basic(BasicFood {
+        keywords: keywords(args, &id),
+        serving: serving(args)?,
+        id,
+        nutrients,
+        barcodes,
+    }))
+}
+
+// A composite food from the add-composite options
+fn composite_food(args: &Args, state: &AppState, id: String) -> Result<Food, String> {
+    let mut components = Vec::new();
+    for component in args.all("component") {
+        let (food_id, amount) = component.split_once('=')
+            .ok_or_else(|| format!("invalid component '{}' (expected food=quantity)", component))?;
+        let food = find_food(&state.db, food_id.trim())?;
+        let quantity = Quantity::parse(amount)
+            .ok_or_else(|| format!("invalid quantity '{}'", amount))?;
+        let servings = food.servings_for(&quantity).map_err(|e| e.to_string())?;
+        components.push((food.id().to_string(), servings));
+    }
+    if components.is_empty() {
+        return Err("a composite food needs at least one --component".to_string());
+    }
+    Ok(Food::Composite(CompositeFood {
+        keywords: keywords(args, &id),
+        serving: serving(args)?,
+        recipe_yield: recipe_yield(args)?,
+        id,
+        components,
+    }))
+}
+
+// Keywords from --keywords, always including the food's own id like the interactive menu does
+fn keywords(args: &Args, id: &str) -> Vec<String> {
+    let mut keywords: Vec<String> = args.option("keywords").unwrap_or("")
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if !keywords.iter().any(|kw| kw == id) {
+        keywords.push(id.to_string());
+    }
+    keywords
+}
+
+fn serving(args: &Args) -> Result<Option<ServingSize>, String> {
+    let size = match args.option("serving") {
+        Some(size) => size,
+        None if args.all("unit").is_empty() => return Ok(None),
+        None => return Err("--unit needs a --serving size".to_string()),
+    };
+    let mut serving = ServingSize::parse(size).ok_or_else(|| format!("invalid serving size '{}'", size))?;
+    for unit in args.all("unit") {
+        let (name, amount) = unit.split_once('=')
+            .ok_or_else(|| format!("invalid unit '{}' (expected name=amount)", unit))?;
+        let amount: f32 = amount.trim().parse()
+            .map_err(|_| format!("invalid amount for unit '{}'", name))?;
+        serving.household_units.insert(name.trim().to_lowercase(), amount);
+    }
+    Ok(Some(serving))
+}
+
+// --yield "6 servings" or "1.8 kg"
+fn recipe_yield(args: &Args) -> Result<Option<RecipeYield>, String> {
+    args.option("yield")
+        .map(|input| RecipeYield::parse(input).ok_or_else(|| format!("invalid yield '{}' (expected e.g. \"6 servings\" or \"1.8 kg\")", input)))
+        .transpose()
+}
+
+fn add_food(state: &mut AppState, food: Food) -> Result<(), String> {
+    if state.db.foods.contains_key(food.id()) {
+        return Err(format!("a food named '{}' already exists; use 'food edit' to change it", food.id()));
+    }
+    let errors = state.db.validate_food(&food);
+    if !errors.is_empty() {
+        return Err(errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("; "));
+    }
+    let id = food.id().to_string();
+    let command = AddFoodCommand::new(food);
+    if !state.execute_command(command) {
+        return Err(format!("failed to add '{}'", id));
+    }
+    println!("Added '{}'", id);
+    Ok(())
+}
+
+// Replaces a food's definition. Past log entries keep the nutrition they were logged with.
+fn edit_food(state: &mut AppState, food: Food) -> Result<(), String> {
+    let errors = state.db.validate_food(&food);
+    if !errors.is_empty() {
+        return Err(errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("; "));
+    }
+    let id = food.id().to_string();
+    if !state.execute_command(EditFoodCommand::new(food)) {
+        return Err(format!("failed to update '{}'", id));
+    }
+    println!("Updated '{}'", id);
+    Ok(())
+}
+
+// Search results pass their relevance in `scores`, one per food
+fn print_foods(state: &AppState, foods: &[&Food], scores: &[f32], as_json: bool) -> Result<(), String> {
+    if as_json {
+        let foods_json: Vec<_> = foods.iter().enumerate().map(|(i, food)| {
+            let mut item = json!({
+                "food": food,
+                "nutrients_per_serving": compute_nutrients(food, &state.db.foods),
+            });
+            if let Some(score) = scores.get(i) {
+                item["score"] = json!((f64::from(*score) * 100.0).round() / 100.0);
+            }
+            item
+        }).collect();
+        return print_json(&json!(foods_json));
+    }
+    for food in foods {
+        let nutrients = compute_nutrients(food, &state.db.foods);
+        let kind = match food {
+            Food::Basic(_) => "basic".to_string(),
+            Food::Composite(CompositeFood { recipe_yield: Some(recipe_yield), .. }) => format!("composite, makes {}", recipe_yield),
+            Food::Composite(_) => "composite".to_string(),
+        };
+        println!("{} ({}) | Calories: {:.1} | {}", food.id(), kind, nutrients.calories, crate::format_macros(&nutrients));
+    }
+    Ok(())
+}
+
+fn run_summary(args: &Args, state: &mut AppState) -> Result<(), String> {
+    let date = args.date(state)?;
+    let mode = args.mode(state)?;
+    state.apply_schedule(std::slice::from_ref(&date));
+    let totals = state.daily_log.get_total_nutrients(&date, &state.db.foods, mode);
+
+    let rows: Vec<(Nutrient, f32, Option<f32>)> = Nutrient::ALL.iter()
+        .map(|n| (*n, totals.get(*n), state.profile.get_nutrient_target(&date, *n)))
+        .collect();
+
+    // Calories per meal, with every meal that has a budget listed even if nothing was eaten
+    let groups = state.daily_log.entries_by_meal(&date, &state.profile.meal_names());
+    let mut meals: Vec<(String, f32, Option<f32>)> = groups.iter().map(|(meal, entries)| {
+        let calories = entries.iter().map(|(_, entry)| entry.nutrients(&state.db.foods, mode).calories).sum();
+        (meal.clone(), calories, state.profile.find_meal(meal).and_then(|meal| meal.calorie_budget))
+    }).collect();
+    for meal in &state.profile.meals {
+        if meal.calorie_budget.is_some() && !meals.iter().any(|(name, _, _)| name == &meal.name) {
+            meals.push((meal.name.clone(), 0.0, meal.calorie_budget));
+        }
+    }
+    let meal_names = state.profile.meal_names();
+    meals.sort_by_key(|(name, _, _)| meal_names.iter().position(|n| n == name).unwrap_or(meal_names.len()));
+
+    if args.flag("json") {
+        let meals_json: Vec<_> = meals.iter().map(|(meal, calories, budget)| json!({
+            "meal": meal,
+            "calories": calories,
+            "budget": budget,
+            "status": budget.map(|b| TargetStatus::evaluate(*calories, b).name()),
+        })).collect();
+        let nutrients: serde_json::Map<String, serde_json::Value> = rows.iter().map(|(nutrient, consumed, target)| {
+            let value = json!({
+                "consumed": consumed,
+                "target": target,
+                "unit": nutrient.unit(),
+                "status": target.map(|t| TargetStatus::evaluate(*consumed, t).name()),
+            });
+            (nutrient.name().to_lowercase(), value)
+        }).collect();
+        return print_json(&json!({ "date": date, "mode": mode.name(), "nutrients": nutrients, "meals": meals_json }));
+    }
+
+    println!("Summary for {} ({} values)", date, mode.name());
+    for (nutrient, consumed, target) in rows {
+        match target {
+            Some(target) => println!("{:<8} {:>8.1} / {:.1} {} - {}", nutrient.name(), consumed, target,
+                                     nutrient.unit(), TargetStatus::evaluate(consumed, target).name()),
+            None => println!("{:<8} {:>8.1} {}", nutrient.name(), consumed, nutrient.unit()),
+        }
+    }
+    for (meal, calories, budget) in meals {
+        match budget {
+            Some(budget) => println!("{:<10} {:>6.1} / {:.0} kcal - {}", meal, calories, budget,
+                                     TargetStatus::evaluate(calories, budget).name()),
+            None => println!("{:<10} {:>6.1} kcal", meal, calories),
+        }
+    }
+    Ok(())
+}
+
+fn run_report(args: &Args, state: &AppState) -> Result<(), String> {
+    let (start, end) = match args.word(1) {
+        Some("week") => week_of(&args.date(state)?),
+        Some("month") => month_of(&args.date(state)?),
+        Some(start) => Some((start.to_string(), args.required_word(2, "end date")?.to_string())),
+        None => return Err("expected 'report week', 'report month' or 'report <from> <to>'".to_string()),
+    }.ok_or("invalid date")?;
+    let top = match args.option("top") {
+        Some(top) => top.parse::<usize>().map_err(|_| format!("--top expects a whole number, got '{}'", top))?,
+        None => DEFAULT_TOP_FOODS,
+    };
+    let report = RangeReport::build(&start, &end, &state.daily_log, &state.profile, &state.db.foods, args.mode(state)?)
+        .ok_or_else(|| format!("invalid range {} to {} (expected YYYY-MM-DD dates, earliest first)", start, end))?;
+
+    if args.flag("json") {
+        let mut value = json!(report);
+        if let Some(foods) = value["foods"].as_array_mut() {
+            foods.truncate(top);
+        }
+        return print_json(&value);
+    }
+    print_range_report(&report, top);
+    Ok(())
+}
+
+fn run_chart(args: &Args, state: &AppState) -> Result<(), String> {
+    let date = args.date(state)?;
+    let days = match args.option("days") {
+        Some(days) => days.parse::<usize>().ok()
+            .filter(|days| (1..=MAX_CHART_DAYS).contains(days))
+            .ok_or_else(|| format!("--days expects a number from 1 to {}, got '{}'", MAX_CHART_DAYS, days))?,
+        None => DEFAULT_CHART_DAYS,
+    };
+    let points = calorie_series(&state.daily_log, &state.profile, &state.db.foods, args.mode(state)?, &date, days)
+        .ok_or_else(|| format!("invalid date '{}'", date))?;
+    let lines = match args.word(1) {
+        Some("bar") | None => bar_chart(&points),
+        Some("line") => line_chart(&points),
+        Some(other) => return Err(format!("unknown chart '{}' (expected bar or line)", other)),
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn run_weight(args: &Args, state: &mut AppState) -> Result<(), String> {
+    let date = args.date(state)?;
+    match args.word(1) {
+        Some("add") => {
+            let value = args.required_word(2, "weight in kg")?;
+            let mut entry = WeightEntry::new(value.parse().map_err(|_| format!("weight expects a number, got '{}'", value))?);
+            entry.body_fat_pct = args.number("body-fat")?;
+            for measure in args.all("measure") {
+                let measurements = parse_measurements(measure)
+                    .ok_or_else(|| format!("invalid measurement '{}' (expected name=cm with a positive length)", measure))?;
+                entry.measurements.extend(measurements);
+            }
+            entry.validate()?;
+            let command = RecordWeightCommand::new(&date, Some(entry));
+            let description = command.description();
+            if !state.execute_command(command) {
+                return Err("failed to record weight".to_string());
+            }
+            println!("{}", description);
+            Ok(())
+        },
+        Some("remove") => {
+            if !state.profile.weights.contains_key(&date) {
+                return Err(format!("no weigh-in on {}", date));
+            }
+            if !state.execute_command(RecordWeightCommand::new(&date, None)) {
+                return Err("failed to remove weigh-in".to_string());
+            }
+            println!("Removed weigh-in on {}", date);
+            Ok(())
+        },
+        Some("list") => {
+            if args.flag("json") {
+                return print_json(&json!(state.profile.weights));
+            }
+            if state.profile.weights.is_empty() {
+                println!("No weigh-ins.");
+            }
+            for (date, entry) in &state.profile.weights {
+                println!("{}  {}", date, entry);
+            }
+            Ok(())
+        },
+        Some("report") => {
+            let start = match args.option("from") {
+                Some(start) => start.to_string(),
+                None => offset_date(&date, 1 - WEIGHT_REPORT_DAYS).ok_or_else(|| format!("invalid date '{}'", date))?,
+            };
+            let report = WeightReport::build(&start, &date, &state.daily_log, &state.profile, &state.db.foods, args.mode(state)?)
+                .ok_or_else(|| format!("invalid range {} to {} (expected YYYY-MM-DD dates, earliest first)", start, date))?;
+            if args.flag("json") {
+                return print_json(&json!(report));
+            }
+            print_weight_report(&report);
+            Ok(())
+        },
+        _ => Err("expected 'weight add', 'weight remove', 'weight list' or 'weight report'".to_string()),
+    }
+}
+
+fn run_profile(args: &Args, state: &mut AppState) -> Result<(), String> {
+    match args.word(1) {
+        Some("show") => {
+            let profile = &state.profile;
+            if args.flag("json") {
+                return print_json(&json!(profile));
+            }
+            println!("Gender: {:?}", profile.gender);
+            println!("Age: {}", profile.age);
+            println!("Height: {} cm", profile.height_cm);
+            println!("Weight: {} kg", profile.weight_kg);
+            println!("Weight Used for Targets: {} ({:.1} kg on {})", profile.weight_source.name(),
+                     profile.weight_on(&state.current_date), state.current_date);
+            println!("Activity Level: {:?}", profile.activity_level);
+            println!("Target Formula: {:?}", profile.target_formula);
+            println!("Calculated Target: {:.0} kcal", profile.calculate_target_calories(&state.current_date));
+            let meals: Vec<String> = profile.meals.iter().map(|meal| match meal.calorie_budget {
+                Some(budget) => format!("{} ({:.0} kcal)", meal.name, budget),
+                None => meal.name.clone(),
+            }).collect();
+            println!("Meals: {}", meals.join(", "));
+            Ok(())
+        },
+        Some("set") => {
+            let field = args.required_word(2, "profile field")?;
+            let value = args.required_word(3, "value")?;
+            let number = || value.parse::<f32>().map_err(|_| format!("{} expects a number, got '{}'", field, value));
+            let setting = match field {
+                "gender" => ProfileSetting::Gender(value.parse::<Gender>()?),
+                "age" => ProfileSetting::Age(value.parse().map_err(|_| format!("age expects a whole number, got '{}'", value))?),
+                "height" => ProfileSetting::HeightCm(number()?),
+                "weight" => ProfileSetting::WeightKg(number()?),
+                "activity" => ProfileSetting::ActivityLevel(value.parse::<ActivityLevel>()?),
+                "formula" => ProfileSetting::TargetFormula(value.parse::<TargetFormula>()?),
+                "weight-source" => ProfileSetting::WeightSource(value.parse::<WeightSource>()?),
+                other => return Err(format!("unknown profile field '{}'", other)),
+            };
+            setting.validate()?;
+            if !state.execute_command(UpdateProfileCommand::new(setting)) {
+                return Err(format!("failed to update {}", field));
+            }
+            println!("Updated {} to {}", field, value);
+            Ok(())
+        },
+        Some("budget") => {
+            let name = args.required_word(2, "meal")?;
+            let value = args.required_word(3, "calories")?;
+            let budget = match value {
+                "none" | "clear" => None,
+                _ => Some(value.parse::<f32>().ok().filter(|b| *b > 0.0)
+                    .ok_or_else(|| format!("budget expects a positive number or 'none', got '{}'", value))?),
+            };
+            let mut meals = state.profile.meals.clone();
+            let meal = meals.iter_mut().find(|meal| meal.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("unknown meal '{}' (meals: {})", name, state.profile.meal_names().join(", ")))?;
+            meal.calorie_budget = budget;
+            let meal_name = meal.name.clone();
+            if !state.execute_command(UpdateProfileCommand::new(ProfileSetting::Meals(meals))) {
+                return Err("failed to update meal budget".to_string());
+            }
+            match budget {
+                Some(budget) => println!("{} budget set to {:.0} kcal", meal_name, budget),
+                None => println!("{} budget removed", meal_name),
+            }
+            Ok(())
+        },
+        _ => Err("expected 'profile show', 'profile set' or 'profile budget'".to_string()),
+    }
+}
+
+fn run_schedule(args: &Args, state: &mut AppState) -> Result<(), String> {
+    match args.word(1) {
+        Some("list") => {
+            let rules = state.schedule.rules();
+            if args.flag("json") {
+                return print_json(&json!(rules));
+            }
+            if rules.is_empty() {
+                println!("No scheduled entries.");
+            }
+            for rule in rules {
+                println!("#{} {}", rule.id, rule.describe());
+            }
+            Ok(())
+        },
+        Some("add") => {
+            let food = find_food(&state.db, args.required_word(2, "food id")?)?;
+            let quantity = quantity(args)?;
+            food.servings_for(&quantity).map_err(|e| e.to_string())?;
+            let days = args.option("days").ok_or("missing --days")?;
+            let days = parse_days(days)
+                .ok_or_else(|| format!("invalid --days '{}' (expected daily, weekdays, weekends or e.g. mon,wed,fri)", days))?;
+            let start = args.date(state)?;
+            let end = match args.option("until") {
+                Some(end) if NaiveDate::parse_from_str(end, "%Y-%m-%d").is_err() =>
+                    return Err(format!("invalid date '{}' (expected YYYY-MM-DD)", end)),
+                Some(end) if end < start.as_str() => return Err(format!("--until {} is before {}", end, start)),
+                end => end.map(|end| end.to_string()),
+            };
+
+            let rule = ScheduleRule::new(food.id(), quantity, meal(args, state)?, days, &start, end);
+            let description = rule.describe();
+            if !state.execute_command(AddScheduleRuleCommand::new(rule)) {
+                return Err("failed to add the rule".to_string());
+            }
+            // New rules go at the end of the list
+            let id = state.schedule.rules().last().map(|rule| rule.id).unwrap_or_default();
+            println!("Scheduled #{} {}", id, description);
+            Ok(())
+        },
+        Some("remove") => {
+            let id = rule_id(args)?;
+            let description = state.schedule.rules().iter().find(|rule| rule.id == id)
+                .map(|rule| rule.describe())
+                .ok_or_else(|| format!("no scheduled rule #{}", id))?;
+            if !state.execute_command(RemoveScheduleRuleCommand::new(id)) {
+                return Err(format!("failed to remove rule #{}", id));
+            }
+            println!("Removed #{} {}", id, description);
+            Ok(())
+        },
+        Some("skip") => {
+            let id = rule_id(args)?;
+            let date = args.date(state)?;
+            if !state.schedule.rules().iter().any(|rule| rule.id == id) {
+                return Err(format!("no scheduled rule #{}", id));
+            }
+            if !state.execute_command(SkipScheduledCommand::new(id, &date)) {
+                return Err(format!("failed to skip rule #{}", id));
+            }
+            println!("Skipped #{} on {}", id, date);
+            Ok(())
+        },
+        _ => Err("expected 'schedule list', 'schedule add', 'schedule remove' or 'schedule skip'".to_string()),
+    }
+}
+
+fn rule_id(args: &Args) -> Result<u64, String> {
+    let word = args.required_word(2, "rule number")?;
+    word.trim_start_matches('#').parse()
+        .map_err(|_| format!("invalid rule number '{}'", word))
+}
+
+fn run_materialize(args: &Args, state: &mut AppState) -> Result<(), String> {
+    let from = args.date(state)?;
+    let until = args.option("until").ok_or("missing --until")?;
+    let dates = date_range(&from, until)
+        .ok_or_else(|| format!("invalid range {} to {}", from, until))?;
+    if dates.len() > MAX_COPY_DAYS {
+        return Err(format!("materialize at most {} days at a time", MAX_COPY_DAYS));
+    }
+    if state.daily_log.is_read_only() || state.schedule.is_read_only() {
+        return Err("the log or schedule failed to load; fix or restore it first".to_string());
+    }
+    let added = state.apply_schedule(&dates);
+    println!("Logged {} scheduled entr(ies) from {} to {}", added, from, until);
+    Ok(())
+}
+
+fn run_config(args: &Args, state: &AppState) -> Result<(), String> {
+    if args.word(1) != Some("show") {
+        return Err("expected 'config show'".to_string());
+    }
+    let config = &state.config;
+    if args.flag("json") {
+        return print_json(&json!({
+            "config_file": config.config_file,
+            "data_dir": config.data_dir,
+            "food_db": config.food_db_path(),
+            "log": config.log_path(),
+            "profile": config.profile_path(),
+            "schedule": config.schedule_path(),
+            "open_food_facts": config.open_food_facts_path(),
+            "settings": config.settings,
+        }));
+    }
+    match &config.config_file {
+        Some(path) if path.exists() => println!("Config file: {}", path.display()),
+        Some(path) => println!("Config file: {} (not present, using defaults)", path.display()),
+        None => println!("Config file: none"),
+    }
+    println!("Data directory: {}", config.data_dir.display());
+    println!("Food database: {}", config.food_db_path());
+    println!("Log: {}", config.log_path());
+    println!("Profile: {}", config.profile_path());
+    println!("Schedule: {}", config.schedule_path());
+    match config.open_food_facts_path() {
+        Some(path) => println!("Open Food Facts export: {}", path.display()),
+        None => println!("Open Food Facts export: none"),
+    }
+    println!("Report mode: {}", config.settings.report_mode.name());
+    println!("Backups kept per file: {}", config.settings.backup_count);
+    Ok(())
+}
+
+fn run_undo(state: &mut AppState, redo: bool) -> Result<(), String> {
+    let action = if redo { state.redo() } else { state.undo() };
+    let action = action.map_err(|e| e.to_string())?;
+    println!("{} {}", if redo { "Redid" } else { "Undid" }, action);
+    Ok(())
+}
+
+fn run_history(args: &Args, state: &AppState) -> Result<(), String> {
+    let done = state.command_manager.command_history();
+    let undone = state.command_manager.redo_history();
+    if args.flag("json") {
+        return print_json(&json!({ "undo": done, "redo": undone }));
+    }
+    if done.is_empty() && undone.is_empty() {
+        println!("No history.");
+    }
+    for (i, action) in done.iter().enumerate() {
+        println!("{}. {}", i + 1, action);
+    }
+    for action in &undone {
+        println!("   (undone) {}", action);
+    }
+    Ok(())
+}
+
+fn run_restore(args: &Args, state: &mut AppState) -> Result<(), String> {
+    let backups = state.backups();
+    let Some(number) = args.word(1) else {
+        if args.flag("json") {
+            return print_json(&json!(backups.iter().enumerate().map(|(i, backup)| json!({
+                "number": i + 1,
+                "file": backup.original,
+                "backup": backup.path,
+                "created": backup.created.format("%Y-%m-%d %H:%M:%S").to_string(),
+            })).collect::<Vec<_>>()));
+        }
+        if backups.is_empty() {
+            println!("No backups yet.");
+        }
+        for (i, backup) in backups.iter().enumerate() {
+            println!("{}. {}", i + 1, describe_backup(backup));
+        }
+        return Ok(());
+    };
+
+    let backup = number.parse::<usize>().ok()
+        .filter(|n| *n >= 1 && *n <= backups.len())
+        .map(|n| &backups[n - 1])
+        .ok_or_else(|| format!("no backup numbered '{}'; run 'diet_manager restore' to list them", number))?;
+    state.restore_backup(backup).map_err(|e| e.to_string())?;
+    println!("Restored {}", describe_backup(backup));
+    Ok(())
+}
+
+fn print_json(value: &serde_json::Value) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    println!("{}", text);
+    Ok(())
+}
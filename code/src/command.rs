@@ -1,54 +1,54 @@
 use crate::database::Database;
-use crate::food::{Food, BasicFood, CompositeFood};
-use crate::log::DailyLog;
+use crate::food::{Food, Quantity, UnitError, compute_nutrients};
+use crate::log::{DailyLog, LogEntry};
+use crate::profile::{MacroTargets, Profile, ProfileSetting};
+use crate::schedule::{Schedule, ScheduleRule};
+use crate::weight::WeightEntry;
+use crate::storage::{self, StoreError};
+use serde::{Serialize, Deserialize};
+use chrono::Local;
+use std::collections::HashMap;
 use std::fmt;
 
-// Trait defining an undoable command
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+// The data a command works on, borrowed from the app state only while the command runs
+pub struct CommandContext<'a> {
+    pub db: &'a mut Database,
+    pub log: &'a mut DailyLog,
+    pub profile: &'a mut Profile,
+    pub schedule: &'a mut Schedule,
+}
+
+// Trait defining an undoable command. Commands keep only what they need to undo and redo
+// themselves; the data they change is passed in each time they run.
 pub trait UndoableCommand: fmt::Debug {
-    fn execute(&mut self) -> bool;
-    fn undo(&mut self) -> bool;
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool;
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool;
     fn description(&self) -> String;
 }
 
 // Command for adding a food to the database
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct AddFoodCommand {
     food: Food,
-    db: *mut Database,  // Using raw pointers to avoid ownership issues
 }
 
 impl AddFoodCommand {
-    pub fn new(food: Food, db: &mut Database) -> Self {
-        AddFoodCommand {
-            food,
-            db: db as *mut Database,
-        }
+    pub fn new(food: Food) -> Self {
+        AddFoodCommand { food }
     }
 }
 
 impl UndoableCommand for AddFoodCommand {
-    fn execute(&mut self) -> bool {
-        unsafe {
-            let db = &mut *self.db;
-            let id = match &self.food {
-                Food::Basic(basic) => basic.id.clone(),
-                Food::Composite(composite) => composite.id.clone(),
-            };
-            db.add_food(self.food.clone());
-            true
-        }
+    // Refuses an id that is already taken; replacing a food is an `EditFoodCommand`
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        ctx.db.add_food(self.food.clone()).is_ok()
     }
 
-    fn undo(&mut self) -> bool {
-        unsafe {
-            let db = &mut *self.db;
-            let id = match &self.food {
-                Food::Basic(basic) => basic.id.clone(),
-                Food::Composite(composite) => composite.id.clone(),
-            };
-            db.foods.remove(&id);
-            true
-        }
+    // Refuses if a composite has started using the food since it was added
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        ctx.db.remove_food(self.food.id()).is_ok()
     }
 
     fn description(&self) -> String {
@@ -60,130 +60,1132 @@ impl UndoableCommand for AddFoodCommand {
     }
 }
 
+// Command for removing a food from the database. Composites and schedule rules that use the
+// food block the removal; log entries that reference it are frozen so history keeps its
+// nutrition.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RemoveFoodCommand {
+    food_id: String,
+    removed: Option<Food>,
+    frozen: Vec<u64>, // Ids of the log entries frozen by this command
+}
+
+impl RemoveFoodCommand {
+    pub fn new(food_id: &str) -> Self {
+        RemoveFoodCommand {
+            food_id: food_id.to_string(),
+            removed: None,
+            frozen: Vec::new(),
+        }
+    }
+}
+
+impl UndoableCommand for RemoveFoodCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        if !db.referencing_composites(&self.food_id).is_empty() || !ctx.schedule.rules_for_food(&self.food_id).is_empty() {
+            return false;
+        }
+        self.frozen = log.freeze_entries(std::slice::from_ref(&self.food_id), &db.foods);
+        match db.remove_food(&self.food_id) {
+            Ok(food) => {
+                self.removed = Some(food);
+                true
+            },
+            Err(_) => {
+                log.unfreeze_entries(&self.frozen);
+                false
+            }
+        }
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        match self.removed.take() {
+            Some(food) => {
+                db.foods.insert(food.id().to_string(), food);
+                log.unfreeze_entries(&self.frozen);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn description(&self) -> String {
+        format!("Remove food '{}'", self.food_id)
+    }
+}
+
+// Command for replacing a food's definition while keeping its id. Log entries of the food and
+// of every composite built from it are frozen first, so past days keep their old nutrition.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EditFoodCommand {
+    food: Food,
+    previous: Option<Food>,
+    frozen: Vec<u64>, // Ids of the log entries frozen by this command
+}
+
+impl EditFoodCommand {
+    pub fn new(food: Food) -> Self {
+        EditFoodCommand {
+            food,
+            previous: None,
+            frozen: Vec::new(),
+        }
+    }
+}
+
+impl UndoableCommand for EditFoodCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        let previous = match db.foods.get(self.food.id()) {
+            Some(previous) => previous.clone(),
+            None => return false,
+        };
+        if !db.validate_food(&self.food).is_empty() {
+            return false;
+        }
+        self.frozen = log.freeze_entries(&db.dependents(self.food.id()), &db.foods);
+        self.previous = Some(previous);
+        db.replace_food(self.food.clone()).is_ok()
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        match self.previous.take() {
+            Some(previous) => {
+                db.foods.insert(previous.id().to_string(), previous);
+                log.unfreeze_entries(&self.frozen);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn description(&self) -> String {
+        format!("Edit food '{}'", self.food.id())
+    }
+}
+
+// Command for renaming a food. The new id is cascaded into composites, log entries and
+// schedule rules.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RenameFoodCommand {
+    old_id: String,
+    new_id: String,
+    renamed_entries: Vec<u64>,
+    #[serde(default)]
+    renamed_rules: Vec<u64>,
+}
+
+impl RenameFoodCommand {
+    pub fn new(old_id: &str, new_id: &str) -> Self {
+        RenameFoodCommand {
+            old_id: old_id.to_string(),
+            new_id: new_id.to_string(),
+            renamed_entries: Vec::new(),
+            renamed_rules: Vec::new(),
+        }
+    }
+}
+
+impl UndoableCommand for RenameFoodCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        if db.rename_food(&self.old_id, &self.new_id).is_err() {
+            return false;
+        }
+        self.renamed_entries = log.entries_for_food(&self.old_id);
+        log.rename_entries(&self.renamed_entries, &self.new_id);
+        self.renamed_rules = ctx.schedule.rules_for_food(&self.old_id);
+        ctx.schedule.rename_rules(&self.renamed_rules, &self.new_id);
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        if db.rename_food(&self.new_id, &self.old_id).is_err() {
+            return false;
+        }
+        log.rename_entries(&self.renamed_entries, &self.old_id);
+        ctx.schedule.rename_rules(&self.renamed_rules, &self.old_id);
+        true
+    }
+
+    fn description(&self) -> String {
+        format!("Rename food '{}' to '{}'", self.old_id, self.new_id)
+    }
+}
+
+// Command for importing many foods at once, e.g. from a FoodData Central download. New foods
+// are added and existing ones replaced as one batch, so a single undo takes the import back.
+// Imports can hold thousands of foods, so the journal only keeps what the next undo or redo
+// needs: the ids and replaced definitions while the import is applied, and the imported
+// foods themselves once it has been undone.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportFoodsCommand {
+    source: String,
+    replacing: usize, // How many of the foods replace existing ones
+    #[serde(default)]
+    pending: Vec<Food>, // Imported definitions while they are not in the database
+    #[serde(default)]
+    ids: Vec<String>, // Imported foods while they are in the database
+    #[serde(default)]
+    replaced: Vec<Food>, // Definitions the import replaced
+    #[serde(default)]
+    frozen: Vec<u64>, // Ids of the log entries frozen by this command
+}
+
+impl ImportFoodsCommand {
+    pub fn new(source: &str, add: Vec<Food>, replace: Vec<Food>) -> Self {
+        ImportFoodsCommand {
+            source: source.to_string(),
+            replacing: replace.len(),
+            pending: add.into_iter().chain(replace).collect(),
+            ids: Vec::new(),
+            replaced: Vec::new(),
+            frozen: Vec::new(),
+        }
+    }
+}
+
+impl UndoableCommand for ImportFoodsCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        if self.pending.is_empty() {
+            return false;
+        }
+        // Log entries of replaced foods, and of composites built from them, keep their nutrition
+        let replaced: Vec<Food> = self.pending.iter().filter_map(|food| db.foods.get(food.id()).cloned()).collect();
+        let mut affected: Vec<String> = Vec::new();
+        for food in &replaced {
+            for id in db.dependents(food.id()) {
+                if !affected.contains(&id) {
+                    affected.push(id);
+                }
+            }
+        }
+        let frozen = log.freeze_entries(&affected, &db.foods);
+
+        // All or nothing: the foods go in together, so they may refer to each other, and are
+        // taken out again if any of them is refused
+        let ids: Vec<String> = self.pending.iter().map(|food| food.id().to_string()).collect();
+        for food in self.pending.drain(..) {
+            db.foods.insert(food.id().to_string(), food);
+        }
+        if !db.validate_foods(&ids).is_empty() {
+            self.pending = ids.iter().filter_map(|id| db.foods.remove(id)).collect();
+            for food in replaced {
+                db.foods.insert(food.id().to_string(), food);
+            }
+            log.unfreeze_entries(&frozen);
+            return false;
+        }
+        self.ids = ids;
+        self.replaced = replaced;
+        self.frozen = frozen;
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        let db = &mut *ctx.db;
+        let log = &mut *ctx.log;
+        // Refuses if a food from outside the import has started using a food it added
+        let in_use = self.ids.iter()
+            .filter(|id| !self.replaced.iter().any(|food| food.id() == id.as_str()))
+            .any(|id| db.referencing_composites(id).iter().any(|user| !self.ids.contains(user)));
+        if self.ids.is_empty() || in_use {
+            return false;
+        }
+        self.pending = self.ids.drain(..).filter_map(|id| db.foods.remove(&id)).collect();
+        for food in self.replaced.drain(..) {
+            db.foods.insert(food.id().to_string(), food);
+        }
+        log.unfreeze_entries(&self.frozen);
+        true
+    }
+
+    fn description(&self) -> String {
+        let count = self.pending.len() + self.ids.len();
+        match self.replacing {
+            0 => format!("Import {} food(s) from {}", count, self.source),
+            replacing => format!("Import {} food(s) from {}, replacing {}", count, self.source, replacing),
+        }
+    }
+}
+
 // Command for logging food in daily log
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct LogFoodCommand {
     date: String,
-    food_id: String,
-    servings: f32,
-    log: *mut DailyLog,
-    entry_index: Option<usize>, // Store the index for undoing
+    entry: LogEntry,
+    position: Option<usize>, // Where the entry sat when it was undone, so redo puts it back there
 }
 
 impl LogFoodCommand {
-    pub fn new(date: &str, food_id: &str, servings: f32, log: &mut DailyLog) -> Self {
-        LogFoodCommand {
+    // Converts the quantity into servings of the food up front, so a bad unit is
+    // reported before anything is logged, and snapshots the food's nutrition as of now
+    pub fn new(date: &str, food: &Food, quantity: Quantity, meal: Option<String>, foods: &HashMap<String, Food>) -> Result<Self, UnitError> {
+        let servings = food.servings_for(&quantity)?;
+        Ok(LogFoodCommand {
             date: date.to_string(),
-            food_id: food_id.to_string(),
-            servings,
-            log: log as *mut DailyLog,
-            entry_index: None,
-        }
+            entry: LogEntry {
+                id: 0,
+                logged_at: Some(Local::now().format("%Y-%m-%d %H:%M:%S").to_string()),
+                food_id: food.id().to_string(),
+                servings,
+                quantity: if quantity.is_servings() { None } else { Some(quantity) },
+                snapshot: Some(compute_nutrients(food, foods)),
+                meal,
+                rule: None,
+            },
+            position: None,
+        })
+    }
+
+    pub fn servings(&self) -> f32 {
+        self.entry.servings
     }
 }
 
 impl UndoableCommand for LogFoodCommand {
-    fn execute(&mut self) -> bool {
-        unsafe {
-            let log = &mut *self.log;
-            
-            // Get the current number of entries for this date to determine the index
-            let entries = log.get_log_entries(&self.date);
-            self.entry_index = Some(entries.len());
-            
-            log.add_food(&self.date, &self.food_id, self.servings);
-            true
-        }
-    }
-
-    fn undo(&mut self) -> bool {
-        unsafe {
-            let log = &mut *self.log;
-            if let Some(index) = self.entry_index {
-                return log.remove_food(&self.date, index);
-            }
-            false
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        // The entry keeps the id it was given the first time, so later commands still find it
+        self.entry.id = match self.position {
+            Some(index) => ctx.log.insert_entry(&self.date, index, self.entry.clone()),
+            None => ctx.log.add_entry(&self.date, self.entry.clone()),
+        };
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match ctx.log.remove_entry(self.entry.id) {
+            Some((_, index, _)) => {
+                self.position = Some(index);
+                true
+            },
+            None => false,
         }
     }
 
     fn description(&self) -> String {
-        format!("Log {:.1} serving(s) of '{}' on {}", self.servings, self.food_id, self.date)
+        let amount = match &self.entry.quantity {
+            Some(quantity) => quantity.to_string(),
+            None => format!("{:.1} serving(s)", self.entry.servings),
+        };
+        match &self.entry.meal {
+            Some(meal) => format!("Log {} of '{}' for {} on {}", amount, self.entry.food_id, meal.to_lowercase(), self.date),
+            None => format!("Log {} of '{}' on {}", amount, self.entry.food_id, self.date),
+        }
     }
 }
 
-// Command for removing food from daily log
-#[derive(Debug)]
+// Command for removing food from daily log. Undo puts the entry back where it was.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct RemoveLogEntryCommand {
     date: String,
-    index: usize,
-    food_id: String,
-    servings: f32,
-    log: *mut DailyLog,
+    entry: LogEntry,
+    position: Option<usize>, // Filled in when the entry is removed
 }
 
 impl RemoveLogEntryCommand {
-    pub fn new(date: &str, index: usize, food_id: &str, servings: f32, log: &mut DailyLog) -> Self {
+    pub fn new(date: &str, entry: LogEntry) -> Self {
         RemoveLogEntryCommand {
             date: date.to_string(),
-            index,
-            food_id: food_id.to_string(),
-            servings,
-            log: log as *mut DailyLog,
+            entry,
+            position: None,
         }
     }
 }
 
 impl UndoableCommand for RemoveLogEntryCommand {
-    fn execute(&mut self) -> bool {
-        unsafe {
-            let log = &mut *self.log;
-            log.remove_food(&self.date, self.index)
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        match ctx.log.remove_entry(self.entry.id) {
+            Some((date, index, entry)) => {
+                self.date = date;
+                self.position = Some(index);
+                self.entry = entry;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match self.position {
+            Some(index) => {
+                ctx.log.insert_entry(&self.date, index, self.entry.clone());
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn description(&self) -> String {
+        format!("Remove '{}' entry from {}", self.entry.food_id, self.date)
+    }
+}
+
+// Command for changing the amount or meal of a logged entry. The entry keeps its id, its
+// nutrition snapshot and the schedule rule that generated it, so the rule still treats the
+// day as handled.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EditLogEntryCommand {
+    date: String,
+    entry: LogEntry, // The entry as it reads after the edit
+    previous: Option<LogEntry>, // Filled in when the edit is applied
+}
+
+impl EditLogEntryCommand {
+    // Converts the new quantity into servings up front, like `LogFoodCommand::new`
+    pub fn new(date: &str, entry: &LogEntry, food: &Food, quantity: Quantity, meal: Option<String>) -> Result<Self, UnitError> {
+        let servings = food.servings_for(&quantity)?;
+        Ok(EditLogEntryCommand {
+            date: date.to_string(),
+            entry: LogEntry {
+                servings,
+                quantity: if quantity.is_servings() { None } else { Some(quantity) },
+                meal,
+                ..entry.clone()
+            },
+            previous: None,
+        })
+    }
+}
+
+impl UndoableCommand for EditLogEntryCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        self.previous = ctx.log.replace_entry(self.entry.clone());
+        self.previous.is_some()
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match self.previous.take() {
+            Some(previous) => ctx.log.replace_entry(previous).is_some(),
+            None => false,
         }
     }
 
-    fn undo(&mut self) -> bool {
-        unsafe {
-            let log = &mut *self.log;
-            log.add_food(&self.date, &self.food_id, self.servings);
-            true
+    fn description(&self) -> String {
+        let amount = match &self.entry.quantity {
+            Some(quantity) => quantity.to_string(),
+            None => format!("{:.1} serving(s)", self.entry.servings),
+        };
+        format!("Edit '{}' entry on {} to {}", self.entry.food_id, self.date, amount)
+    }
+}
+
+// Command for copying a day's entries, or one meal's, to one or more other dates as a single
+// undoable batch. The copies are logged as new entries with the foods' current nutrition.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CopyEntriesCommand {
+    source_date: String,
+    meal: Option<String>,
+    target_dates: Vec<String>,
+    copies: Vec<(String, LogEntry)>, // Filled in on first run and reused on redo, so ids stay the same
+}
+
+impl CopyEntriesCommand {
+    pub fn new(source_date: &str, meal: Option<String>, target_dates: Vec<String>) -> Self {
+        CopyEntriesCommand {
+            source_date: source_date.to_string(),
+            meal,
+            // Copying a day onto itself would only duplicate it
+            target_dates: target_dates.into_iter().filter(|date| date != source_date).collect(),
+            copies: Vec::new(),
+        }
+    }
+}
+
+impl UndoableCommand for CopyEntriesCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        if self.copies.is_empty() {
+            let logged_at = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+            let sources = ctx.log.entries_for_meal(&self.source_date, self.meal.as_deref());
+            for date in &self.target_dates {
+                for source in &sources {
+                    let snapshot = match ctx.db.foods.get(&source.food_id) {
+                        Some(food) => Some(compute_nutrients(food, &ctx.db.foods)),
+                        None => source.snapshot,
+                    };
+                    let copy = LogEntry {
+                        id: 0,
+                        logged_at: Some(logged_at.clone()),
+                        snapshot,
+                        rule: None,
+                        ..(*source).clone()
+                    };
+                    self.copies.push((date.clone(), copy));
+                }
+            }
+        }
+        if self.copies.is_empty() {
+            return false;
+        }
+        for (date, entry) in self.copies.iter_mut() {
+            entry.id = ctx.log.add_entry(date, entry.clone());
+        }
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        for (_, entry) in self.copies.iter().rev() {
+            ctx.log.remove_entry(entry.id);
+        }
+        true
+    }
+
+    fn description(&self) -> String {
+        let what = match &self.meal {
+            Some(meal) => format!("{} entries", meal.to_lowercase()),
+            None => "entries".to_string(),
+        };
+        let targets = match self.target_dates.as_slice() {
+            [date] => date.clone(),
+            [first, .., last] => format!("{} to {}", first, last),
+            [] => "nowhere".to_string(),
+        };
+        format!("Copy {} from {} to {}", what, self.source_date, targets)
+    }
+}
+
+// Command for adding a schedule rule. Undo also takes out the entries the rule has generated
+// since, and redo puts them back, so an accidental rule leaves nothing behind.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AddScheduleRuleCommand {
+    rule: ScheduleRule, // Given its id on first run; redo keeps it
+    generated: Vec<(String, usize, LogEntry)>, // Date, position and entry of each generated entry taken out by undo
+}
+
+impl AddScheduleRuleCommand {
+    pub fn new(rule: ScheduleRule) -> Self {
+        AddScheduleRuleCommand {
+            rule,
+            generated: Vec::new(),
+        }
+    }
+}
+
+impl UndoableCommand for AddScheduleRuleCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        if self.rule.id == 0 {
+            self.rule.id = ctx.schedule.add_rule(self.rule.clone());
+            return true;
+        }
+        if !ctx.schedule.restore_rule(self.rule.clone()) {
+            return false;
+        }
+        // Put back in reverse, so each entry returns to the position it was taken from
+        for (date, index, entry) in self.generated.drain(..).rev() {
+            ctx.log.insert_entry(&date, index, entry);
+        }
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match ctx.schedule.remove_rule(self.rule.id) {
+            Some(rule) => {
+                // Kept with its handled dates, so redo doesn't generate the same entries again
+                self.rule = rule;
+                self.generated = ctx.log.entries_from_rule(self.rule.id).into_iter()
+                    .filter_map(|id| ctx.log.remove_entry(id))
+                    .collect();
+                true
+            },
+            None => false,
         }
     }
 
     fn description(&self) -> String {
-        format!("Remove entry #{} (food: '{}') from {}", self.index + 1, self.food_id, self.date)
+        format!("Schedule {}", self.rule.describe())
     }
 }
 
-// Main CommandManager to handle the undo stack
-#[derive(Debug)]
+// Command for removing a schedule rule. Entries it already generated stay in the log.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RemoveScheduleRuleCommand {
+    rule_id: u64,
+    removed: Option<ScheduleRule>,
+}
+
+impl RemoveScheduleRuleCommand {
+    pub fn new(rule_id: u64) -> Self {
+        RemoveScheduleRuleCommand {
+            rule_id,
+            removed: None,
+        }
+    }
+}
+
+impl UndoableCommand for RemoveScheduleRuleCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        self.removed = ctx.schedule.remove_rule(self.rule_id);
+        self.removed.is_some()
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match self.removed.take() {
+            Some(rule) => ctx.schedule.restore_rule(rule),
+            None => false,
+        }
+    }
+
+    fn description(&self) -> String {
+        match &self.removed {
+            Some(rule) => format!("Remove scheduled #{} {}", rule.id, rule.describe()),
+            None => format!("Remove scheduled rule #{}", self.rule_id),
+        }
+    }
+}
+
+// Command for skipping a schedule rule on one date. The entry it generated there, if any, is
+// taken out; undo puts it back, or lets the rule generate the date again if it hadn't yet.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SkipScheduledCommand {
+    rule_id: u64,
+    date: String,
+    was_handled: bool,
+    removed: Vec<(usize, LogEntry)>, // Position and entry of each generated entry taken out
+}
+
+impl SkipScheduledCommand {
+    pub fn new(rule_id: u64, date: &str) -> Self {
+        SkipScheduledCommand {
+            rule_id,
+            date: date.to_string(),
+            was_handled: false,
+            removed: Vec::new(),
+        }
+    }
+}
+
+impl UndoableCommand for SkipScheduledCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        let Some(was_handled) = ctx.schedule.set_handled(self.rule_id, &self.date, true) else {
+            return false;
+        };
+        self.was_handled = was_handled;
+        self.removed = ctx.log.entries_for_rule(&self.date, self.rule_id).into_iter()
+            .filter_map(|id| ctx.log.remove_entry(id))
+            .map(|(_, index, entry)| (index, entry))
+            .collect();
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        if ctx.schedule.set_handled(self.rule_id, &self.date, self.was_handled).is_none() {
+            return false;
+        }
+        for (index, entry) in self.removed.drain(..).rev() {
+            ctx.log.insert_entry(&self.date, index, entry);
+        }
+        true
+    }
+
+    fn description(&self) -> String {
+        format!("Skip scheduled rule #{} on {}", self.rule_id, self.date)
+    }
+}
+
+// Command for changing one profile field
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateProfileCommand {
+    setting: ProfileSetting,
+    previous: Option<ProfileSetting>,
+}
+
+impl UpdateProfileCommand {
+    pub fn new(setting: ProfileSetting) -> Self {
+        UpdateProfileCommand {
+            setting,
+            previous: None,
+        }
+    }
+}
+
+impl UndoableCommand for UpdateProfileCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        self.previous = Some(ctx.profile.apply_setting(self.setting.clone()));
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match self.previous.take() {
+            Some(previous) => {
+                ctx.profile.apply_setting(previous);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn description(&self) -> String {
+        format!("Set {}", self.setting)
+    }
+}
+
+// Command for setting or, with `None`, removing the calorie target override for a date
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SetDailyTargetCommand {
+    date: String,
+    target: Option<f32>,
+    previous: Option<f32>,
+}
+
+impl SetDailyTargetCommand {
+    pub fn new(date: &str, target: Option<f32>) -> Self {
+        SetDailyTargetCommand {
+            date: date.to_string(),
+            target,
+            previous: None,
+        }
+    }
+}
+
+impl UndoableCommand for SetDailyTargetCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        self.previous = match self.target {
+            Some(target) => ctx.profile.set_daily_override(&self.date, target),
+            None => ctx.profile.remove_daily_override(&self.date),
+        };
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match self.previous {
+            Some(previous) => ctx.profile.set_daily_override(&self.date, previous),
+            None => ctx.profile.remove_daily_override(&self.date),
+        };
+        true
+    }
+
+    fn description(&self) -> String {
+        match self.target {
+            Some(target) => format!("Set calorie target for {} to {:.0}", self.date, target),
+            None => format!("Remove calorie target override for {}", self.date),
+        }
+    }
+}
+
+// Command for setting or, with `None`, removing the macro target override for a date
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SetMacroTargetsCommand {
+    date: String,
+    targets: Option<MacroTargets>,
+    previous: Option<MacroTargets>,
+}
+
+impl SetMacroTargetsCommand {
+    pub fn new(date: &str, targets: Option<MacroTargets>) -> Self {
+        SetMacroTargetsCommand {
+            date: date.to_string(),
+            targets,
+            previous: None,
+        }
+    }
+}
+
+impl UndoableCommand for SetMacroTargetsCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        self.previous = match &self.targets {
+            Some(targets) => ctx.profile.set_macro_override(&self.date, targets.clone()),
+            None => ctx.profile.remove_macro_override(&self.date),
+        };
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match self.previous.clone() {
+            Some(previous) => ctx.profile.set_macro_override(&self.date, previous),
+            None => ctx.profile.remove_macro_override(&self.date),
+        };
+        true
+    }
+
+    fn description(&self) -> String {
+        match &self.targets {
+            Some(targets) => format!("Set macro targets for {} to {}", self.date, targets),
+            None => format!("Remove macro target override for {}", self.date),
+        }
+    }
+}
+
+// Command for recording or, with `None`, removing the weigh-in for a date
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecordWeightCommand {
+    date: String,
+    entry: Option<WeightEntry>,
+    previous: Option<WeightEntry>,
+}
+
+impl RecordWeightCommand {
+    pub fn new(date: &str, entry: Option<WeightEntry>) -> Self {
+        RecordWeightCommand {
+            date: date.to_string(),
+            entry,
+            previous: None,
+        }
+    }
+}
+
+impl UndoableCommand for RecordWeightCommand {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        self.previous = match self.entry.clone() {
+            Some(entry) => ctx.profile.set_weight(&self.date, entry),
+            None => ctx.profile.remove_weight(&self.date),
+        };
+        true
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        match self.previous.clone() {
+            Some(previous) => ctx.profile.set_weight(&self.date, previous),
+            None => ctx.profile.remove_weight(&self.date),
+        };
+        true
+    }
+
+    fn description(&self) -> String {
+        match &self.entry {
+            Some(entry) => format!("Record weight of {} on {}", entry, self.date),
+            None => format!("Remove weigh-in on {}", self.date),
+        }
+    }
+}
+
+// Every kind of command, in a form that can be written to the history journal
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", content = "data")]
+pub enum CommandRecord {
+    AddFood(AddFoodCommand),
+    RemoveFood(RemoveFoodCommand),
+    EditFood(EditFoodCommand),
+    RenameFood(RenameFoodCommand),
+    LogFood(LogFoodCommand),
+    RemoveLogEntry(RemoveLogEntryCommand),
+    EditLogEntry(EditLogEntryCommand),
+    UpdateProfile(UpdateProfileCommand),
+    SetDailyTarget(SetDailyTargetCommand),
+    SetMacroTargets(SetMacroTargetsCommand),
+    CopyEntries(CopyEntriesCommand),
+    RecordWeight(RecordWeightCommand),
+    ImportFoods(ImportFoodsCommand),
+    AddScheduleRule(AddScheduleRuleCommand),
+    RemoveScheduleRule(RemoveScheduleRuleCommand),
+    SkipScheduled(SkipScheduledCommand),
+}
+
+// The data files commands change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataFile {
+    Foods,
+    Log,
+    Profile,
+    Schedule,
+}
+
+impl CommandRecord {
+    // Files this command changes when it runs or is undone, which must be saved along with
+    // the journal that records it
+    pub fn data_files(&self) -> &'static [DataFile] {
+        match self {
+            CommandRecord::AddFood(_) | CommandRecord::ImportFoods(_) => &[DataFile::Foods],
+            CommandRecord::RemoveFood(_) | CommandRecord::EditFood(_) => &[DataFile::Foods, DataFile::Log],
+            CommandRecord::RenameFood(_) => &[DataFile::Foods, DataFile::Log, DataFile::Schedule],
+            CommandRecord::LogFood(_) | CommandRecord::RemoveLogEntry(_) | CommandRecord::EditLogEntry(_)
+                | CommandRecord::CopyEntries(_) => &[DataFile::Log],
+            CommandRecord::RemoveScheduleRule(_) => &[DataFile::Schedule],
+            CommandRecord::AddScheduleRule(_) | CommandRecord::SkipScheduled(_) => &[DataFile::Schedule, DataFile::Log],
+            CommandRecord::UpdateProfile(_) | CommandRecord::SetDailyTarget(_)
+                | CommandRecord::SetMacroTargets(_) | CommandRecord::RecordWeight(_) => &[DataFile::Profile],
+        }
+    }
+
+    fn command(&self) -> &dyn UndoableCommand {
+        match self {
+            CommandRecord::AddFood(command) => command,
+            CommandRecord::RemoveFood(command) => command,
+            CommandRecord::EditFood(command) => command,
+            CommandRecord::RenameFood(command) => command,
+            CommandRecord::LogFood(command) => command,
+            CommandRecord::RemoveLogEntry(command) => command,
+            CommandRecord::EditLogEntry(command) => command,
+            CommandRecord::UpdateProfile(command) => command,
+            CommandRecord::SetDailyTarget(command) => command,
+            CommandRecord::SetMacroTargets(command) => command,
+            CommandRecord::CopyEntries(command) => command,
+            CommandRecord::RecordWeight(command) => command,
+            CommandRecord::ImportFoods(command) => command,
+            CommandRecord::AddScheduleRule(command) => command,
+            CommandRecord::RemoveScheduleRule(command) => command,
+            CommandRecord::SkipScheduled(command) => command,
+        }
+    }
+
+    fn command_mut(&mut self) -> &mut dyn UndoableCommand {
+        match self {
+            CommandRecord::AddFood(command) => command,
+            CommandRecord::RemoveFood(command) => command,
+            CommandRecord::EditFood(command) => command,
+            CommandRecord::RenameFood(command) => command,
+            CommandRecord::LogFood(command) => command,
+            CommandRecord::RemoveLogEntry(command) => command,
+            CommandRecord::EditLogEntry(command) => command,
+            CommandRecord::UpdateProfile(command) => command,
+            CommandRecord::SetDailyTarget(command) => command,
+            CommandRecord::SetMacroTargets(command) => command,
+            CommandRecord::CopyEntries(command) => command,
+            CommandRecord::RecordWeight(command) => command,
+            CommandRecord::ImportFoods(command) => command,
+            CommandRecord::AddScheduleRule(command) => command,
+            CommandRecord::RemoveScheduleRule(command) => command,
+            CommandRecord::SkipScheduled(command) => command,
+        }
+    }
+}
+
+impl UndoableCommand for CommandRecord {
+    fn execute(&mut self, ctx: &mut CommandContext) -> bool {
+        self.command_mut().execute(ctx)
+    }
+
+    fn undo(&mut self, ctx: &mut CommandContext) -> bool {
+        self.command_mut().undo(ctx)
+    }
+
+    fn description(&self) -> String {
+        self.command().description()
+    }
+}
+
+impl From<AddFoodCommand> for CommandRecord {
+    fn from(command: AddFoodCommand) -> Self {
+        CommandRecord::AddFood(command)
+    }
+}
+
+impl From<RemoveFoodCommand> for CommandRecord {
+    fn from(command: RemoveFoodCommand) -> Self {
+        CommandRecord::RemoveFood(command)
+    }
+}
+
+impl From<EditFoodCommand> for CommandRecord {
+    fn from(command: EditFoodCommand) -> Self {
+        CommandRecord::EditFood(command)
+    }
+}
+
+impl From<RenameFoodCommand> for CommandRecord {
+    fn from(command: RenameFoodCommand) -> Self {
+        CommandRecord::RenameFood(command)
+    }
+}
+
+impl From<LogFoodCommand> for CommandRecord {
+    fn from(command: LogFoodCommand) -> Self {
+        CommandRecord::LogFood(command)
+    }
+}
+
+impl From<RemoveLogEntryCommand> for CommandRecord {
+    fn from(command: RemoveLogEntryCommand) -> Self {
+        CommandRecord::RemoveLogEntry(command)
+    }
+}
+
+impl From<UpdateProfileCommand> for CommandRecord {
+    fn from(command: UpdateProfileCommand) -> Self {
+        CommandRecord::UpdateProfile(command)
+    }
+}
+
+impl From<SetDailyTargetCommand> for CommandRecord {
+    fn from(command: SetDailyTargetCommand) -> Self {
+        CommandRecord::SetDailyTarget(command)
+    }
+}
+
+impl From<SetMacroTargetsCommand> for CommandRecord {
+    fn from(command: SetMacroTargetsCommand) -> Self {
+        CommandRecord::SetMacroTargets(command)
+    }
+}
+
+impl From<CopyEntriesCommand> for CommandRecord {
+    fn from(command: CopyEntriesCommand) -> Self {
+        CommandRecord::CopyEntries(command)
+    }
+}
+
+impl From<RecordWeightCommand> for CommandRecord {
+    fn from(command: RecordWeightCommand) -> Self {
+        CommandRecord::RecordWeight(command)
+    }
+}
+
+impl From<ImportFoodsCommand> for CommandRecord {
+    fn from(command: ImportFoodsCommand) -> Self {
+        CommandRecord::ImportFoods(command)
+    }
+}
+
+impl From<EditLogEntryCommand> for CommandRecord {
+    fn from(command: EditLogEntryCommand) -> Self {
+        CommandRecord::EditLogEntry(command)
+    }
+}
+
+impl From<AddScheduleRuleCommand> for CommandRecord {
+    fn from(command: AddScheduleRuleCommand) -> Self {
+        CommandRecord::AddScheduleRule(command)
+    }
+}
+
+impl From<RemoveScheduleRuleCommand> for CommandRecord {
+    fn from(command: RemoveScheduleRuleCommand) -> Self {
+        CommandRecord::RemoveScheduleRule(command)
+    }
+}
+
+impl From<SkipScheduledCommand> for CommandRecord {
+    fn from(command: SkipScheduledCommand) -> Self {
+        CommandRecord::SkipScheduled(command)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryError {
+    NothingToUndo,
+    NothingToRedo,
+    UndoRefused(String), // Description of the command that could not be undone
+    RedoRefused(String),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::NothingToUndo => write!(f, "nothing to undo"),
+            HistoryError::NothingToRedo => write!(f, "nothing to redo"),
+            HistoryError::UndoRefused(description) => write!(f,
+                "cannot undo the last change ({}): the data has changed since, e.g. a composite food now uses the food or its old id is taken. It stays in the history.",
+                description),
+            HistoryError::RedoRefused(description) => write!(f,
+                "cannot redo the next change ({}): it no longer fits the current data, e.g. the food it changes is gone or the id is taken. It stays in the history.",
+                description),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+// Main CommandManager to handle the undo and redo stacks. Both stacks are kept in a journal
+// file next to the data, so changes made in an earlier session can still be undone.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct CommandManager {
-    undo_stack: Vec<Box<dyn UndoableCommand>>,
+    undo_stack: Vec<CommandRecord>,
+    redo_stack: Vec<CommandRecord>,
+    #[serde(skip)]
+    file_path: String,
+    #[serde(skip)]
+    history_limit: usize, // Oldest commands are forgotten beyond this many
 }
 
 impl CommandManager {
-    pub fn new() -> Self {
+    pub fn new(file_path: &str, history_limit: usize) -> Self {
         CommandManager {
             undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            file_path: file_path.to_string(),
+            history_limit,
+        }
+    }
+
+    // Restores the history saved by an earlier session. Unlike the data files, a damaged
+    // journal is not protected: history is only a convenience, so it is simply started over.
+    pub fn load(&mut self) -> Result<(), StoreError> {
+        let loaded = storage::read_json::<CommandManager>(&self.file_path)?;
+        if let Some(loaded) = loaded {
+            self.undo_stack = loaded.undo_stack;
+            self.redo_stack = loaded.redo_stack;
+            self.trim();
         }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), StoreError> {
+        storage::write_json(&self.file_path, self, 0)
     }
 
-    pub fn execute_command(&mut self, mut command: Box<dyn UndoableCommand>) -> bool {
-        let success = command.execute();
+    // Runs a new command. Anything that was undone can no longer be redone afterwards.
+    pub fn execute_command(&mut self, command: impl Into<CommandRecord>, ctx: &mut CommandContext) -> bool {
+        let mut command = command.into();
+        let success = command.execute(ctx);
         if success {
             self.undo_stack.push(command);
+            self.redo_stack.clear();
+            self.trim();
         }
         success
     }
 
-    pub fn undo_last_command(&mut self) -> Option<String> {
-        if let Some(mut command) = self.undo_stack.pop() {
-            let description = command.description();
-            match command.undo() {
-                true => Some(description),
-                false => None,
-            }
+    // Undoes the latest command. A command that refuses stays where it was, so it can be
+    // tried again once whatever blocked it is resolved.
+    pub fn undo_last_command(&mut self, ctx: &mut CommandContext) -> Result<String, HistoryError> {
+        let mut command = self.undo_stack.pop().ok_or(HistoryError::NothingToUndo)?;
+        let description = command.description();
+        if command.undo(ctx) {
+            self.redo_stack.push(command);
+            Ok(description)
         } else {
-            None
+            self.undo_stack.push(command);
+            Err(HistoryError::UndoRefused(description))
+        }
+    }
+
+    pub fn redo_last_command(&mut self, ctx: &mut CommandContext) -> Result<String, HistoryError> {
+        let mut command = self.redo_stack.pop().ok_or(HistoryError::NothingToRedo)?;
+        let description = command.description();
+        if command.execute(ctx) {
+            self.undo_stack.push(command);
+            Ok(description)
+        } else {
+            self.redo_stack.push(command);
+            Err(HistoryError::RedoRefused(description))
+        }
+    }
+
+    // The command the next undo would take back
+    pub fn next_undo(&self) -> Option<&CommandRecord> {
+        self.undo_stack.last()
+    }
+
+    pub fn next_redo(&self) -> Option<&CommandRecord> {
+        self.redo_stack.last()
+    }
+
+    // Forgets every recorded command, used when the data they refer to is replaced wholesale
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    fn trim(&mut self) {
+        if self.undo_stack.len() > self.history_limit {
+            let excess = self.undo_stack.len() - self.history_limit;
+            self.undo_stack.drain(..excess);
+        }
+        // The next command to redo is the last one, so the oldest are dropped from the front
+        if self.redo_stack.len() > self.history_limit {
+            let excess = self.redo_stack.len() - self.history_limit;
+            self.redo_stack.drain(..excess);
         }
     }
 
@@ -191,9 +1193,249 @@ impl CommandManager {
         !self.undo_stack.is_empty()
     }
 
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    // Descriptions of the commands that can be undone, oldest first
     pub fn command_history(&self) -> Vec<String> {
         self.undo_stack.iter()
             .map(|cmd| cmd.description())
             .collect()
     }
-}
\ No newline at end of file
+
+    // Descriptions of the undone commands that can be redone, next to redo first
+    pub fn redo_history(&self) -> Vec<String> {
+        self.redo_stack.iter()
+            .rev()
+            .map(|cmd| cmd.description())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{BasicFood, CompositeFood, Nutrients};
+
+    struct Stores {
+        db: Database,
+        log: DailyLog,
+        profile: Profile,
+        schedule: Schedule,
+    }
+
+    impl Stores {
+        fn new() -> Self {
+            Stores {
+                db: Database::new("food_db.json", 0),
+                log: DailyLog::new("log.json", 0),
+                profile: Profile::new("profile.json", 0),
+                schedule: Schedule::new("schedule.json", 0),
+            }
+        }
+
+        fn ctx(&mut self) -> CommandContext<'_> {
+            CommandContext { db: &mut self.db, log: &mut self.log, profile: &mut self.profile, schedule: &mut self.schedule }
+        }
+    }
+
+    fn add(id: &str) -> AddFoodCommand {
+        AddFoodCommand::new(Food::Basic(BasicFood {
+            id: id.to_string(),
+            keywords: vec![id.to_string()],
+            nutrients: Nutrients::default(),
+            serving: None,
+            barcodes: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn history_cap_keeps_the_next_commands_to_redo() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        for id in ["A", "B", "C", "D"] {
+            assert!(manager.execute_command(add(id), &mut stores.ctx()));
+        }
+        for _ in 0..4 {
+            manager.undo_last_command(&mut stores.ctx()).unwrap();
+        }
+        manager.history_limit = 2;
+        manager.trim();
+        assert_eq!(manager.redo_history(), ["Add basic food 'A'", "Add basic food 'B'"]);
+
+        manager.execute_command(add("E"), &mut stores.ctx());
+        manager.execute_command(add("F"), &mut stores.ctx());
+        manager.execute_command(add("G"), &mut stores.ctx());
+        assert_eq!(manager.command_history(), ["Add basic food 'F'", "Add basic food 'G'"]);
+    }
+
+    #[test]
+    fn undo_and_redo_run_in_reverse_order() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        manager.execute_command(add("A"), &mut stores.ctx());
+        manager.execute_command(add("B"), &mut stores.ctx());
+
+        assert_eq!(manager.undo_last_command(&mut stores.ctx()), Ok("Add basic food 'B'".to_string()));
+        assert_eq!(manager.undo_last_command(&mut stores.ctx()), Ok("Add basic food 'A'".to_string()));
+        assert_eq!(manager.undo_last_command(&mut stores.ctx()), Err(HistoryError::NothingToUndo));
+        assert!(stores.db.foods.is_empty());
+
+        assert_eq!(manager.redo_last_command(&mut stores.ctx()), Ok("Add basic food 'A'".to_string()));
+        assert_eq!(manager.redo_last_command(&mut stores.ctx()), Ok("Add basic food 'B'".to_string()));
+        assert_eq!(manager.redo_last_command(&mut stores.ctx()), Err(HistoryError::NothingToRedo));
+        assert_eq!(stores.db.foods.len(), 2);
+    }
+
+    #[test]
+    fn a_new_command_clears_redo() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        manager.execute_command(add("A"), &mut stores.ctx());
+        manager.undo_last_command(&mut stores.ctx()).unwrap();
+        assert!(manager.can_redo());
+
+        manager.execute_command(add("B"), &mut stores.ctx());
+        assert!(!manager.can_redo());
+        assert_eq!(manager.command_history(), ["Add basic food 'B'"]);
+    }
+
+    #[test]
+    fn refused_undo_stays_in_history() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        manager.execute_command(add("A"), &mut stores.ctx());
+        // A composite added outside the history now depends on A, so A can't be taken away
+        stores.db.foods.insert("Bowl".to_string(), Food::Composite(CompositeFood {
+            id: "Bowl".to_string(),
+            keywords: Vec::new(),
+            components: vec![("A".to_string(), 1.0)],
+            serving: None,
+            recipe_yield: None,
+        }));
+
+        let refused = manager.undo_last_command(&mut stores.ctx());
+        assert_eq!(refused, Err(HistoryError::UndoRefused("Add basic food 'A'".to_string())));
+        assert_eq!(manager.command_history(), ["Add basic food 'A'"]);
+        assert!(!manager.can_redo());
+        assert!(stores.db.foods.contains_key("A"));
+    }
+
+    #[test]
+    fn journal_round_trips_both_stacks() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        manager.execute_command(add("A"), &mut stores.ctx());
+        manager.execute_command(RenameFoodCommand::new("A", "B"), &mut stores.ctx());
+        manager.execute_command(UpdateProfileCommand::new(ProfileSetting::Age(40)), &mut stores.ctx());
+        manager.undo_last_command(&mut stores.ctx()).unwrap();
+
+        let path = std::env::temp_dir().join(format!("diet_manager_history_{}.json", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        manager.file_path = path.clone();
+        manager.save().unwrap();
+        let mut restored = CommandManager::new(&path, 10);
+        let loaded = restored.load();
+        let _ = std::fs::remove_file(&path);
+        loaded.unwrap();
+
+        assert_eq!(restored.command_history(), manager.command_history());
+        assert_eq!(restored.redo_history(), manager.redo_history());
+        restored.redo_last_command(&mut stores.ctx()).unwrap();
+        assert_eq!(stores.profile.age, 40);
+        restored.undo_last_command(&mut stores.ctx()).unwrap();
+        restored.undo_last_command(&mut stores.ctx()).unwrap();
+        assert!(stores.db.foods.contains_key("A") && !stores.db.foods.contains_key("B"));
+    }
+
+    #[test]
+    fn adding_a_taken_id_is_refused() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        assert!(manager.execute_command(add("A"), &mut stores.ctx()));
+        let mut second = add("A");
+        if let Food::Basic(basic) = &mut second.food {
+            basic.nutrients.calories = 500.0;
+        }
+        assert!(!manager.execute_command(second, &mut stores.ctx()));
+        assert_eq!(manager.command_history().len(), 1);
+        assert_eq!(compute_nutrients(&stores.db.foods["A"], &stores.db.foods).calories, 0.0);
+    }
+
+    // A daily "Milk" rule from a Monday, with its entry for that day already generated
+    fn scheduled(stores: &mut Stores, manager: &mut CommandManager) -> u64 {
+        manager.execute_command(add("Milk"), &mut stores.ctx());
+        let rule = ScheduleRule::new("Milk", Quantity::servings(1.0), None, crate::schedule::parse_days("daily").unwrap(), "2026-01-05", None);
+        assert!(manager.execute_command(AddScheduleRuleCommand::new(rule), &mut stores.ctx()));
+        let applied = stores.schedule.apply(&["2026-01-05".to_string()], &mut stores.log, &stores.db.foods);
+        assert_eq!(applied.added, 1);
+        stores.schedule.rules()[0].id
+    }
+
+    #[test]
+    fn undoing_a_skip_puts_back_the_entry_and_the_due_day() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        let id = scheduled(&mut stores, &mut manager);
+
+        assert!(manager.execute_command(SkipScheduledCommand::new(id, "2026-01-05"), &mut stores.ctx()));
+        assert!(stores.log.get_log_entries("2026-01-05").is_empty());
+        manager.undo_last_command(&mut stores.ctx()).unwrap();
+        assert_eq!(stores.log.entries_for_rule("2026-01-05", id).len(), 1);
+
+        // Skipping a day that wasn't generated yet and undoing it leaves the day due again
+        manager.execute_command(SkipScheduledCommand::new(id, "2026-01-06"), &mut stores.ctx());
+        manager.undo_last_command(&mut stores.ctx()).unwrap();
+        let applied = stores.schedule.apply(&["2026-01-06".to_string()], &mut stores.log, &stores.db.foods);
+        assert_eq!(applied.added, 1);
+    }
+
+    #[test]
+    fn undoing_a_new_rule_takes_out_its_entries_and_redo_restores_them() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        let id = scheduled(&mut stores, &mut manager);
+        let entry_id = stores.log.entries_for_rule("2026-01-05", id)[0];
+
+        manager.undo_last_command(&mut stores.ctx()).unwrap();
+        assert!(stores.schedule.rules().is_empty());
+        assert!(stores.log.get_log_entries("2026-01-05").is_empty());
+
+        manager.redo_last_command(&mut stores.ctx()).unwrap();
+        assert_eq!(stores.schedule.rules()[0].id, id);
+        assert_eq!(stores.log.entries_for_rule("2026-01-05", id), [entry_id]);
+    }
+
+    #[test]
+    fn removed_rule_comes_back_with_its_id() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        let id = scheduled(&mut stores, &mut manager);
+
+        assert!(manager.execute_command(RemoveScheduleRuleCommand::new(id), &mut stores.ctx()));
+        assert!(stores.schedule.rules().is_empty());
+        assert_eq!(stores.log.get_log_entries("2026-01-05").len(), 1);
+        manager.undo_last_command(&mut stores.ctx()).unwrap();
+        assert_eq!(stores.schedule.rules()[0].id, id);
+    }
+
+    #[test]
+    fn editing_a_generated_entry_keeps_its_rule() {
+        let mut stores = Stores::new();
+        let mut manager = CommandManager::new("history.json", 10);
+        let id = scheduled(&mut stores, &mut manager);
+        let entry = stores.log.get_log_entries("2026-01-05")[0].clone();
+
+        let edit = EditLogEntryCommand::new("2026-01-05", &entry, &stores.db.foods["Milk"], Quantity::servings(2.0), Some("Lunch".to_string())).unwrap();
+        assert!(manager.execute_command(edit, &mut stores.ctx()));
+        let edited = stores.log.get_log_entries("2026-01-05")[0].clone();
+        assert_eq!((edited.id, edited.rule, edited.servings), (entry.id, Some(id), 2.0));
+        assert_eq!(edited.meal.as_deref(), Some("Lunch"));
+        let applied = stores.schedule.apply(&["2026-01-05".to_string()], &mut stores.log, &stores.db.foods);
+        assert_eq!(applied.added, 0);
+
+        manager.undo_last_command(&mut stores.ctx()).unwrap();
+        assert_eq!(stores.log.get_log_entries("2026-01-05")[0].servings, 1.0);
+    }
+}
@@ -0,0 +1,203 @@
+use serde::{Serialize, Deserialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::log::ReportMode;
+use crate::command;
+use crate::storage;
+
+const APP_DIR: &str = "diet_manager";
+pub const DATA_DIR_ENV: &str = "DIET_MANAGER_DATA_DIR";
+pub const CONFIG_ENV: &str = "DIET_MANAGER_CONFIG";
+
+// Settings read from the config file. Anything missing falls back to the defaults below.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub data_dir: Option<PathBuf>,
+    pub food_db_file: String,
+    pub log_file: String,
+    pub profile_file: String,
+    pub history_file: String,
+    pub schedule_file: String,
+    pub open_food_facts: Option<PathBuf>, // Export used to look up unknown barcodes
+    pub report_mode: ReportMode,
+    pub backup_count: usize, // Earlier versions kept of each data file
+    pub history_limit: usize, // Commands kept in the undo history
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            data_dir: None,
+            food_db_file: "food_db.json".to_string(),
+            log_file: "log.json".to_string(),
+            profile_file: "profile.json".to_string(),
+            history_file: "history.json".to_string(),
+            schedule_file: "schedule.json".to_string(),
+            open_food_facts: None,
+            report_mode: ReportMode::Historical,
+            backup_count: storage::DEFAULT_BACKUP_COUNT,
+            history_limit: command::DEFAULT_HISTORY_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    MissingValue(String),
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, source: serde_json::Error },
+    NoDataDir,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ConfigError::Io { path, source } => write!(f, "cannot access {}: {}", path.display(), source),
+            ConfigError::Parse { path, source } => write!(f, "invalid config file {}: {}", path.display(), source),
+            ConfigError::NoDataDir => write!(f, "cannot determine a data directory; pass --data-dir or set {}", DATA_DIR_ENV),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Where the app keeps its files, resolved once at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub config_file: Option<PathBuf>,
+    pub settings: Settings,
+}
+
+impl Config {
+    // Resolves the configuration, removing the global --data-dir and --config options from
+    // `args` so the rest can be handled as a subcommand. The data directory comes from, in order:
+    // --data-dir, $DIET_MANAGER_DATA_DIR, `data_dir` in the config file, then the XDG default.
+    pub fn resolve(args: &mut Vec<String>) -> Result<Config, ConfigError> {
+        let data_dir_flag = take_option(args, "--data-dir")?;
+        let config_flag = take_option(args, "--config")?;
+
+        let config_file = config_flag
+            .or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from))
+            .or_else(|| base_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join(APP_DIR).join("config.json")));
+
+        let settings = match &config_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(data) => serde_json::from_str(&data)
+                    .map_err(|source| ConfigError::Parse { path: path.clone(), source })?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Settings::default(),
+                Err(source) => return Err(ConfigError::Io { path: path.clone(), source }),
+            },
+            None => Settings::default(),
+        };
+
+        let data_dir = data_dir_flag
+            .or_else(|| env::var_os(DATA_DIR_ENV).map(PathBuf::from))
+            .or_else(|| settings.data_dir.clone())
+            .or_else(|| base_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join(APP_DIR)))
+            .ok_or(ConfigError::NoDataDir)?;
+
+        Ok(Config { data_dir, config_file, settings })
+    }
+
+    pub fn food_db_path(&self) -> String {
+        self.path_string(&self.settings.food_db_file)
+    }
+
+    pub fn log_path(&self) -> String {
+        self.path_string(&self.settings.log_file)
+    }
+
+    pub fn profile_path(&self) -> String {
+        self.path_string(&self.settings.profile_file)
+    }
+
+    pub fn history_path(&self) -> String {
+        self.path_string(&self.settings.history_file)
+    }
+
+    pub fn schedule_path(&self) -> String {
+        self.path_string(&self.settings.schedule_file)
+    }
+
+    // The Open Food Facts export, if one is configured. Relative paths are taken from the
+    // data directory.
+    pub fn open_food_facts_path(&self) -> Option<PathBuf> {
+        self.settings.open_food_facts.as_ref().map(|path| self.data_dir.join(path))
+    }
+
+    fn path_string(&self, file: &str) -> String {
+        self.data_dir.join(file).to_string_lossy().into_owned()
+    }
+
+    // Creates the data directory. When it holds none of the data files yet but the working
+    // directory does (where older versions kept them), those files are copied over once.
+    // Returns the names of the files that were copied.
+    pub fn prepare_data_dir(&self) -> Result<Vec<String>, ConfigError> {
+        fs::create_dir_all(&self.data_dir)
+            .map_err(|source| ConfigError::Io { path: self.data_dir.clone(), source })?;
+
+        let files = [&self.settings.food_db_file, &self.settings.log_file, &self.settings.profile_file];
+        if files.iter().any(|file| self.data_dir.join(file).exists()) {
+            return Ok(Vec::new());
+        }
+        let cwd = env::current_dir().map_err(|source| ConfigError::Io { path: PathBuf::from("."), source })?;
+        if same_dir(&cwd, &self.data_dir) {
+            return Ok(Vec::new());
+        }
+
+        let mut copied = Vec::new();
+        for file in files {
+            let legacy = cwd.join(file);
+            if legacy.is_file() {
+                fs::copy(&legacy, self.data_dir.join(file))
+                    .map_err(|source| ConfigError::Io { path: legacy.clone(), source })?;
+                copied.push(file.clone());
+            }
+        }
+        Ok(copied)
+    }
+}
+
+// Pulls `--name value` or `--name=value` out of the argument list
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<PathBuf>, ConfigError> {
+    let prefix = format!("{}=", name);
+    let mut value = None;
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == name {
+            if i + 1 >= args.len() {
+                return Err(ConfigError::MissingValue(name.to_string()));
+            }
+            value = Some(PathBuf::from(args.remove(i + 1)));
+            args.remove(i);
+        } else if let Some(rest) = args[i].strip_prefix(&prefix) {
+            value = Some(PathBuf::from(rest));
+            args.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    Ok(value)
+}
+
+// $XDG_*_HOME when set, otherwise the given directory under $HOME (or %APPDATA% on Windows)
+fn base_dir(xdg_var: &str, home_fallback: &str) -> Option<PathBuf> {
+    env::var_os(xdg_var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(home_fallback)))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
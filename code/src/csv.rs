@@ -0,0 +1,132 @@
+use std::io::{self, BufRead};
+
+// Reader for comma- or tab-separated files. Quoted fields may contain the delimiter,
+// doubled quotes ("") and line breaks, as in RFC 4180. Some exports (such as tab-separated
+// dumps) use quotes as ordinary characters, so quoting can be turned off.
+pub struct CsvReader<R: BufRead> {
+    reader: R,
+    delimiter: char,
+    quoting: bool,
+    line: String,
+    first: bool,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    pub fn new(reader: R, delimiter: char) -> Self {
+        CsvReader {
+            reader,
+            delimiter,
+            quoting: true,
+            line: String::new(),
+            first: true,
+        }
+    }
+
+    pub fn without_quotes(mut self) -> Self {
+        self.quoting = false;
+        self
+    }
+
+    // The next record's fields, or `None` at the end of the input
+    pub fn next_record(&mut self) -> io::Result<Option<Vec<String>>> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                // A quoted field left open at the end of the file keeps what was read
+                if in_quotes || !fields.is_empty() {
+                    fields.push(field);
+                    return Ok(Some(fields));
+                }
+                return Ok(None);
+            }
+            let mut text = self.line.as_str();
+            if self.first {
+                text = text.trim_start_matches('\u{feff}');
+                self.first = false;
+            }
+
+            let mut chars = text.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c != '"' {
+                        field.push(c);
+                    } else if chars.peek() == Some(&'"') {
+                        field.push('"');
+                        chars.next();
+                    } else {
+                        in_quotes = false;
+                    }
+                } else if c == '"' && self.quoting && field.is_empty() {
+                    in_quotes = true;
+                } else if c == self.delimiter {
+                    fields.push(std::mem::take(&mut field));
+                } else if c != '\n' && c != '\r' {
+                    field.push(c);
+                }
+            }
+            if !in_quotes {
+                fields.push(field);
+                return Ok(Some(fields));
+            }
+        }
+    }
+}
+
+// Position of a named column in a header row, ignoring case
+pub fn column(header: &[String], name: &str) -> Option<usize> {
+    header.iter().position(|column| column.trim().eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(text: &str, delimiter: char) -> Vec<Vec<String>> {
+        let mut reader = CsvReader::new(text.as_bytes(), delimiter);
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[test]
+    fn quoted_fields_may_hold_the_delimiter() {
+        assert_eq!(records("1,\"Apples, raw\",52\n", ','), vec![vec!["1", "Apples, raw", "52"]]);
+    }
+
+    #[test]
+    fn doubled_quotes_are_one_quote() {
+        assert_eq!(records("\"Cheese, \"\"cheddar\"\"\",x\n", ','), vec![vec!["Cheese, \"cheddar\"", "x"]]);
+    }
+
+    #[test]
+    fn quoted_fields_may_span_lines() {
+        assert_eq!(
+            records("id,note\r\n1,\"first line\nsecond line\"\r\n2,plain\r\n", ','),
+            vec![vec!["id", "note"], vec!["1", "first line\nsecond line"], vec!["2", "plain"]]
+        );
+    }
+
+    #[test]
+    fn skips_byte_order_mark_and_keeps_empty_fields() {
+        assert_eq!(records("\u{feff}a,,c\n", ','), vec![vec!["a", "", "c"]]);
+    }
+
+    #[test]
+    fn quotes_are_ordinary_without_quoting() {
+        let mut reader = CsvReader::new("12\t\"Best\" bar\n".as_bytes(), '\t').without_quotes();
+        assert_eq!(reader.next_record().unwrap(), Some(vec!["12".to_string(), "\"Best\" bar".to_string()]));
+        assert_eq!(reader.next_record().unwrap(), None);
+    }
+
+    #[test]
+    fn finds_columns_ignoring_case() {
+        let header = vec!["fdc_id".to_string(), " Description ".to_string()];
+        assert_eq!(column(&header, "description"), Some(1));
+        assert_eq!(column(&header, "gtin_upc"), None);
+    }
+}
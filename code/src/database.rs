@@ -1,56 +1,489 @@
-use std::collections::HashMap;
-use std::fs;
-use serde_json;
-use crate::food::Food;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::barcode::{gtin14, same_product};
+use crate::food::{Food, RecipeYield};
+use crate::search::{self, MatchMode, SearchHit};
+use crate::storage::{self, StoreError};
+
+// Problems found when checking how composite foods reference each other
+#[derive(Debug, Clone, PartialEq)]
+pub enum FoodGraphError {
+    Cycle(Vec<String>), // Food ids along the cycle, starting and ending with the same id
+    DanglingReference { food_id: String, component_id: String },
+    NonPositiveServings { food_id: String, component_id: String, servings: f32 },
+    InUse { food_id: String, used_by: Vec<String> },
+    UnknownFood(String),
+    DuplicateId(String),
+    DuplicateBarcode { food_id: String, barcode: String, used_by: String },
+    InvalidYield { food_id: String, recipe_yield: RecipeYield },
+}
+
+impl fmt::Display for FoodGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FoodGraphError::Cycle(path) =>
+                write!(f, "composite foods form a cycle: {}", path.join(" -> ")),
+            FoodGraphError::DanglingReference { food_id, component_id } =>
+                write!(f, "'{}' uses unknown food '{}'", food_id, component_id),
+            FoodGraphError::NonPositiveServings { food_id, component_id, servings } =>
+                write!(f, "'{}' uses {} serving(s) of '{}'; servings must be a positive finite number", food_id, servings, component_id),
+            FoodGraphError::InUse { food_id, used_by } =>
+                write!(f, "'{}' is used by composite food(s): {}", food_id, used_by.join(", ")),
+            FoodGraphError::UnknownFood(food_id) =>
+                write!(f, "no food named '{}'", food_id),
+            FoodGraphError::DuplicateId(food_id) =>
+                write!(f, "a food named '{}' already exists", food_id),
+            FoodGraphError::DuplicateBarcode { food_id, barcode, used_by } =>
+                write!(f, "'{}' has barcode {}, which '{}' already has", food_id, barcode, used_by),
+            FoodGraphError::InvalidYield { food_id, recipe_yield: RecipeYield::Servings(count) } =>
+                write!(f, "'{}' makes {} serving(s); the yield must be positive", food_id, count),
+            FoodGraphError::InvalidYield { food_id, recipe_yield } =>
+                write!(f, "'{}' makes {}, so it needs a positive yield and a serving size in the same unit", food_id, recipe_yield),
+        }
+    }
+}
+
+impl std::error::Error for FoodGraphError {}
+
+impl FoodGraphError {
+    // The foods at fault, i.e. the ones set aside when the error is found while loading
+    fn offending_foods(&self) -> Vec<&str> {
+        match self {
+            FoodGraphError::Cycle(path) => path.iter().map(String::as_str).collect(),
+            FoodGraphError::DanglingReference { food_id, .. }
+            | FoodGraphError::NonPositiveServings { food_id, .. }
+            | FoodGraphError::DuplicateBarcode { food_id, .. }
+            | FoodGraphError::InvalidYield { food_id, .. }
+            | FoodGraphError::DuplicateId(food_id) => vec![food_id.as_str()],
+            FoodGraphError::InUse { .. } | FoodGraphError::UnknownFood(_) => Vec::new(),
+        }
+    }
+}
 
 pub struct Database {
     pub foods: HashMap<String, Food>,
+    // Foods set aside at load because they break the food graph. They are saved back
+    // unchanged, so fixing the file brings them back, but can't be used until then.
+    pub quarantined: Vec<Food>,
     pub file_path: String,
+    read_only: bool, // Set when the file failed to load, so it is never overwritten
+    backup_count: usize,
 }
 
 impl Database {
-    pub fn new(file_path: &str) -> Self {
+    pub fn new(file_path: &str, backup_count: usize) -> Self {
         Database {
             foods: HashMap::new(),
+            quarantined: Vec::new(),
             file_path: file_path.to_string(),
+            read_only: false,
+            backup_count,
+        }
+    }
+
+    // Loads the foods and validates the whole food graph. Foods with problems, and composites
+    // that use them, are moved to `quarantined` and the problems are returned. If the file
+    // can't be read or parsed the database stays empty and refuses to save over it.
+    pub fn load(&mut self) -> Result<Vec<FoodGraphError>, StoreError> {
+        let path = self.file_path.clone();
+        self.load_from(&path)
+    }
+
+    // Loads the most recent backup instead; saving afterwards replaces the main file
+    pub fn load_backup(&mut self) -> Result<Vec<FoodGraphError>, StoreError> {
+        match storage::latest_backup(&self.file_path) {
+            Some(backup) => self.load_from(&backup.path.to_string_lossy()),
+            None => Err(StoreError::NoBackup(self.file_path.clone())),
+        }
+    }
+
+    fn load_from(&mut self, path: &str) -> Result<Vec<FoodGraphError>, StoreError> {
+        match storage::read_json::<Vec<Food>>(path) {
+            Ok(loaded_foods) => {
+                self.foods = HashMap::new();
+                self.quarantined = Vec::new();
+                self.read_only = false;
+                let mut errors = Vec::new();
+                for food in loaded_foods.unwrap_or_default() {
+                    if self.foods.contains_key(food.id()) {
+                        errors.push(FoodGraphError::DuplicateId(food.id().to_string()));
+                        self.quarantined.push(food);
+                    } else {
+                        self.foods.insert(food.id().to_string(), food);
+                    }
+                }
+                // Setting a food aside can leave composites that use it dangling, so check again
+                // until the remaining graph is valid
+                loop {
+                    let found = self.validate();
+                    if found.is_empty() {
+                        break;
+                    }
+                    for error in &found {
+                        for id in error.offending_foods() {
+                            if let Some(food) = self.foods.remove(id) {
+                                self.quarantined.push(food);
+                            }
+                        }
+                    }
+                    errors.extend(found);
+                }
+                Ok(errors)
+            },
+            Err(e) => {
+                self.read_only = true;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), StoreError> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly(self.file_path.clone()));
+        }
+        let foods_vec: Vec<&Food> = self.foods.values().chain(&self.quarantined).collect();
+        storage::write_json(&self.file_path, &foods_vec, self.backup_count)
+    }
+
+    // Adds a new food, refusing it if the id is taken or it would leave the food graph invalid.
+    // Changing an existing food goes through `replace_food`.
+    pub fn add_food(&mut self, food: Food) -> Result<(), Vec<FoodGraphError>> {
+        if self.foods.contains_key(food.id()) {
+            return Err(vec![FoodGraphError::DuplicateId(food.id().to_string())]);
+        }
+        let errors = self.validate_food(&food);
+        if !errors.is_empty() {
+            return Err(errors);
         }
+        self.foods.insert(food.id().to_string(), food);
+        Ok(())
     }
 
-    pub fn load(&mut self) {
-        if let Ok(data) = fs::read_to_string(&self.file_path) {
-            if let Ok(loaded_foods) = serde_json::from_str::<Vec<Food>>(&data) {
-                self.foods = loaded_foods.into_iter().map(|f| {
-                    let id = match &f {
-                        Food::Basic(b) => b.id.clone(),
-                        Food::Composite(c) => c.id.clone(),
-                    };
-                    (id, f)
-                }).collect();
+    // Swaps in a new definition for an existing food and returns the old one
+    pub fn replace_food(&mut self, food: Food) -> Result<Food, Vec<FoodGraphError>> {
+        let errors = self.validate_food(&food);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        match self.foods.get_mut(food.id()) {
+            Some(current) => Ok(std::mem::replace(current, food)),
+            None => Err(vec![FoodGraphError::UnknownFood(food.id().to_string())]),
+        }
+    }
+
+    // Checks a food as if it were added to the database, without modifying anything
+    pub fn validate_food(&self, food: &Food) -> Vec<FoodGraphError> {
+        let lookup = |id: &str| if id == food.id() { Some(food) } else { self.foods.get(id) };
+        let mut errors = check_components(food, &lookup);
+        if self.is_quarantined(food.id()) {
+            errors.push(FoodGraphError::DuplicateId(food.id().to_string()));
+        }
+        if let Some(cycle) = find_cycle(food.id(), &lookup, &mut Vec::new(), &mut HashSet::new()) {
+            errors.push(FoodGraphError::Cycle(cycle));
+        }
+        for barcode in food.barcodes() {
+            if let Some(other) = self.find_by_barcode(barcode).filter(|other| other.id() != food.id()) {
+                errors.push(FoodGraphError::DuplicateBarcode {
+                    food_id: food.id().to_string(),
+                    barcode: barcode.clone(),
+                    used_by: other.id().to_string(),
+                });
+            }
+        }
+        errors
+    }
+
+    // Checks foods that are already in the database, such as a batch inserted together.
+    // Barcodes are looked up in one index, so checking a large import stays linear.
+    pub fn validate_foods(&self, ids: &[String]) -> Vec<FoodGraphError> {
+        let lookup = |id: &str| self.foods.get(id);
+        let mut owners: HashMap<String, Vec<&str>> = HashMap::new(); // 14-digit barcode -> food ids
+        for food in self.foods.values() {
+            for barcode in food.barcodes() {
+                owners.entry(gtin14(barcode)).or_default().push(food.id());
+            }
+        }
+
+        let mut errors = Vec::new();
+        for id in ids {
+            let Some(food) = self.foods.get(id) else {
+                errors.push(FoodGraphError::UnknownFood(id.clone()));
+                continue;
+            };
+            if self.is_quarantined(id) {
+                errors.push(FoodGraphError::DuplicateId(id.clone()));
+            }
+            errors.extend(check_components(food, &lookup));
+            if let Some(cycle) = find_cycle(id, &lookup, &mut Vec::new(), &mut HashSet::new()) {
+                errors.push(FoodGraphError::Cycle(cycle));
+            }
+            for barcode in food.barcodes() {
+                let other = owners.get(&gtin14(barcode)).and_then(|ids| ids.iter().find(|other| **other != id.as_str()));
+                if let Some(other) = other {
+                    errors.push(FoodGraphError::DuplicateBarcode {
+                        food_id: id.clone(),
+                        barcode: barcode.clone(),
+                        used_by: other.to_string(),
+                    });
+                }
+            }
+        }
+        errors
+    }
+
+    // Checks every food in the database, reporting each cycle once
+    pub fn validate(&self) -> Vec<FoodGraphError> {
+        let lookup = |id: &str| self.foods.get(id);
+        let mut ids: Vec<&String> = self.foods.keys().collect();
+        ids.sort();
+
+        let mut errors: Vec<FoodGraphError> = ids.iter()
+            .flat_map(|id| check_components(&self.foods[*id], &lookup))
+            .collect();
+
+        let mut finished = HashSet::new();
+        for id in &ids {
+            if let Some(cycle) = find_cycle(id, &lookup, &mut Vec::new(), &mut finished) {
+                finished.extend(cycle.iter().cloned());
+                errors.push(FoodGraphError::Cycle(cycle));
+            }
+        }
+
+        // Each barcode is reported against the first food (by id) that has it
+        let mut seen: HashMap<String, &String> = HashMap::new();
+        for id in ids {
+            for barcode in self.foods[id].barcodes() {
+                match seen.get(&gtin14(barcode)) {
+                    Some(first) => errors.push(FoodGraphError::DuplicateBarcode {
+                        food_id: id.clone(),
+                        barcode: barcode.clone(),
+                        used_by: (*first).clone(),
+                    }),
+                    None => {
+                        seen.insert(gtin14(barcode), id);
+                    },
+                }
             }
         }
+        errors
     }
 
-    pub fn save(&self) {
-        let foods_vec: Vec<&Food> = self.foods.values().collect();
-        if let Ok(data) = serde_json::to_string_pretty(&foods_vec) {
-            let _ = fs::write(&self.file_path, data);
+    // Removes a food that no composite uses
+    pub fn remove_food(&mut self, id: &str) -> Result<Food, FoodGraphError> {
+        let used_by = self.referencing_composites(id);
+        if !used_by.is_empty() {
+            return Err(FoodGraphError::InUse { food_id: id.to_string(), used_by });
         }
+        self.foods.remove(id).ok_or_else(|| FoodGraphError::UnknownFood(id.to_string()))
     }
 
-    pub fn add_food(&mut self, food: Food) {
-        let id = match &food {
-            Food::Basic(b) => b.id.clone(),
-            Food::Composite(c) => c.id.clone(),
+    // Gives a food a new id and updates every composite that uses it. The old id is also
+    // swapped out of the food's keywords, since foods are created with their id as a keyword.
+    pub fn rename_food(&mut self, old_id: &str, new_id: &str) -> Result<(), FoodGraphError> {
+        if self.foods.contains_key(new_id) || self.is_quarantined(new_id) {
+            return Err(FoodGraphError::DuplicateId(new_id.to_string()));
+        }
+        let mut food = self.foods.remove(old_id).ok_or_else(|| FoodGraphError::UnknownFood(old_id.to_string()))?;
+
+        let (id, keywords) = match &mut food {
+            Food::Basic(b) => (&mut b.id, &mut b.keywords),
+            Food::Composite(c) => (&mut c.id, &mut c.keywords),
         };
-        self.foods.insert(id, food);
+        *id = new_id.to_string();
+        for keyword in keywords.iter_mut().filter(|kw| kw.as_str() == old_id) {
+            *keyword = new_id.to_string();
+        }
+        self.foods.insert(new_id.to_string(), food);
+
+        for other in self.foods.values_mut() {
+            if let Food::Composite(composite) = other {
+                for (component_id, _) in composite.components.iter_mut().filter(|(cid, _)| cid == old_id) {
+                    *component_id = new_id.to_string();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Composite foods that list the given food as a direct component
+    pub fn referencing_composites(&self, id: &str) -> Vec<String> {
+        let mut ids: Vec<String> = self.foods.values().filter_map(|food| match food {
+            Food::Composite(c) if c.components.iter().any(|(cid, _)| cid == id) => Some(c.id.clone()),
+            _ => None,
+        }).collect();
+        ids.sort();
+        ids
+    }
+
+    // The food itself plus every composite whose nutrition depends on it, directly or indirectly
+    pub fn dependents(&self, id: &str) -> Vec<String> {
+        let mut found = vec![id.to_string()];
+        let mut next = 0;
+        while next < found.len() {
+            for parent in self.referencing_composites(&found[next]) {
+                if !found.contains(&parent) {
+                    found.push(parent);
+                }
+            }
+            next += 1;
+        }
+        found
+    }
+
+    pub fn is_quarantined(&self, id: &str) -> bool {
+        self.quarantined.iter().any(|food| food.id() == id)
+    }
+
+    pub fn find_by_barcode(&self, barcode: &str) -> Option<&Food> {
+        self.foods.values().find(|food| food.barcodes().iter().any(|code| same_product(code, barcode)))
+    }
+
+    // Foods matching a query, best first; see `search::search`
+    pub fn search(&self, query: &str, mode: MatchMode) -> Vec<SearchHit<'_>> {
+        search::search(&self.foods, query, mode)
+    }
+}
+
+fn check_components<'a>(food: &Food, lookup: &impl Fn(&str) -> Option<&'a Food>) -> Vec<FoodGraphError> {
+    let mut errors = Vec::new();
+    if let Food::Composite(composite) = food {
+        for (component_id, servings) in &composite.components {
+            if lookup(component_id).is_none() {
+                errors.push(FoodGraphError::DanglingReference {
+                    food_id: composite.id.clone(),
+                    component_id: component_id.clone(),
+                });
+            }
+            if !servings.is_finite() || *servings <= 0.0 {
+                errors.push(FoodGraphError::NonPositiveServings {
+                    food_id: composite.id.clone(),
+                    component_id: component_id.clone(),
+                    servings: *servings,
+                });
+            }
+        }
+        if let (Some(recipe_yield), None) = (composite.recipe_yield, composite.portion()) {
+            errors.push(FoodGraphError::InvalidYield { food_id: composite.id.clone(), recipe_yield });
+        }
+    }
+    errors
+}
+
+// Depth-first search through composite components. `path` holds the ids currently being
+// visited; `finished` remembers foods already known to be acyclic so they are not rescanned.
+fn find_cycle<'a>(
+    id: &str,
+    lookup: &impl Fn(&str) -> Option<&'a Food>,
+    path: &mut Vec<String>,
+    finished: &mut HashSet<String>,
+) -> Option<Vec<String>> {
+    if let Some(start) = path.iter().position(|visited| visited == id) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(id.to_string());
+        return Some(cycle);
     }
-    
-    pub fn search_by_keyword(&self, keyword: &str) -> Vec<&Food> {
-        self.foods.values().filter(|food| {
-            match food {
-                Food::Basic(b) => b.keywords.iter().any(|kw| kw.contains(keyword)),
-                Food::Composite(c) => c.keywords.iter().any(|kw| kw.contains(keyword)),
+    if finished.contains(id) {
+        return None;
+    }
+
+    if let Some(Food::Composite(composite)) = lookup(id) {
+        path.push(id.to_string());
+        for (component_id, _) in &composite.components {
+            if let Some(cycle) = find_cycle(component_id, lookup, path, finished) {
+                return Some(cycle);
             }
-        }).collect()
+        }
+        path.pop();
+    }
+    finished.insert(id.to_string());
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{BasicFood, CompositeFood, Nutrients};
+
+    fn basic(id: &str) -> Food {
+        Food::Basic(BasicFood {
+            id: id.to_string(),
+            keywords: vec![id.to_string()],
+            nutrients: Nutrients::default(),
+            serving: None,
+            barcodes: Vec::new(),
+        })
+    }
+
+    fn composite(id: &str, components: &[(&str, f32)]) -> Food {
+        Food::Composite(CompositeFood {
+            id: id.to_string(),
+            keywords: vec![id.to_string()],
+            components: components.iter().map(|(id, servings)| (id.to_string(), *servings)).collect(),
+            serving: None,
+            recipe_yield: None,
+        })
+    }
+
+    fn database(foods: Vec<Food>) -> Database {
+        let mut db = Database::new("food_db.json", 0);
+        for food in foods {
+            db.add_food(food).unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn refuses_self_and_indirect_cycles() {
+        let mut db = database(vec![basic("Oats"), composite("Porridge", &[("Oats", 1.0)])]);
+        let errors = db.add_food(composite("Loop", &[("Loop", 1.0)])).unwrap_err();
+        assert_eq!(errors, [FoodGraphError::Cycle(vec!["Loop".to_string(), "Loop".to_string()])]);
+
+        db.add_food(composite("Bowl", &[("Porridge", 1.0)])).unwrap();
+        // Porridge using Bowl would close Porridge -> Bowl -> Porridge
+        let errors = db.validate_food(&composite("Porridge", &[("Oats", 1.0), ("Bowl", 1.0)]));
+        assert!(matches!(errors.as_slice(), [FoodGraphError::Cycle(path)] if path.first() == path.last()));
+    }
+
+    #[test]
+    fn refuses_dangling_ids_and_bad_servings() {
+        let db = database(vec![basic("Oats")]);
+        let errors = db.validate_food(&composite("Porridge", &[("Oat Milk", 1.0)]));
+        assert_eq!(errors, [FoodGraphError::DanglingReference {
+            food_id: "Porridge".to_string(),
+            component_id: "Oat Milk".to_string(),
+        }]);
+        for servings in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let errors = db.validate_food(&composite("Porridge", &[("Oats", servings)]));
+            assert!(matches!(errors.as_slice(), [FoodGraphError::NonPositiveServings { .. }]), "{} servings", servings);
+        }
+        assert!(db.validate_food(&composite("Porridge", &[("Oats", 0.5)])).is_empty());
+    }
+
+    #[test]
+    fn load_sets_aside_bad_foods_and_keeps_the_rest() {
+        let foods = vec![
+            basic("Oats"),
+            composite("Porridge", &[("Oats", 1.0)]),
+            composite("A", &[("B", 1.0)]),
+            composite("B", &[("A", 1.0)]),
+            composite("Uses A", &[("A", 1.0), ("Oats", 1.0)]), // Fine itself, but A is set aside
+            composite("Ghost", &[("Nothing", 1.0)]),
+        ];
+        let path = std::env::temp_dir().join(format!("diet_manager_foods_{}.json", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        std::fs::write(&path, serde_json::to_string(&foods).unwrap()).unwrap();
+        let mut db = Database::new(&path, 0);
+        let loaded = db.load();
+        let _ = std::fs::remove_file(&path);
+
+        let errors = loaded.unwrap();
+        assert!(errors.iter().any(|e| matches!(e, FoodGraphError::Cycle(_))));
+        let mut kept: Vec<&String> = db.foods.keys().collect();
+        kept.sort();
+        assert_eq!(kept, ["Oats", "Porridge"]);
+        let mut set_aside: Vec<&str> = db.quarantined.iter().map(|food| food.id()).collect();
+        set_aside.sort();
+        assert_eq!(set_aside, ["A", "B", "Ghost", "Uses A"]);
+        assert!(db.add_food(basic("Ghost")).is_err());
     }
 }
@@ -0,0 +1,447 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde_json::Value;
+use crate::csv::{CsvReader, column};
+use crate::barcode::{gtin14, parse_barcode};
+use crate::food::{BasicFood, Food, MeasureKind, Nutrient, Nutrients, ServingSize, keywords_from};
+
+// FoodData Central nutrient ids. Energy is reported in several ways depending on the data
+// type; they are tried in this order.
+const ENERGY_IDS: [u32; 3] = [1008, 2047, 2048]; // kcal, Atwater general, Atwater specific
+const ENERGY_KJ: u32 = 1062;
+const NUTRIENT_IDS: [(u32, Nutrient); 7] = [
+    (1003, Nutrient::Protein),
+    (1005, Nutrient::Carbs),
+    (1004, Nutrient::Fat),
+    (1079, Nutrient::Fiber),
+    (2000, Nutrient::Sugar),
+    (1063, Nutrient::Sugar), // "Sugars, Total NLEA", used by branded foods
+    (1093, Nutrient::Sodium),
+];
+const KJ_PER_KCAL: f32 = 4.184;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io { path: PathBuf, source: io::Error },
+    Json { path: PathBuf, source: serde_json::Error },
+    MissingColumn { path: PathBuf, column: String },
+    Unrecognized(PathBuf), // Neither a CSV download directory nor a JSON download
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Io { path, source } =>
+                write!(f, "cannot read {}: {}", path.display(), source),
+            ImportError::Json { path, source } =>
+                write!(f, "{} is not valid JSON: {}", path.display(), source),
+            ImportError::MissingColumn { path, column } =>
+                write!(f, "{} has no '{}' column", path.display(), column),
+            ImportError::Unrecognized(path) =>
+                write!(f, "{} is not a FoodData Central download (expected a CSV folder with food.csv or a JSON file)", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::Io { source, .. } => Some(source),
+            ImportError::Json { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// A food as read from the download. Nutrients are per 100 g, as FoodData Central reports them.
+#[derive(Debug, Clone)]
+pub struct FdcFood {
+    pub fdc_id: u64,
+    pub description: String,
+    pub data_type: String,
+    pub category: Option<String>,
+    pub gtin: Option<String>, // Barcode of a branded food
+    amounts: HashMap<u32, f32>, // Nutrient id -> amount, for the nutrients we use
+}
+
+impl FdcFood {
+    // Nutrients per 100 g, or `None` when the food has no energy value
+    pub fn nutrients(&self) -> Option<Nutrients> {
+        let calories = ENERGY_IDS.iter()
+            .find_map(|id| self.amounts.get(id).copied())
+            .or_else(|| self.amounts.get(&ENERGY_KJ).map(|kj| kj / KJ_PER_KCAL))?;
+        let mut nutrients = Nutrients { calories, ..Nutrients::default() };
+        // The first id listed for a nutrient wins when a food reports both
+        for (id, nutrient) in NUTRIENT_IDS.iter().rev() {
+            if let Some(amount) = self.amounts.get(id) {
+                nutrients.set(*nutrient, *amount);
+            }
+        }
+        Some(nutrients)
+    }
+
+    pub fn to_food(&self, id: &str) -> Option<Food> {
+        Some(Food::Basic(BasicFood {
+            id: id.to_string(),
+            keywords: keywords_from(&self.description),
+            nutrients: self.nutrients()?,
+            serving: Some(ServingSize {
+                amount: 100.0,
+                kind: MeasureKind::Mass,
+                household_units: Default::default(),
+            }),
+            // Codes that fail validation are dropped rather than stored as bad barcodes
+            barcodes: self.gtin.iter().filter_map(|gtin| parse_barcode(gtin).ok()).collect(),
+        }))
+    }
+}
+
+// Which foods to read. Data types and categories match loosely, so "sr legacy" finds
+// "sr_legacy_food" and "fruit" finds "Fruits and Fruit Juices". Empty lists match everything.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub data_types: Vec<String>,
+    pub categories: Vec<String>,
+}
+
+impl Filter {
+    fn matches_type(&self, data_type: &str) -> bool {
+        let data_type = normalize(data_type);
+        self.data_types.is_empty() || self.data_types.iter().any(|wanted| data_type.contains(&normalize(wanted)))
+    }
+
+    fn matches_category(&self, category: Option<&str>) -> bool {
+        if self.categories.is_empty() {
+            return true;
+        }
+        let Some(category) = category.map(normalize) else {
+            return false;
+        };
+        self.categories.iter().any(|wanted| category.contains(&normalize(wanted)))
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_lowercase()).collect()
+}
+
+// Reads a FoodData Central download: either the folder of a CSV download (or its food.csv)
+// or one of the JSON downloads
+pub fn read_foods(path: &Path, filter: &Filter) -> Result<Vec<FdcFood>, ImportError> {
+    if path.is_dir() {
+        return read_csv_dir(path, filter);
+    }
+    match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase()).as_deref() {
+        Some("csv") => read_csv_dir(path.parent().unwrap_or(Path::new(".")), filter),
+        Some("json") => read_json(path, filter),
+        _ => Err(ImportError::Unrecognized(path.to_path_buf())),
+    }
+}
+
+fn open_csv(path: &Path) -> Result<CsvReader<BufReader<File>>, ImportError> {
+    let file = File::open(path).map_err(|source| ImportError::Io { path: path.to_path_buf(), source })?;
+    Ok(CsvReader::new(BufReader::new(file), ','))
+}
+
+// Reads a CSV file's rows, keeping the named columns in the order given. A missing optional
+// file gives no rows.
+fn read_columns(path: &Path, names: &[&str], mut row: impl FnMut(Vec<&str>)) -> Result<(), ImportError> {
+    let mut reader = open_csv(path)?;
+    let io_error = |source| ImportError::Io { path: path.to_path_buf(), source };
+    let header = reader.next_record().map_err(io_error)?.unwrap_or_default();
+    let indexes: Vec<usize> = names.iter()
+        .map(|name| column(&header, name)
+            .ok_or_else(|| ImportError::MissingColumn { path: path.to_path_buf(), column: name.to_string() }))
+        .collect::<Result<_, _>>()?;
+    while let Some(record) = reader.next_record().map_err(io_error)? {
+        row(indexes.iter().map(|i| record.get(*i).map_or("", |field| field.trim())).collect());
+    }
+    Ok(())
+}
+
+fn read_csv_dir(dir: &Path, filter: &Filter) -> Result<Vec<FdcFood>, ImportError> {
+    let food_csv = dir.join("food.csv");
+    if !food_csv.is_file() {
+        return Err(ImportError::Unrecognized(dir.to_path_buf()));
+    }
+
+    // Category names live in separate tables depending on the data type
+    let mut categories: HashMap<String, String> = HashMap::new();
+    let category_csv = dir.join("food_category.csv");
+    if category_csv.is_file() {
+        read_columns(&category_csv, &["id", "description"], |row| {
+            categories.insert(row[0].to_string(), row[1].to_string());
+        })?;
+    }
+    let mut survey_categories: HashMap<String, String> = HashMap::new();
+    let wweia_csv = dir.join("wweia_food_category.csv");
+    if wweia_csv.is_file() {
+        read_columns(&wweia_csv, &["wweia_food_category", "wweia_food_category_description"], |row| {
+            survey_categories.insert(row[0].to_string(), row[1].to_string());
+        })?;
+    }
+
+    let mut foods: Vec<FdcFood> = Vec::new();
+    read_columns(&food_csv, &["fdc_id", "data_type", "description", "food_category_id"], |row| {
+        let Ok(fdc_id) = row[0].parse::<u64>() else { return };
+        if !filter.matches_type(row[1]) {
+            return;
+        }
+        let category = if row[1].contains("survey") { &survey_categories } else { &categories }
+            .get(row[3]).cloned();
+        foods.push(FdcFood {
+            fdc_id,
+            description: row[2].to_string(),
+            data_type: row[1].to_string(),
+            category,
+            gtin: None,
+            amounts: HashMap::new(),
+        });
+    })?;
+
+    let branded_csv = dir.join("branded_food.csv");
+    if branded_csv.is_file() && foods.iter().any(|food| food.data_type.contains("branded")) {
+        let mut branded: HashMap<u64, (String, String)> = HashMap::new();
+        read_columns(&branded_csv, &["fdc_id", "branded_food_category", "gtin_upc"], |row| {
+            if let Ok(fdc_id) = row[0].parse() {
+                branded.insert(fdc_id, (row[1].to_string(), row[2].to_string()));
+            }
+        })?;
+        for food in foods.iter_mut() {
+            if let Some((category, gtin)) = branded.remove(&food.fdc_id) {
+                food.category = food.category.take().or(Some(category));
+                food.gtin = Some(gtin).filter(|gtin| !gtin.is_empty());
+            }
+        }
+    }
+    foods.retain(|food| filter.matches_category(food.category.as_deref()));
+
+    let wanted: HashSet<u32> = ENERGY_IDS.iter().copied().chain([ENERGY_KJ]).chain(NUTRIENT_IDS.iter().map(|(id, _)| *id)).collect();
+    let index: HashMap<u64, usize> = foods.iter().enumerate().map(|(i, food)| (food.fdc_id, i)).collect();
+    read_columns(&dir.join("food_nutrient.csv"), &["fdc_id", "nutrient_id", "amount"], |row| {
+        let (Ok(fdc_id), Ok(nutrient_id), Ok(amount)) = (row[0].parse::<u64>(), row[1].parse::<u32>(), row[2].parse::<f32>()) else {
+            return;
+        };
+        if let (Some(i), true) = (index.get(&fdc_id), wanted.contains(&nutrient_id)) {
+            foods[*i].amounts.insert(nutrient_id, amount);
+        }
+    })?;
+    Ok(foods)
+}
+
+fn read_json(path: &Path, filter: &Filter) -> Result<Vec<FdcFood>, ImportError> {
+    let file = File::open(path).map_err(|source| ImportError::Io { path: path.to_path_buf(), source })?;
+    let value: Value = serde_json::from_reader(BufReader::new(file))
+        .map_err(|source| ImportError::Json { path: path.to_path_buf(), source })?;
+
+    // Downloads wrap the list in an object such as {"FoundationFoods": [...]}
+    let items = match &value {
+        Value::Array(items) => items,
+        Value::Object(object) => match object.values().find_map(|value| value.as_array()) {
+            Some(items) => items,
+            None => return Err(ImportError::Unrecognized(path.to_path_buf())),
+        },
+        _ => return Err(ImportError::Unrecognized(path.to_path_buf())),
+    };
+
+    let mut foods = Vec::new();
+    for item in items {
+        let (Some(fdc_id), Some(description)) = (item["fdcId"].as_u64(), item["description"].as_str()) else {
+            continue;
+        };
+        let data_type = item["dataType"].as_str().unwrap_or_default();
+        let category = item["foodCategory"]["description"].as_str()
+            .or_else(|| item["wweiaFoodCategory"]["wweiaFoodCategoryDescription"].as_str())
+            .or_else(|| item["brandedFoodCategory"].as_str());
+        if !filter.matches_type(data_type) || !filter.matches_category(category) {
+            continue;
+        }
+
+        let amounts = item["foodNutrients"].as_array().into_iter().flatten()
+            .filter_map(|entry| Some((entry["nutrient"]["id"].as_u64()? as u32, entry["amount"].as_f64()? as f32)))
+            .collect();
+        foods.push(FdcFood {
+            fdc_id,
+            description: description.trim().to_string(),
+            data_type: data_type.to_string(),
+            category: category.map(|category| category.to_string()),
+            gtin: item["gtinUpc"].as_str().map(|gtin| gtin.to_string()),
+            amounts,
+        });
+    }
+    Ok(foods)
+}
+
+// What to do when an imported food's name is already used in the database
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnConflict {
+    Skip,
+    Replace,
+    Rename, // Import it as "<description> (FDC <id>)"
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "skip" => Ok(OnConflict::Skip),
+            "replace" => Ok(OnConflict::Replace),
+            "rename" => Ok(OnConflict::Rename),
+            other => Err(format!("unknown conflict policy '{}' (expected skip, replace or rename)", other)),
+        }
+    }
+}
+
+// The foods an import will add or replace, worked out before anything changes
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    pub add: Vec<Food>,
+    pub replace: Vec<Food>,
+    pub renamed: usize,
+    pub skipped_existing: usize,
+    pub skipped_no_energy: usize,
+    pub dropped_barcodes: usize, // Left off because another food already has them
+}
+
+impl ImportPlan {
+    // e.g. "120 new, 3 replaced, 2 skipped because the name is taken"
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} new", self.add.len())];
+        if !self.replace.is_empty() {
+            parts.push(format!("{} replaced", self.replace.len()));
+        }
+        if self.renamed > 0 {
+            parts.push(format!("{} renamed with their FDC id", self.renamed));
+        }
+        if self.skipped_existing > 0 {
+            parts.push(format!("{} skipped because the name is taken", self.skipped_existing));
+        }
+        if self.skipped_no_energy > 0 {
+            parts.push(format!("{} skipped for lack of an energy value", self.skipped_no_energy));
+        }
+        if self.dropped_barcodes > 0 {
+            parts.push(format!("{} barcode(s) left off because another food has them", self.dropped_barcodes));
+        }
+        parts.join(", ")
+    }
+}
+
+// Turns the foods read into database foods. Names are the FDC descriptions; two foods in
+// the same download with the same description are told apart by their FDC id, and clashes
+// with existing foods follow `on_conflict`. A barcode that an existing food or an earlier
+// food in the download already has is left off, since a product's barcode can only belong
+// to one food. At most `limit` foods are taken.
+pub fn plan_import(foods: &[FdcFood], existing: &HashMap<String, Food>, on_conflict: OnConflict, limit: Option<usize>) -> ImportPlan {
+    let mut plan = ImportPlan::default();
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut barcodes: HashMap<String, String> = HashMap::new(); // 14-digit barcode -> food id
+    for food in existing.values() {
+        for code in food.barcodes() {
+            barcodes.insert(gtin14(code), food.id().to_string());
+        }
+    }
+    for food in foods {
+        if limit.is_some_and(|limit| plan.add.len() + plan.replace.len() >= limit) {
+            break;
+        }
+        let description = food.description.split_whitespace().collect::<Vec<_>>().join(" ");
+        let alternative = format!("{} (FDC {})", description, food.fdc_id);
+        let id = if taken.contains(&description) {
+            alternative
+        } else if existing.contains_key(&description) {
+            match on_conflict {
+                OnConflict::Skip => {
+                    plan.skipped_existing += 1;
+                    continue;
+                },
+                OnConflict::Replace => description,
+                OnConflict::Rename => {
+                    plan.renamed += 1;
+                    alternative
+                },
+            }
+        } else {
+            description
+        };
+        if taken.contains(&id) || (existing.contains_key(&id) && on_conflict != OnConflict::Replace) {
+            plan.skipped_existing += 1;
+            continue;
+        }
+
+        let Some(mut imported) = food.to_food(&id) else {
+            plan.skipped_no_energy += 1;
+            continue;
+        };
+        if let Food::Basic(basic) = &mut imported {
+            let before = basic.barcodes.len();
+            basic.barcodes.retain(|code| barcodes.get(&gtin14(code)).is_none_or(|owner| *owner == id));
+            plan.dropped_barcodes += before - basic.barcodes.len();
+            for code in &basic.barcodes {
+                barcodes.insert(gtin14(code), id.clone());
+            }
+        }
+        taken.insert(id.clone());
+        if existing.contains_key(&id) {
+            plan.replace.push(imported);
+        } else {
+            plan.add.push(imported);
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fdc_food(fdc_id: u64, description: &str, gtin: Option<&str>) -> FdcFood {
+        FdcFood {
+            fdc_id,
+            description: description.to_string(),
+            data_type: "branded_food".to_string(),
+            category: None,
+            gtin: gtin.map(|gtin| gtin.to_string()),
+            amounts: HashMap::from([(ENERGY_IDS[0], 100.0)]),
+        }
+    }
+
+    fn barcodes(food: &Food) -> Vec<String> {
+        food.barcodes().to_vec()
+    }
+
+    #[test]
+    fn leaves_off_barcodes_another_food_has() {
+        let cola = fdc_food(1, "Cola", Some("036000291452")).to_food("Cola").unwrap();
+        let existing = HashMap::from([("Cola".to_string(), cola)]);
+        let foods = [
+            fdc_food(2, "Cola  drink", Some("0036000291452")), // Same product as the existing Cola
+            fdc_food(3, "Crunchy bar", Some("4006381333931")),
+            fdc_food(4, "Crunchy bar", Some("4006381333931")), // Repeated in the download
+            fdc_food(5, "Cola", Some("036000291452")), // Replaces Cola, keeping its barcode
+        ];
+
+        let plan = plan_import(&foods, &existing, OnConflict::Replace, None);
+        assert_eq!(plan.dropped_barcodes, 2);
+        let ids: Vec<&str> = plan.add.iter().map(|food| food.id()).collect();
+        assert_eq!(ids, ["Cola drink", "Crunchy bar", "Crunchy bar (FDC 4)"]);
+        assert!(barcodes(&plan.add[0]).is_empty());
+        assert_eq!(barcodes(&plan.add[1]), ["4006381333931"]);
+        assert!(barcodes(&plan.add[2]).is_empty());
+        assert_eq!(barcodes(&plan.replace[0]), ["036000291452"]);
+    }
+
+    #[test]
+    fn skips_taken_names_and_foods_without_energy() {
+        let existing = HashMap::from([("Cola".to_string(), fdc_food(1, "Cola", None).to_food("Cola").unwrap())]);
+        let mut no_energy = fdc_food(2, "Water", None);
+        no_energy.amounts.clear();
+        let plan = plan_import(&[fdc_food(3, "Cola", None), no_energy], &existing, OnConflict::Skip, None);
+        assert!(plan.add.is_empty() && plan.replace.is_empty());
+        assert_eq!((plan.skipped_existing, plan.skipped_no_energy), (1, 1));
+    }
+}
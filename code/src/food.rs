@@ -1,11 +1,313 @@
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul};
+
+// Nutrient profile of one serving. Calories are in kcal, sodium in mg and everything else in grams.
+// Every field defaults to 0 so older databases that only stored calories still load.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct Nutrients {
+    pub calories: f32,
+    pub protein: f32,
+    pub carbs: f32,
+    pub fat: f32,
+    pub fiber: f32,
+    pub sugar: f32,
+    pub sodium: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Nutrient {
+    Calories,
+    Protein,
+    Carbs,
+    Fat,
+    Fiber,
+    Sugar,
+    Sodium,
+}
+
+impl Nutrient {
+    pub const ALL: [Nutrient; 7] = [
+        Nutrient::Calories,
+        Nutrient::Protein,
+        Nutrient::Carbs,
+        Nutrient::Fat,
+        Nutrient::Fiber,
+        Nutrient::Sugar,
+        Nutrient::Sodium,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Nutrient::Calories => "Calories",
+            Nutrient::Protein => "Protein",
+            Nutrient::Carbs => "Carbs",
+            Nutrient::Fat => "Fat",
+            Nutrient::Fiber => "Fiber",
+            Nutrient::Sugar => "Sugar",
+            Nutrient::Sodium => "Sodium",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Nutrient::Calories => "kcal",
+            Nutrient::Sodium => "mg",
+            _ => "g",
+        }
+    }
+
+    // Energy density used to turn a share of the calorie target into grams
+    pub fn kcal_per_gram(&self) -> Option<f32> {
+        match self {
+            Nutrient::Protein | Nutrient::Carbs => Some(4.0),
+            Nutrient::Fat => Some(9.0),
+            _ => None,
+        }
+    }
+}
+
+impl Nutrients {
+    pub fn get(&self, nutrient: Nutrient) -> f32 {
+        match nutrient {
+            Nutrient::Calories => self.calories,
+            Nutrient::Protein => self.protein,
+            Nutrient::Carbs => self.carbs,
+            Nutrient::Fat => self.fat,
+            Nutrient::Fiber => self.fiber,
+            Nutrient::Sugar => self.sugar,
+            Nutrient::Sodium => self.sodium,
+        }
+    }
+
+    pub fn set(&mut self, nutrient: Nutrient, value: f32) {
+        match nutrient {
+            Nutrient::Calories => self.calories = value,
+            Nutrient::Protein => self.protein = value,
+            Nutrient::Carbs => self.carbs = value,
+            Nutrient::Fat => self.fat = value,
+            Nutrient::Fiber => self.fiber = value,
+            Nutrient::Sugar => self.sugar = value,
+            Nutrient::Sodium => self.sodium = value,
+        }
+    }
+}
+
+impl Add for Nutrients {
+    type Output = Nutrients;
+
+    fn add(self, other: Nutrients) -> Nutrients {
+        Nutrients {
+            calories: self.calories + other.calories,
+            protein: self.protein + other.protein,
+            carbs: self.carbs + other.carbs,
+            fat: self.fat + other.fat,
+            fiber: self.fiber + other.fiber,
+            sugar: self.sugar + other.sugar,
+            sodium: self.sodium + other.sodium,
+        }
+    }
+}
+
+impl AddAssign for Nutrients {
+    fn add_assign(&mut self, other: Nutrients) {
+        *self = *self + other;
+    }
+}
+
+impl Mul<f32> for Nutrients {
+    type Output = Nutrients;
+
+    fn mul(self, factor: f32) -> Nutrients {
+        Nutrients {
+            calories: self.calories * factor,
+            protein: self.protein * factor,
+            carbs: self.carbs * factor,
+            fat: self.fat * factor,
+            fiber: self.fiber * factor,
+            sugar: self.sugar * factor,
+            sodium: self.sodium * factor,
+        }
+    }
+}
+
+impl Sum for Nutrients {
+    fn sum<I: Iterator<Item = Nutrients>>(iter: I) -> Nutrients {
+        iter.fold(Nutrients::default(), |acc, n| acc + n)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MeasureKind {
+    Mass,   // measured in grams
+    Volume, // measured in millilitres
+}
+
+// How much one serving weighs (or holds), plus food-specific household measures
+// such as "slice" or "cup" expressed in the same grams/millilitres.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServingSize {
+    pub amount: f32,
+    pub kind: MeasureKind,
+    #[serde(default)]
+    pub household_units: BTreeMap<String, f32>,
+}
+
+impl ServingSize {
+    // Parses a serving size such as "100 g", "1 cup" or "250ml" into grams or millilitres
+    pub fn parse(input: &str) -> Option<ServingSize> {
+        let quantity = Quantity::parse(input)?;
+        let (kind, factor) = standard_unit(&normalize_unit(&quantity.unit))?;
+        Some(ServingSize {
+            amount: quantity.amount * factor,
+            kind,
+            household_units: BTreeMap::new(),
+        })
+    }
+
+    pub fn base_unit(&self) -> &'static str {
+        match self.kind {
+            MeasureKind::Mass => "g",
+            MeasureKind::Volume => "ml",
+        }
+    }
+}
+
+// An amount the user ate or put into a recipe, e.g. "150 g", "1 cup" or "2 servings"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub amount: f32,
+    pub unit: String,
+}
+
+impl Quantity {
+    pub fn servings(amount: f32) -> Self {
+        Quantity {
+            amount,
+            unit: "serving".to_string(),
+        }
+    }
+
+    // Parses "150 g", "150g", "1.5 cups" or a bare number (servings)
+    pub fn parse(input: &str) -> Option<Quantity> {
+        let input = input.trim();
+        let split = input
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(input.len());
+        let amount: f32 = input[..split].parse().ok()?;
+        let unit = input[split..].trim();
+        if unit.is_empty() {
+            Some(Quantity::servings(amount))
+        } else {
+            Some(Quantity {
+                amount,
+                unit: unit.to_lowercase(),
+            })
+        }
+    }
+
+    pub fn is_servings(&self) -> bool {
+        matches!(normalize_unit(&self.unit).as_str(), "" | "serving")
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_servings() {
+            write!(f, "{:.1} serving(s)", self.amount)
+        } else {
+            write!(f, "{} {}", self.amount, self.unit)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitError {
+    UnknownUnit { food_id: String, unit: String },
+    NoServingSize { food_id: String, unit: String },
+    IncompatibleUnit { food_id: String, unit: String },
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnitError::UnknownUnit { food_id, unit } =>
+                write!(f, "'{}' is not a known unit for '{}'", unit, food_id),
+            UnitError::NoServingSize { food_id, unit } =>
+                write!(f, "'{}' has no serving size, so '{}' cannot be converted to servings", food_id, unit),
+            UnitError::IncompatibleUnit { food_id, unit } =>
+                write!(f, "'{}' cannot be measured in '{}'", food_id, unit),
+        }
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+// Lowercases a unit and strips plural endings so "Cups", "slices" and "tbsp." all match
+fn normalize_unit(unit: &str) -> String {
+    let unit = unit.trim().trim_end_matches('.').to_lowercase();
+    match unit.as_str() {
+        "servings" | "serving" => "serving".to_string(),
+        "g" | "gram" | "grams" | "gr" => "g".to_string(),
+        "kg" | "kilogram" | "kilograms" => "kg".to_string(),
+        "mg" | "milligram" | "milligrams" => "mg".to_string(),
+        "oz" | "ounce" | "ounces" => "oz".to_string(),
+        "lb" | "lbs" | "pound" | "pounds" => "lb".to_string(),
+        "ml" | "millilitre" | "millilitres" | "milliliter" | "milliliters" => "ml".to_string(),
+        "l" | "litre" | "litres" | "liter" | "liters" => "l".to_string(),
+        "tsp" | "teaspoon" | "teaspoons" => "tsp".to_string(),
+        "tbsp" | "tablespoon" | "tablespoons" => "tbsp".to_string(),
+        "cup" | "cups" => "cup".to_string(),
+        "fl oz" | "floz" | "fluid ounce" | "fluid ounces" => "fl oz".to_string(),
+        _ => {
+            if let Some(stem) = unit.strip_suffix("es").filter(|s| s.ends_with(['s', 'x', 'h'])) {
+                stem.to_string()
+            } else if let Some(stem) = unit.strip_suffix('s').filter(|s| !s.is_empty()) {
+                stem.to_string()
+            } else {
+                unit
+            }
+        }
+    }
+}
+
+// Whether a unit is "serving" or a standard mass or volume unit, in any spelling
+pub fn is_standard_unit(unit: &str) -> bool {
+    let unit = normalize_unit(unit);
+    unit == "serving" || standard_unit(&unit).is_some()
+}
+
+// Size of a standard unit in grams or millilitres
+fn standard_unit(unit: &str) -> Option<(MeasureKind, f32)> {
+    match unit {
+        "g" => Some((MeasureKind::Mass, 1.0)),
+        "kg" => Some((MeasureKind::Mass, 1000.0)),
+        "mg" => Some((MeasureKind::Mass, 0.001)),
+        "oz" => Some((MeasureKind::Mass, 28.3495)),
+        "lb" => Some((MeasureKind::Mass, 453.592)),
+        "ml" => Some((MeasureKind::Volume, 1.0)),
+        "l" => Some((MeasureKind::Volume, 1000.0)),
+        "tsp" => Some((MeasureKind::Volume, 5.0)),
+        "tbsp" => Some((MeasureKind::Volume, 15.0)),
+        "cup" => Some((MeasureKind::Volume, 240.0)),
+        "fl oz" => Some((MeasureKind::Volume, 29.5735)),
+        _ => None,
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BasicFood {
     pub id: String,
     pub keywords: Vec<String>,
-    pub calories: f32,
+    #[serde(flatten)]
+    pub nutrients: Nutrients,
+    #[serde(default)]
+    pub serving: Option<ServingSize>,
+    #[serde(default)]
+    pub barcodes: Vec<String>, // UPC/EAN codes of packaged products
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -13,6 +315,55 @@ pub struct CompositeFood {
     pub id: String,
     pub keywords: Vec<String>,
     pub components: Vec<(String, f32)>, // (component food id, servings)
+    #[serde(default)]
+    pub serving: Option<ServingSize>,
+    #[serde(default, rename = "yield")]
+    pub recipe_yield: Option<RecipeYield>,
+}
+
+impl CompositeFood {
+    // Share of the whole batch in one serving. Without a yield the batch is one serving;
+    // a total amount needs a serving size of the same kind to divide by.
+    pub fn portion(&self) -> Option<f32> {
+        match self.recipe_yield {
+            None => Some(1.0),
+            Some(RecipeYield::Servings(count)) => (count > 0.0).then(|| 1.0 / count),
+            Some(RecipeYield::Total { amount, kind }) => match &self.serving {
+                Some(serving) if serving.kind == kind && amount > 0.0 => Some(serving.amount / amount),
+                _ => None,
+            },
+        }
+    }
+}
+
+// How much a recipe makes, so that one serving is a portion rather than the whole pot
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipeYield {
+    Servings(f32),
+    Total { amount: f32, kind: MeasureKind }, // Cooked weight in grams or volume in millilitres
+}
+
+impl RecipeYield {
+    // Parses "6", "6 servings", "1.8 kg" or "2 l"
+    pub fn parse(input: &str) -> Option<RecipeYield> {
+        let quantity = Quantity::parse(input)?;
+        if quantity.is_servings() {
+            return Some(RecipeYield::Servings(quantity.amount));
+        }
+        let total = ServingSize::parse(input)?;
+        Some(RecipeYield::Total { amount: total.amount, kind: total.kind })
+    }
+}
+
+impl fmt::Display for RecipeYield {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecipeYield::Servings(count) => write!(f, "{} serving(s)", count),
+            RecipeYield::Total { amount, kind: MeasureKind::Mass } => write!(f, "{} g", amount),
+            RecipeYield::Total { amount, kind: MeasureKind::Volume } => write!(f, "{} ml", amount),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -22,18 +373,189 @@ pub enum Food {
     Composite(CompositeFood),
 }
 
-// Recursively computes the calories for a food item given the entire foods database.
-pub fn compute_calories(food: &Food, food_map: &HashMap<String, Food>) -> f32 {
+impl Food {
+    pub fn id(&self) -> &str {
+        match self {
+            Food::Basic(basic) => &basic.id,
+            Food::Composite(composite) => &composite.id,
+        }
+    }
+
+    pub fn serving(&self) -> Option<&ServingSize> {
+        match self {
+            Food::Basic(basic) => basic.serving.as_ref(),
+            Food::Composite(composite) => composite.serving.as_ref(),
+        }
+    }
+
+    // Only basic foods are packaged products with barcodes
+    pub fn barcodes(&self) -> &[String] {
+        match self {
+            Food::Basic(basic) => &basic.barcodes,
+            Food::Composite(_) => &[],
+        }
+    }
+
+    // Converts a quantity into a number of servings of this food. Food-specific household
+    // units win over the standard ones, so a food can define its own "cup".
+    pub fn servings_for(&self, quantity: &Quantity) -> Result<f32, UnitError> {
+        if quantity.is_servings() {
+            return Ok(quantity.amount);
+        }
+
+        let unit = normalize_unit(&quantity.unit);
+        let food_id = self.id().to_string();
+        let serving = match self.serving() {
+            Some(serving) if serving.amount > 0.0 => serving,
+            _ if standard_unit(&unit).is_some() => {
+                return Err(UnitError::NoServingSize { food_id, unit: quantity.unit.clone() });
+            },
+            _ => return Err(UnitError::UnknownUnit { food_id, unit: quantity.unit.clone() }),
+        };
+
+        let household = serving.household_units.iter()
+            .find(|(name, _)| normalize_unit(name) == unit)
+            .map(|(_, amount)| *amount);
+        if let Some(amount) = household {
+            return Ok(quantity.amount * amount / serving.amount);
+        }
+
+        match standard_unit(&unit) {
+            Some((kind, factor)) if kind == serving.kind => Ok(quantity.amount * factor / serving.amount),
+            Some(_) => Err(UnitError::IncompatibleUnit { food_id, unit: quantity.unit.clone() }),
+            None => Err(UnitError::UnknownUnit { food_id, unit: quantity.unit.clone() }),
+        }
+    }
+}
+
+// Words left out of keywords because every other description has them
+const STOP_WORDS: [&str; 10] = ["and", "or", "with", "of", "the", "in", "to", "for", "by", "from"];
+
+// Distinct words of a product description, e.g. "Apples, fuji, with skin, raw" -> apples, fuji, skin, raw
+pub fn keywords_from(description: &str) -> Vec<String> {
+    let mut keywords: Vec<String> = Vec::new();
+    for word in description.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        if word.len() >= 2 && !STOP_WORDS.contains(&word.as_str()) && !keywords.contains(&word) {
+            keywords.push(word);
+        }
+    }
+    keywords
+}
+
+// Recursively computes the nutrients of one serving of a food item given the entire foods database.
+// A composite's components make up the whole batch, which is divided by its yield. Missing
+// components count as nothing, and a component that leads back into a food already being
+// computed is skipped, so a bad database can't overflow the stack.
+pub fn compute_nutrients(food: &Food, food_map: &HashMap<String, Food>) -> Nutrients {
+    compute_nutrients_guarded(food, food_map, &mut Vec::new())
+}
+
+fn compute_nutrients_guarded<'a>(food: &'a Food, food_map: &'a HashMap<String, Food>, visiting: &mut Vec<&'a str>) -> Nutrients {
     match food {
-        Food::Basic(basic) => basic.calories,
+        Food::Basic(basic) => basic.nutrients,
         Food::Composite(composite) => {
-            composite.components.iter().fold(0.0, |sum, (id, servings)| {
-                if let Some(component_food) = food_map.get(id) {
-                    sum + compute_calories(component_food, food_map) * servings
-                } else {
-                    sum
+            visiting.push(&composite.id);
+            let total = composite.components.iter().fold(Nutrients::default(), |sum, (id, servings)| {
+                match food_map.get(id) {
+                    Some(component_food) if !visiting.contains(&id.as_str()) => {
+                        sum + compute_nutrients_guarded(component_food, food_map, visiting) * *servings
+                    },
+                    _ => sum,
                 }
-            })
+            });
+            visiting.pop();
+            total * composite.portion().unwrap_or(1.0)
         }
     }
 }
+
+// Recursively computes the calories for a food item given the entire foods database.
+pub fn compute_calories(food: &Food, food_map: &HashMap<String, Food>) -> f32 {
+    compute_nutrients(food, food_map).calories
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn food(serving: Option<&str>, household: &[(&str, f32)]) -> Food {
+        let serving = serving.map(|size| {
+            let mut serving = ServingSize::parse(size).unwrap();
+            serving.household_units = household.iter().map(|(name, amount)| (name.to_string(), *amount)).collect();
+            serving
+        });
+        Food::Basic(BasicFood {
+            id: "Oats".to_string(),
+            keywords: Vec::new(),
+            nutrients: Nutrients::default(),
+            serving,
+            barcodes: Vec::new(),
+        })
+    }
+
+    fn servings(food: &Food, quantity: &str) -> Result<f32, UnitError> {
+        food.servings_for(&Quantity::parse(quantity).unwrap())
+    }
+
+    fn batch(serving: Option<&str>, recipe_yield: Option<&str>) -> CompositeFood {
+        CompositeFood {
+            id: "Chili".to_string(),
+            keywords: Vec::new(),
+            components: Vec::new(),
+            serving: serving.and_then(ServingSize::parse),
+            recipe_yield: recipe_yield.and_then(RecipeYield::parse),
+        }
+    }
+
+    #[test]
+    fn normalizes_spellings_and_plurals() {
+        assert_eq!(normalize_unit("Grams"), "g");
+        assert_eq!(normalize_unit("tbsp."), "tbsp");
+        assert_eq!(normalize_unit("Cups"), "cup");
+        assert_eq!(normalize_unit("fluid ounces"), "fl oz");
+        assert_eq!(normalize_unit("slices"), "slice");
+        assert_eq!(normalize_unit("boxes"), "box");
+        assert_eq!(ServingSize::parse("1 cup").map(|s| (s.amount, s.kind)), Some((240.0, MeasureKind::Volume)));
+    }
+
+    #[test]
+    fn converts_grams_and_millilitres_to_servings() {
+        let oats = food(Some("100 g"), &[]);
+        assert_eq!(servings(&oats, "150 g"), Ok(1.5));
+        assert_eq!(servings(&oats, "0.5 kg"), Ok(5.0));
+        assert_eq!(servings(&oats, "2"), Ok(2.0));
+
+        let milk = food(Some("250 ml"), &[]);
+        assert_eq!(servings(&milk, "1 l"), Ok(4.0));
+        assert_eq!(servings(&milk, "1 cup"), Ok(0.96));
+    }
+
+    #[test]
+    fn household_units_win_over_standard_ones() {
+        let oats = food(Some("40 g"), &[("cup", 80.0), ("scoop", 20.0)]);
+        assert_eq!(servings(&oats, "1 cup"), Ok(2.0));
+        assert_eq!(servings(&oats, "3 scoops"), Ok(1.5));
+    }
+
+    #[test]
+    fn reports_units_that_cannot_be_converted() {
+        let oats = food(Some("40 g"), &[]);
+        assert!(matches!(servings(&oats, "1 cup"), Err(UnitError::IncompatibleUnit { .. })));
+        assert!(matches!(servings(&oats, "3 handfuls"), Err(UnitError::UnknownUnit { .. })));
+
+        let no_serving = food(None, &[]);
+        assert!(matches!(servings(&no_serving, "100 g"), Err(UnitError::NoServingSize { .. })));
+        assert!(matches!(servings(&no_serving, "3 handfuls"), Err(UnitError::UnknownUnit { .. })));
+    }
+
+    #[test]
+    fn portion_divides_the_batch_by_its_yield() {
+        assert_eq!(batch(None, None).portion(), Some(1.0));
+        assert_eq!(batch(None, Some("4 servings")).portion(), Some(0.25));
+        assert_eq!(batch(None, Some("0 servings")).portion(), None);
+        assert_eq!(batch(Some("400 g"), Some("1.6 kg")).portion(), Some(0.25));
+        assert_eq!(batch(Some("400 ml"), Some("1.6 kg")).portion(), None); // Volume serving, mass yield
+        assert_eq!(batch(None, Some("1.6 kg")).portion(), None);
+    }
+}
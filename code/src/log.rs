@@ -1,12 +1,83 @@
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use std::fs;
-use crate::food::{Food, compute_calories};
+use chrono::{Duration, NaiveDate};
+use crate::food::{Food, Nutrients, Quantity, compute_nutrients};
+use crate::storage::{self, StoreError};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogEntry {
+    #[serde(default)]
+    pub id: u64, // Unique across the whole log; 0 until the entry is added to it
+    #[serde(default)]
+    pub logged_at: Option<String>, // Local time the entry was recorded, "YYYY-MM-DD HH:MM:SS"
     pub food_id: String,
     pub servings: f32,
+    #[serde(default)]
+    pub quantity: Option<Quantity>, // What the user entered, e.g. "150 g", before conversion to servings
+    #[serde(default)]
+    pub snapshot: Option<Nutrients>, // Nutrients per serving as resolved when the entry was logged
+    #[serde(default)]
+    pub meal: Option<String>, // Meal slot name; entries from before meals existed have none
+    #[serde(default)]
+    pub rule: Option<u64>, // Schedule rule that generated the entry, if any
+}
+
+// Heading for entries that aren't filed under any meal
+pub const UNASSIGNED_MEAL: &str = "Unassigned";
+
+// The date `days` after (or, when negative, before) the given date
+pub fn offset_date(date: &str, days: i64) -> Option<String> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some((date + Duration::days(days)).format("%Y-%m-%d").to_string())
+}
+
+// Every date from `start` to `end` inclusive, or `None` if either date is invalid or the
+// range runs backwards
+pub fn date_range(start: &str, end: &str) -> Option<Vec<String>> {
+    let start = NaiveDate::parse_from_str(start, "%Y-%m-%d").ok()?;
+    let end = NaiveDate::parse_from_str(end, "%Y-%m-%d").ok()?;
+    if end < start {
+        return None;
+    }
+    Some(start.iter_days()
+        .take_while(|date| *date <= end)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .collect())
+}
+
+// How log entries are turned into nutrients for reports
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportMode {
+    Historical, // Use the values snapshotted when the food was logged
+    Current,    // Recompute from the food database as it is now
+}
+
+impl ReportMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReportMode::Historical => "historical",
+            ReportMode::Current => "current",
+        }
+    }
+}
+
+impl LogEntry {
+    // Time of day the entry was logged, e.g. "08:30", if known
+    pub fn logged_time(&self) -> Option<&str> {
+        self.logged_at.as_deref().and_then(|at| at.get(11..16))
+    }
+
+    // Nutrients for this entry. Each mode falls back to the other source when its own is
+    // missing, so entries of deleted foods still count in current mode.
+    pub fn nutrients(&self, foods: &HashMap<String, Food>, mode: ReportMode) -> Nutrients {
+        let current = || foods.get(&self.food_id).map(|food| compute_nutrients(food, foods));
+        let per_serving = match mode {
+            ReportMode::Historical => self.snapshot.or_else(current),
+            ReportMode::Current => current().or(self.snapshot),
+        };
+        per_serving.unwrap_or_default() * self.servings
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -14,63 +85,190 @@ pub struct DailyLog {
     // Maps dates (as strings) to a vector of consumed food entries
     logs: HashMap<String, Vec<LogEntry>>,
     file_path: String,
+    read_only: bool, // Set when the file failed to load, so it is never overwritten
+    backup_count: usize,
+    next_id: u64,
 }
 
 impl DailyLog {
-    pub fn new(file_path: &str) -> Self {
+    pub fn new(file_path: &str, backup_count: usize) -> Self {
         DailyLog {
             logs: HashMap::new(),
             file_path: file_path.to_string(),
+            read_only: false,
+            backup_count,
+            next_id: 1,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<(), StoreError> {
+        let path = self.file_path.clone();
+        self.load_from(&path)
+    }
+
+    // Loads the most recent backup instead; saving afterwards replaces the main file
+    pub fn load_backup(&mut self) -> Result<(), StoreError> {
+        match storage::latest_backup(&self.file_path) {
+            Some(backup) => self.load_from(&backup.path.to_string_lossy()),
+            None => Err(StoreError::NoBackup(self.file_path.clone())),
         }
     }
 
-    pub fn load(&mut self) {
-        if let Ok(data) = fs::read_to_string(&self.file_path) {
-            if let Ok(loaded_logs) = serde_json::from_str::<HashMap<String, Vec<LogEntry>>>(&data) {
-                self.logs = loaded_logs;
+    fn load_from(&mut self, path: &str) -> Result<(), StoreError> {
+        match storage::read_json::<HashMap<String, Vec<LogEntry>>>(path) {
+            Ok(loaded_logs) => {
+                self.logs = loaded_logs.unwrap_or_default();
+                self.read_only = false;
+                self.assign_missing_ids();
+                Ok(())
+            },
+            Err(e) => {
+                self.read_only = true;
+                Err(e)
             }
         }
     }
 
-    pub fn save(&self) {
-        if let Ok(data) = serde_json::to_string_pretty(&self.logs) {
-            let _ = fs::write(&self.file_path, data);
+    pub fn save(&self) -> Result<(), StoreError> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly(self.file_path.clone()));
         }
+        storage::write_json(&self.file_path, &self.logs, self.backup_count)
     }
 
-    pub fn add_food(&mut self, date: &str, food_id: &str, servings: f32) {
-        let entry = LogEntry {
-            food_id: food_id.to_string(),
-            servings,
-        };
-        
-        self.logs.entry(date.to_string())
-            .or_insert_with(Vec::new)
-            .push(entry);
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // Appends an entry to a day and returns its id. Entries without an id are given one.
+    pub fn add_entry(&mut self, date: &str, entry: LogEntry) -> u64 {
+        let index = self.logs.get(date).map_or(0, |entries| entries.len());
+        self.insert_entry(date, index, entry)
     }
     
-    pub fn remove_food(&mut self, date: &str, index: usize) -> bool {
-        if let Some(entries) = self.logs.get_mut(date) {
-            if index < entries.len() {
-                entries.remove(index);
-                return true;
+    // Puts an entry at a given position, or at the end if the day has fewer entries
+    pub fn insert_entry(&mut self, date: &str, index: usize, mut entry: LogEntry) -> u64 {
+        if entry.id == 0 {
+            entry.id = self.next_id;
+        }
+        self.next_id = self.next_id.max(entry.id + 1);
+        let id = entry.id;
+        let entries = self.logs.entry(date.to_string()).or_default();
+        entries.insert(index.min(entries.len()), entry);
+        id
+    }
+
+    // Takes an entry out of the log, returning its date and position so it can be put back
+    pub fn remove_entry(&mut self, id: u64) -> Option<(String, usize, LogEntry)> {
+        for (date, entries) in self.logs.iter_mut() {
+            if let Some(index) = entries.iter().position(|entry| entry.id == id) {
+                return Some((date.clone(), index, entries.remove(index)));
+            }
+        }
+        None
+    }
+
+    // Migration for logs written before entries had ids. Ids are handed out in date order
+    // so they follow the order the entries were most likely logged in.
+    fn assign_missing_ids(&mut self) {
+        self.next_id = self.logs.values().flatten().map(|entry| entry.id + 1).max().unwrap_or(1);
+        let mut dates: Vec<String> = self.logs.keys().cloned().collect();
+        dates.sort();
+        for date in dates {
+            for entry in self.logs.get_mut(&date).into_iter().flatten() {
+                if entry.id == 0 {
+                    entry.id = self.next_id;
+                    self.next_id += 1;
+                }
             }
         }
-        false
     }
     
-    pub fn get_total_calories(&self, date: &str, foods: &HashMap<String, Food>) -> f32 {
+    pub fn get_total_calories(&self, date: &str, foods: &HashMap<String, Food>, mode: ReportMode) -> f32 {
+        self.get_total_nutrients(date, foods, mode).calories
+    }
+
+    // Sums every nutrient over the entries logged on the given date
+    pub fn get_total_nutrients(&self, date: &str, foods: &HashMap<String, Food>, mode: ReportMode) -> Nutrients {
         if let Some(entries) = self.logs.get(date) {
-            let total = entries.iter().map(|entry| {
-                if let Some(food) = foods.get(&entry.food_id) {
-                    compute_calories(food, foods) * entry.servings
-                } else {
-                    0.0
-                }
-            }).sum();
-            return total;
+            return entries.iter().map(|entry| entry.nutrients(foods, mode)).sum();
+        }
+        Nutrients::default()
+    }
+
+    // Migration for logs written before entries carried snapshots: every entry without one is
+    // given the nutrition its food has today. Returns how many entries were filled in.
+    pub fn backfill_snapshots(&mut self, foods: &HashMap<String, Food>) -> usize {
+        let food_ids: Vec<String> = foods.keys().cloned().collect();
+        self.freeze_entries(&food_ids, foods).len()
+    }
+
+    // Ids of every entry that logs the given food
+    pub fn entries_for_food(&self, food_id: &str) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.logs.values()
+            .flatten()
+            .filter(|entry| entry.food_id == food_id)
+            .map(|entry| entry.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    // Ids of the entries a schedule rule generated on a date
+    pub fn entries_for_rule(&self, date: &str, rule_id: u64) -> Vec<u64> {
+        self.logs.get(date).into_iter().flatten()
+            .filter(|entry| entry.rule == Some(rule_id))
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    // Ids of every entry a schedule rule generated, on any date
+    pub fn entries_from_rule(&self, rule_id: u64) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.logs.values().flatten()
+            .filter(|entry| entry.rule == Some(rule_id))
+            .map(|entry| entry.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    // Swaps in a changed version of an entry, matched by id, and returns the old version
+    pub fn replace_entry(&mut self, entry: LogEntry) -> Option<LogEntry> {
+        let current = self.logs.values_mut().flatten().find(|current| current.id == entry.id)?;
+        Some(std::mem::replace(current, entry))
+    }
+
+    pub fn rename_entries(&mut self, ids: &[u64], food_id: &str) {
+        for entry in self.logs.values_mut().flatten().filter(|entry| ids.contains(&entry.id)) {
+            entry.food_id = food_id.to_string();
+        }
+    }
+
+    // Freezes the current nutrition of the given foods into their log entries so later edits or
+    // removals don't change history. Entries that are already frozen are left alone; the ids
+    // of the entries that were frozen are returned so the change can be undone.
+    pub fn freeze_entries(&mut self, food_ids: &[String], foods: &HashMap<String, Food>) -> Vec<u64> {
+        let mut frozen = Vec::new();
+        for entry in self.logs.values_mut().flatten() {
+            if entry.snapshot.is_some() || !food_ids.contains(&entry.food_id) {
+                continue;
+            }
+            if let Some(food) = foods.get(&entry.food_id) {
+                entry.snapshot = Some(compute_nutrients(food, foods));
+                frozen.push(entry.id);
+            }
+        }
+        frozen
+    }
+
+    pub fn unfreeze_entries(&mut self, ids: &[u64]) {
+        for entry in self.logs.values_mut().flatten().filter(|entry| ids.contains(&entry.id)) {
+            entry.snapshot = None;
         }
-        0.0
     }
     
     pub fn get_log_entries(&self, date: &str) -> Vec<&LogEntry> {
@@ -81,6 +279,39 @@ impl DailyLog {
         }
     }
     
+    // The entries of a day grouped by meal, in the order of `meal_names`. Meals that no
+    // longer exist come next in first-logged order, then entries without a meal. Empty
+    // groups are left out; positions within the day are kept for numbering.
+    pub fn entries_by_meal(&self, date: &str, meal_names: &[String]) -> Vec<(String, Vec<(usize, &LogEntry)>)> {
+        let mut groups: Vec<(String, Vec<(usize, &LogEntry)>)> = meal_names.iter()
+            .map(|name| (name.clone(), Vec::new()))
+            .collect();
+        let mut unassigned = Vec::new();
+        for (index, entry) in self.logs.get(date).into_iter().flatten().enumerate() {
+            let Some(meal) = &entry.meal else {
+                unassigned.push((index, entry));
+                continue;
+            };
+            match groups.iter_mut().find(|(name, _)| name.eq_ignore_ascii_case(meal)) {
+                Some((_, group)) => group.push((index, entry)),
+                None => groups.push((meal.clone(), vec![(index, entry)])),
+            }
+        }
+        groups.push((UNASSIGNED_MEAL.to_string(), unassigned));
+        groups.retain(|(_, group)| !group.is_empty());
+        groups
+    }
+
+    // Entries of a day, optionally only those filed under one meal
+    pub fn entries_for_meal(&self, date: &str, meal: Option<&str>) -> Vec<&LogEntry> {
+        self.logs.get(date).into_iter().flatten()
+            .filter(|entry| match meal {
+                Some(meal) => entry.meal.as_deref().is_some_and(|m| m.eq_ignore_ascii_case(meal)),
+                None => true,
+            })
+            .collect()
+    }
+
     pub fn has_entries_for_date(&self, date: &str) -> bool {
         self.logs.contains_key(date) && !self.logs.get(date).unwrap().is_empty()
     }
@@ -3,41 +3,284 @@ mod database;
 mod log;
 mod command;
 mod profile;
+mod cli;
+mod config;
+mod storage;
+mod schedule;
+mod report;
+mod chart;
+mod weight;
+mod csv;
+mod fdc;
+mod barcode;
+mod off;
+mod recipe;
+mod search;
 
+use std::env;
 use std::io::{self, Write};
 use database::Database;
-use food::{Food, BasicFood, CompositeFood, compute_calories};
-use log::{DailyLog, LogEntry};
-use chrono::{Local, NaiveDate, Duration};
-use command::{CommandManager, UndoableCommand, AddFoodCommand, LogFoodCommand, RemoveLogEntryCommand};
-use profile::{Profile, Gender, ActivityLevel, TargetFormula};
+use food::{Food, BasicFood, CompositeFood, Nutrient, Nutrients, Quantity, RecipeYield, ServingSize, compute_calories, compute_nutrients};
+use log::{DailyLog, LogEntry, ReportMode, date_range, offset_date};
+use chrono::{Local, NaiveDate, Duration, Timelike};
+use command::{CommandContext, CommandManager, CommandRecord, DataFile, HistoryError, CopyEntriesCommand, ImportFoodsCommand, RecordWeightCommand, UndoableCommand, UpdateProfileCommand, SetDailyTargetCommand, SetMacroTargetsCommand, AddFoodCommand, EditFoodCommand, RenameFoodCommand, RemoveFoodCommand, LogFoodCommand, RemoveLogEntryCommand, EditLogEntryCommand, AddScheduleRuleCommand, RemoveScheduleRuleCommand, SkipScheduledCommand};
+use profile::{Profile, ProfileSetting, Meal, default_meals, Gender, ActivityLevel, TargetFormula, MacroTarget, MacroTargets, TargetStatus};
+use config::Config;
+use storage::{Backup, StoreError};
+use schedule::{Schedule, ScheduleRule, parse_days};
+use report::{RangeReport, WeightReport, DEFAULT_TOP_FOODS, WEIGHT_REPORT_DAYS, week_of, month_of};
+use chart::{DEFAULT_CHART_DAYS, MAX_CHART_DAYS, bar_chart, calorie_series, line_chart, weight_chart};
+use weight::{WeightEntry, WeightSource, parse_measurements};
+use fdc::{Filter, OnConflict, plan_import, read_foods};
+use barcode::parse_barcode;
+use recipe::{IngredientMatch, match_ingredient, parse_recipe};
+use search::MatchMode;
 use colored::*;
 
+// Longest date range a batch copy may fill, to catch typos such as a wrong year
+const MAX_COPY_DAYS: usize = 366;
+
 struct AppState {
     current_date: String,
     db: Database,
     daily_log: DailyLog,
     command_manager: CommandManager,
     profile: Profile,
+    schedule: Schedule,
+    report_mode: ReportMode,
+    config: Config,
+}
+
+impl AppState {
+    // Runs a command against the app's data and records it in the undo history
+    fn execute_command(&mut self, command: impl Into<CommandRecord>) -> bool {
+        let command = command.into();
+        let files = command.data_files();
+        let mut ctx = CommandContext {
+            db: &mut self.db,
+            log: &mut self.daily_log,
+            profile: &mut self.profile,
+            schedule: &mut self.schedule,
+        };
+        let success = self.command_manager.execute_command(command, &mut ctx);
+        if success {
+            self.save_changes(files);
+        }
+        success
+    }
+
+    fn undo(&mut self) -> Result<String, HistoryError> {
+        let files = self.command_manager.next_undo().map(|command| command.data_files()).unwrap_or_default();
+        let mut ctx = CommandContext {
+            db: &mut self.db,
+            log: &mut self.daily_log,
+            profile: &mut self.profile,
+            schedule: &mut self.schedule,
+        };
+        let action = self.command_manager.undo_last_command(&mut ctx)?;
+        self.save_changes(files);
+        Ok(action)
+    }
+
+    fn redo(&mut self) -> Result<String, HistoryError> {
+        let files = self.command_manager.next_redo().map(|command| command.data_files()).unwrap_or_default();
+        let mut ctx = CommandContext {
+            db: &mut self.db,
+            log: &mut self.daily_log,
+            profile: &mut self.profile,
+            schedule: &mut self.schedule,
+        };
+        let action = self.command_manager.redo_last_command(&mut ctx)?;
+        self.save_changes(files);
+        Ok(action)
+    }
+
+    // Saves the files a command changed, then the journal. The journal is only written once
+    // the data is, so it never holds changes the data files don't have.
+    fn save_changes(&self, files: &[DataFile]) {
+        let mut saved = true;
+        for file in files {
+            saved &= report_save(match file {
+                DataFile::Foods => self.db.save(),
+                DataFile::Log => self.daily_log.save(),
+                DataFile::Profile => self.profile.save(),
+                DataFile::Schedule => self.schedule.save(),
+            });
+        }
+        if saved {
+            report_save(self.command_manager.save());
+        }
+    }
+
+    // Logs the scheduled entries due on the given dates that haven't been generated yet and
+    // saves the log and schedule if anything was added. Returns how many entries were added.
+    fn apply_schedule(&mut self, dates: &[String]) -> usize {
+        // Never generate into a file that can't be saved; the entries would be generated again
+        if self.daily_log.is_read_only() || self.schedule.is_read_only() {
+            return 0;
+        }
+        let applied = self.schedule.apply(dates, &mut self.daily_log, &self.db.foods);
+        for failure in &applied.failures {
+            eprintln!("{} {}", "⚠️".yellow(), failure);
+        }
+        if applied.added > 0 {
+            report_save(self.daily_log.save());
+            report_save(self.schedule.save());
+        }
+        applied.added
+    }
+
+    // The food with a barcode. Unknown barcodes are looked up in the Open Food Facts export,
+    // and a product found there is added to the database. Returns the food's id and whether
+    // it was added.
+    fn food_for_barcode(&mut self, input: &str) -> Result<(String, bool), String> {
+        let code = parse_barcode(input).map_err(|e| e.to_string())?;
+        if let Some(food) = self.db.find_by_barcode(&code) {
+            return Ok((food.id().to_string(), false));
+        }
+        let path = self.config.open_food_facts_path().ok_or_else(|| format!(
+            "no food has barcode {}; set open_food_facts in the config file to look barcodes up in an Open Food Facts export", code))?;
+        let product = off::find_product(&path, &code)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("barcode {} is not in the food database or in {}", code, path.display()))?;
+
+        // Two products may share a name, so a clash gets the barcode appended
+        let mut id = product.food_id();
+        if self.db.foods.contains_key(&id) || self.db.is_quarantined(&id) {
+            id = format!("{} ({})", id, product.code);
+        }
+        let food = product.to_food(&id)
+            .ok_or_else(|| format!("'{}' ({}) has no energy value in {}", id, code, path.display()))?;
+        let errors = self.db.validate_food(&food);
+        if !errors.is_empty() {
+            return Err(errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("; "));
+        }
+        if !self.execute_command(AddFoodCommand::new(food)) {
+            return Err(format!("failed to add '{}'", id));
+        }
+        Ok((id, true))
+    }
+
+    // Backups of every data file, newest first
+    fn backups(&self) -> Vec<Backup> {
+        let mut backups: Vec<Backup> = [&self.db.file_path, self.daily_log.file_path(), self.profile.file_path(), self.schedule.file_path()]
+            .iter()
+            .flat_map(|path| storage::list_backups(path))
+            .collect();
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.created));
+        backups
+    }
+
+    // Rolls one data file back to a backup and reloads it. Undo history is dropped, since
+    // the recorded commands may refer to data that no longer exists.
+    fn restore_backup(&mut self, backup: &Backup) -> Result<(), StoreError> {
+        storage::restore_backup(backup, self.config.settings.backup_count)?;
+        if backup.original == self.db.file_path {
+            self.db.load()?;
+        } else if backup.original == self.daily_log.file_path() {
+            self.daily_log.load()?;
+        } else if backup.original == self.profile.file_path() {
+            self.profile.load()?;
+        } else if backup.original == self.schedule.file_path() {
+            self.schedule.load()?;
+        }
+        self.command_manager.clear();
+        self.command_manager.save()
+    }
+}
+
+// Short description of a backup for listings, e.g. "log.json from 2025-04-08 09:30:15"
+fn describe_backup(backup: &Backup) -> String {
+    let file = std::path::Path::new(&backup.original).file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| backup.original.clone());
+    format!("{} from {}", file, backup.created.format("%Y-%m-%d %H:%M:%S"))
 }
 
 fn main() {
-    let db_file = "food_db.json";
-    let log_file = "log.json";
-    let profile_file = "profile.json";
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let config = match Config::resolve(&mut args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{} {}", "❌".red(), e);
+            std::process::exit(1);
+        }
+    };
+    match config.prepare_data_dir() {
+        Ok(copied) if !copied.is_empty() => {
+            eprintln!("ℹ️ Copied {} from the current directory into {}", copied.join(", "), config.data_dir.display());
+        },
+        Ok(_) => {},
+        Err(e) => {
+            eprintln!("{} {}", "❌".red(), e);
+            std::process::exit(1);
+        }
+    }
     
     let mut state = AppState {
         current_date: Local::now().naive_local().date().format("%Y-%m-%d").to_string(),
-        db: Database::new(db_file),
-        daily_log: DailyLog::new(log_file),
-        command_manager: CommandManager::new(),
-        profile: Profile::new(profile_file),
+        db: Database::new(&config.food_db_path(), config.settings.backup_count),
+        daily_log: DailyLog::new(&config.log_path(), config.settings.backup_count),
+        command_manager: CommandManager::new(&config.history_path(), config.settings.history_limit),
+        profile: Profile::new(&config.profile_path(), config.settings.backup_count),
+        schedule: Schedule::new(&config.schedule_path(), config.settings.backup_count),
+        report_mode: config.settings.report_mode,
+        config,
     };
     
-    state.db.load();
-    state.daily_log.load();
-    state.profile.load();
+    // Startup notices go to stderr so they don't mix with subcommand output such as --json.
+    // A file that fails to load is left untouched; the menu offers to open its backup instead.
+    let interactive = args.is_empty();
+    let mut db_result = state.db.load();
+    if let Err(e) = &db_result {
+        if offer_backup(&state.db.file_path, e, interactive) {
+            db_result = state.db.load_backup();
+            report_backup_result(&db_result);
+        }
+    }
+    if let Ok(errors) = db_result {
+        if !errors.is_empty() {
+            eprintln!("{}", "⚠️ Problems found in the food database:".yellow().bold());
+            for error in errors {
+                eprintln!("  - {}", error);
+            }
+            let mut ids: Vec<&str> = state.db.quarantined.iter().map(|food| food.id()).collect();
+            ids.sort();
+            eprintln!("{} {}", "⚠️ Set aside until the file is fixed:".yellow(), ids.join(", "));
+        }
+    }
+    if let Err(e) = state.daily_log.load() {
+        if offer_backup(state.daily_log.file_path(), &e, interactive) {
+            report_backup_result(&state.daily_log.load_backup());
+        }
+    }
+    let backfilled = state.daily_log.backfill_snapshots(&state.db.foods);
+    if backfilled > 0 {
+        eprintln!("ℹ️ Recorded nutrition snapshots for {} existing log entr(ies).", backfilled);
+        report_save(state.daily_log.save());
+    }
+    if let Err(e) = state.profile.load() {
+        if offer_backup(state.profile.file_path(), &e, interactive) {
+            report_backup_result(&state.profile.load_backup());
+        }
+    }
+    if let Err(e) = state.schedule.load() {
+        if offer_backup(state.schedule.file_path(), &e, interactive) {
+            report_backup_result(&state.schedule.load_backup());
+        }
+    }
+    if let Err(e) = state.command_manager.load() {
+        eprintln!("{} {}", "⚠️ Undo history could not be restored and starts empty:".yellow(), e);
+    }
+    
+    // Any remaining arguments select a non-interactive subcommand; otherwise run the menu
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args, &mut state));
+    }
+    
     println!("{}", "-------------- Diet Manager (YADA) 🧑‍⚕️🥡🏋️‍♂️ --------------".bold().underline().blue());
+    println!("📂 {}: {}", "Data Directory".bold(), state.config.data_dir.display());
+    open_date(&mut state);
     
     loop {
         println!("{}", "--------------------------------------------------------------------------------------------".bright_black());
@@ -47,22 +290,34 @@ fn main() {
         println!("\n{}", "Food Database:".bold().bright_magenta());
         println!("  {} Add Basic Food    {} Add Composite Food    {} List Foods    {} Search Foods",
                  "1".bold().bright_green(), "2".bold().bright_green(), "3".bold().bright_green(), "4".bold().bright_green());
+        println!("  {} Edit Food        {} Rename Food           {} Remove Food    {} Import Foods (USDA)",
+                 "17".bold().bright_green(), "18".bold().bright_green(), "19".bold().bright_green(), "30".bold().bright_green());
         println!("\n{} Daily Log ({}):", "Daily Log:".bold().bright_magenta(), state.current_date);
-        println!("  {} View Log          {} Log Food              {} Remove Log Entry",
-                 "5".bold().bright_green(), "6".bold().bright_green(), "7".bold().bright_green());
+        println!("  {} View Log          {} Log Food              {} Remove Log Entry   {} Edit Log Entry",
+                 "5".bold().bright_green(), "6".bold().bright_green(), "7".bold().bright_green(), "31".bold().bright_green());
+        println!("  {} Copy Entries     {} Repeat Yesterday      {} Scheduled Entries",
+                 "24".bold().bright_green(), "25".bold().bright_green(), "26".bold().bright_green());
         println!("\n{} Profile & Targets:", "Profile & Targets:".bold().bright_magenta());
         println!("  {} Edit Profile      {} Set Daily Target      {} View Daily Summary",
                  "8".bold().bright_green(), "9".bold().bright_green(), "10".bold().bright_green());
+        println!("  {} Nutrition Report  {} Calorie Chart         {} Weight Log",
+                 "27".bold().bright_green(), "28".bold().bright_green(), "29".bold().bright_green());
         println!("\n{} Date Navigation:", "Date Navigation:".bold().bright_magenta());
         println!("  {} Select Date       {} Previous Day          {} Next Day",
                  "11".bold().bright_green(), "12".bold().bright_green(), "13".bold().bright_green());
         println!("\n{} System:", "System:".bold().bright_magenta());
         println!("  {} Save              {} Exit                  {} Undo Last Action",
                  "14".bold().bright_green(), "15".bold().bright_green(), "16".bold().bright_green());
+        println!("  {} Redo              {} View History          {} Toggle Report Mode ({})",
+                 "20".bold().bright_green(), "21".bold().bright_green(), "22".bold().bright_green(), state.report_mode.name());
+        println!("  {} Restore Backup", "23".bold().bright_green());
         
         if state.command_manager.has_commands() {
             println!("{}", "↩️ Undo available. You can revert the last action.".italic().bright_green());
         }
+        if state.command_manager.can_redo() {
+            println!("{}", "↪️ Redo available. You can reapply the last undone action.".italic().bright_green());
+        }
         
         print!("\n{}: ", "Enter Choice".bold().bright_yellow());
         io::stdout().flush().unwrap();
@@ -89,6 +344,7 @@ fn main() {
                     let prev_day = date - Duration::days(1);
                     state.current_date = prev_day.format("%Y-%m-%d").to_string();
                     println!("📆 {}: {}", "Date Changed To".bold(), state.current_date.bright_cyan());
+                    open_date(&mut state);
                 }
             },
             13 => {
@@ -96,30 +352,66 @@ fn main() {
                     let next_day = date + Duration::days(1);
                     state.current_date = next_day.format("%Y-%m-%d").to_string();
                     println!("📆 {}: {}", "Date Changed To".bold(), state.current_date.bright_cyan());
+                    open_date(&mut state);
                 }
             },
             14 => {
-                state.db.save();
-                state.daily_log.save();
-                state.profile.save();
-                println!("{}", "💾 All data saved! ✅".green().bold());
+                if save_all(&state) {
+                    println!("{}", "💾 All data saved! ✅".green().bold());
+                }
             },
             15 => {
-                state.db.save();
-                state.daily_log.save();
-                state.profile.save();
-                println!("{}", "📁 Database and logs saved. Exiting. 👋".cyan());
+                if save_all(&state) {
+                    println!("{}", "📁 Database and logs saved. Exiting. 👋".cyan());
+                } else {
+                    println!("{}", "⚠️ Some data could not be saved. Exiting. 👋".yellow());
+                }
                 break;
             },
             16 => {
-                if let Some(action) = state.command_manager.undo_last_command() {
-                    println!("{} {}", "✅ Undid Action:".green().bold(), action);
-                    state.db.save();
-                    state.daily_log.save();
-                } else {
-                    println!("{}", "❌ Nothing to undo.".red().bold());
+                match state.undo() {
+                    Ok(action) => {
+                        println!("{} {}", "✅ Undid Action:".green().bold(), action);
+                    },
+                    Err(e) => println!("{} {}", "❌".red().bold(), e.to_string().red()),
+                }
+            },
+            17 => edit_food(&mut state),
+            18 => rename_food(&mut state),
+            19 => remove_food(&mut state),
+            20 => {
+                match state.redo() {
+                    Ok(action) => {
+                        println!("{} {}", "✅ Redid Action:".green().bold(), action);
+                    },
+                    Err(e) => println!("{} {}", "❌".red().bold(), e.to_string().red()),
+                }
+            },
+            21 => view_history(&state),
+            22 => {
+                state.report_mode = match state.report_mode {
+                    ReportMode::Historical => ReportMode::Current,
+                    ReportMode::Current => ReportMode::Historical,
+                };
+                match state.report_mode {
+                    ReportMode::Historical => println!("📜 Reports now use nutrition as it was when each food was logged."),
+                    ReportMode::Current => println!("🔄 Reports now recompute nutrition from the current food database."),
+                }
+            },
+            23 => restore_backup(&mut state),
+            24 => copy_entries(&mut state),
+            25 => {
+                if let Some(yesterday) = offset_date(&state.current_date, -1) {
+                    let command = CopyEntriesCommand::new(&yesterday, None, vec![state.current_date.clone()]);
+                    run_copy(&mut state, command);
                 }
             },
+            26 => manage_schedule(&mut state),
+            27 => view_range_report(&state),
+            28 => view_calorie_chart(&state),
+            29 => manage_weight(&mut state),
+            30 => import_foods(&mut state),
+            31 => edit_log_entry(&mut state),
             _ => {
                 println!("{}", "❌ Unknown Command. Please enter a valid option.".red().bold());
             }
@@ -130,12 +422,175 @@ fn main() {
     }
 }
 
+// Reports a file that failed to load and, in the interactive menu, asks whether to open its
+// backup instead. Returns true when the backup should be loaded.
+fn offer_backup(path: &str, error: &StoreError, interactive: bool) -> bool {
+    eprintln!("{} {}", "❌ Failed to load:".red().bold(), error);
+    let Some(backup) = storage::latest_backup(path) else {
+        eprintln!("   Changes to this file will not be saved until it is fixed.");
+        return false;
+    };
+    if !interactive {
+        eprintln!("   A backup from {} exists; run 'diet_manager restore' to roll back to it.",
+                  backup.created.format("%Y-%m-%d %H:%M:%S"));
+        return false;
+    }
+
+    print!("💾 A backup from {} exists. Load it instead? (y/n): ", backup.created.format("%Y-%m-%d %H:%M:%S"));
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).unwrap();
+    if answer.trim().eq_ignore_ascii_case("y") {
+        true
+    } else {
+        eprintln!("   Changes to this file will not be saved until it is fixed.");
+        false
+    }
+}
+
+fn report_backup_result<T>(result: &Result<T, StoreError>) {
+    match result {
+        Ok(_) => println!("{}", "✅ Backup loaded. Saving will replace the damaged file.".green()),
+        Err(e) => eprintln!("{} {}", "❌ The backup could not be loaded either:".red().bold(), e),
+    }
+}
+
+// Prints a failed save instead of dropping it. Returns true when the save succeeded.
+fn report_save(result: Result<(), StoreError>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{} {}", "❌ Save failed:".red().bold(), e);
+            false
+        }
+    }
+}
+
+fn save_all(state: &AppState) -> bool {
+    let db_saved = report_save(state.db.save());
+    let log_saved = report_save(state.daily_log.save());
+    let profile_saved = report_save(state.profile.save());
+    let schedule_saved = report_save(state.schedule.save());
+    db_saved && log_saved && profile_saved && schedule_saved
+}
+
+fn view_history(state: &AppState) {
+    let done = state.command_manager.command_history();
+    let undone = state.command_manager.redo_history();
+    if done.is_empty() && undone.is_empty() {
+        println!("{}", "📜 No actions yet this session.".bright_black());
+        return;
+    }
+
+    if !done.is_empty() {
+        println!("{}", "📜 Actions this session (oldest first):".bold().bright_cyan());
+        for (i, action) in done.iter().enumerate() {
+            println!("{}. {}", i + 1, action);
+        }
+    }
+    if !undone.is_empty() {
+        println!("{}", "↪️ Undone, can be redone (next first):".bold().bright_cyan());
+        for action in &undone {
+            println!("   {}", action.bright_black());
+        }
+    }
+}
+
+fn restore_backup(state: &mut AppState) {
+    let backups = state.backups();
+    if backups.is_empty() {
+        println!("{}", "❌ No backups yet. One is kept each time a data file changes.".red().bold());
+        return;
+    }
+
+    println!("{}", "💾 Backups (newest first):".bold().bright_cyan());
+    for (i, backup) in backups.iter().enumerate() {
+        println!("{}. {}", i + 1, describe_backup(backup));
+    }
+    print!("Enter the number of the backup to restore (or press Enter to cancel): ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let backup = match input.trim().parse::<usize>() {
+        Ok(n) if n >= 1 && n <= backups.len() => &backups[n - 1],
+        _ => {
+            println!("Restore cancelled.");
+            return;
+        }
+    };
+
+    print!("Replace the current file with {}? Undo history will be cleared. (y/n): ", describe_backup(backup));
+    io::stdout().flush().unwrap();
+    let mut confirm = String::new();
+    io::stdin().read_line(&mut confirm).unwrap();
+    if !confirm.trim().eq_ignore_ascii_case("y") {
+        println!("Restore cancelled.");
+        return;
+    }
+
+    match state.restore_backup(backup) {
+        Ok(()) => println!("{} {}", "✅ Restored".green().bold(), describe_backup(backup)),
+        Err(e) => println!("{} {}", "❌ Restore failed:".red().bold(), e),
+    }
+}
+
+// Seeds the database from a FoodData Central download on disk
+fn import_foods(state: &mut AppState) {
+    let path = prompt_line("Path to the FoodData Central CSV folder or JSON file: 📂 ");
+    if path.is_empty() {
+        return;
+    }
+    let split = |input: String| -> Vec<String> {
+        input.split(',').map(|part| part.trim().to_string()).filter(|part| !part.is_empty()).collect()
+    };
+    let filter = Filter {
+        data_types: split(prompt_line("Data types, e.g. foundation, sr legacy (blank for all): ")),
+        categories: split(prompt_line("Categories, e.g. fruits, vegetables (blank for all): ")),
+    };
+    let on_conflict = match prompt_line("When a name is already taken: 1. Skip  2. Replace  3. Rename (blank to skip): ").as_str() {
+        "2" => OnConflict::Replace,
+        "3" => OnConflict::Rename,
+        _ => OnConflict::Skip,
+    };
+
+    println!("⏳ Reading {}...", path);
+    let foods = match read_foods(std::path::Path::new(&path), &filter) {
+        Ok(foods) => foods,
+        Err(e) => {
+            println!("{} {}", "❌".red(), e);
+            return;
+        }
+    };
+    let plan = plan_import(&foods, &state.db.foods, on_conflict, None);
+    println!("Found {} matching food(s): {}.", foods.len(), plan.summary());
+    if plan.add.is_empty() && plan.replace.is_empty() {
+        println!("Nothing to import.");
+        return;
+    }
+    if !prompt_line("Import them? (y/n): ").eq_ignore_ascii_case("y") {
+        println!("Import cancelled.");
+        return;
+    }
+
+    let command = ImportFoodsCommand::new(&path, plan.add, plan.replace);
+    let description = command.description();
+    if state.execute_command(command) {
+        println!("{} {}", "✅".green(), description);
+    } else {
+        println!("{}", "❌ Import failed; nothing was changed.".red());
+    }
+}
+
 fn add_basic_food(state: &mut AppState) {
     let mut id = String::new();
     print!("{}", "Enter basic food identifier: 🥗 ".bright_yellow());
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut id).unwrap();
     id = id.trim().to_string();
+    if state.db.foods.contains_key(&id) {
+        println!("{}", format!("❌ A food named '{}' already exists. Use Edit Food to change it.", id).red().bold());
+        return;
+    }
     
     let mut keywords = String::new();
     print!("{}", "Enter keywords (comma separated): 🔍 ".magenta());
@@ -158,17 +613,38 @@ fn add_basic_food(state: &mut AppState) {
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut cal_str).unwrap();
     
-    let calories: f32 = cal_str.trim().parse().unwrap_or(0.0);
+    let mut nutrients = Nutrients {
+        calories: cal_str.trim().parse().unwrap_or(0.0),
+        ..Nutrients::default()
+    };
+    
+    // Remaining nutrients are optional; leaving the prompt blank records 0
+    for nutrient in Nutrient::ALL.iter().skip(1) {
+        let mut value_str = String::new();
+        print!("Enter {} per serving ({}, blank for 0): ", nutrient.name().to_lowercase(), nutrient.unit());
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut value_str).unwrap();
+        nutrients.set(*nutrient, value_str.trim().parse().unwrap_or(0.0));
+    }
+    
+    let serving = prompt_serving_size();
+    let barcodes = prompt_barcodes().unwrap_or_default();
+    
     let basic = BasicFood {
         id: id.clone(),
         keywords: keywords_vec,
-        calories,
+        nutrients,
+        serving,
+        barcodes,
     };
     
     let food = Food::Basic(basic);
-    let command = Box::new(AddFoodCommand::new(food, &mut state.db));
+    if !report_invalid_food(&state.db, &food) {
+        return;
+    }
+    let command = AddFoodCommand::new(food);
     
-    if state.command_manager.execute_command(command) {
+    if state.execute_command(command) {
         println!("{}", "✅ Composite food added successfully!".green().bold());
     } else {
         println!("{}", "❌ Failed to add food. Please try again.".red().bold());
@@ -181,6 +657,10 @@ fn add_composite_food(state: &mut AppState) {
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut id).unwrap();
     id = id.trim().to_string();
+    if state.db.foods.contains_key(&id) {
+        println!("{}", format!("❌ A food named '{}' already exists. Use Edit Food to change it.", id).red().bold());
+        return;
+    }
     
     let mut keywords = String::new();
     print!("{}", "Enter keywords (comma separated): 🔍 ".magenta());
@@ -197,6 +677,44 @@ fn add_composite_food(state: &mut AppState) {
         keywords_vec.push(id.clone());
     }
 
+    println!("How do you want to enter the components?");
+    println!("1. One at a time");
+    println!("2. From a recipe's ingredient list");
+    let components = match prompt_line("Enter choice (blank for 1): ").as_str() {
+        "2" => match prompt_recipe_components(&state.db) {
+            Some(components) => components,
+            None => return,
+        },
+        _ => prompt_components(&state.db),
+    };
+    let recipe_yield = prompt_yield();
+    if matches!(recipe_yield, Some(RecipeYield::Total { .. })) {
+        println!("A serving size says how much of the batch one serving is.");
+    }
+    let serving = prompt_serving_size();
+    
+    let composite = CompositeFood {
+        id: id.clone(),
+        keywords: keywords_vec,
+        components,
+        serving,
+        recipe_yield,
+    };
+    
+    let food = Food::Composite(composite);
+    if !report_invalid_food(&state.db, &food) {
+        return;
+    }
+    let command = AddFoodCommand::new(food);
+    
+    if state.execute_command(command) {
+        println!("{}", "✅ Composite food added successfully!".green().bold());
+    } else {
+        println!("{}", "❌ Failed to add food. Please try again.".red().bold());
+    }
+}
+
+fn prompt_components(db: &Database) -> Vec<(String, f32)> {
     let mut components = Vec::new();
     print!("{}", "Enter number of components: 🧩 ".bright_yellow());
     io::stdout().flush().unwrap();
@@ -205,35 +723,235 @@ fn add_composite_food(state: &mut AppState) {
     let num: usize = num_str.trim().parse().unwrap_or(0);
     
     for _ in 0..num {
-        let comp_id = select_food_component(&state.db);
+        let comp_id = select_food_component(db);
         
         if comp_id.is_empty() {
             println!("Component selection cancelled.");
             continue;
         }
         
-        let mut servings_str = String::new();
-        print!("Enter number of servings: ");
-        io::stdout().flush().unwrap();
-        io::stdin().read_line(&mut servings_str).unwrap();
-        let servings: f32 = servings_str.trim().parse().unwrap_or(1.0);
+        let comp_food = &db.foods[&comp_id];
+        let servings = loop {
+            let quantity = prompt_quantity(comp_food);
+            match comp_food.servings_for(&quantity) {
+                Ok(servings) => break servings,
+                Err(e) => println!("{} {}", "❌".red(), e),
+            }
+        };
         
         components.push((comp_id, servings));
     }
-    
-    let composite = CompositeFood {
-        id: id.clone(),
-        keywords: keywords_vec,
-        components,
+    components
+}
+
+// Reads an ingredient list, pasted or from a file, and matches each line to a food. Lines
+// without a clear match, or whose amount doesn't convert, are reviewed one by one. Returns
+// `None` if the user gives up.
+fn prompt_recipe_components(db: &Database) -> Option<Vec<(String, f32)>> {
+    println!("Paste the ingredients and finish with an empty line, or enter the path to a text file:");
+    let first = prompt_line("📋 ");
+    let text = if !first.is_empty() && std::path::Path::new(&first).is_file() {
+        match std::fs::read_to_string(&first) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("{} cannot read {}: {}", "❌".red(), first, e);
+                return None;
+            }
+        }
+    } else {
+        let mut text = first;
+        loop {
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+                break;
+            }
+            text.push('\n');
+            text.push_str(line.trim_end());
+        }
+        text
+    };
+
+    let ingredients = parse_recipe(&text);
+    if ingredients.is_empty() {
+        println!("{}", "❌ No ingredients found.".red());
+        return None;
+    }
+    let mut components = Vec::new();
+    for found in ingredients.into_iter().map(|ingredient| match_ingredient(ingredient, &db.foods)) {
+        let component = match (&found.food_id, found.servings) {
+            (Some(food_id), Some(servings)) => Some((food_id.clone(), servings)),
+            _ => review_ingredient(db, &found),
+        };
+        match component {
+            Some((food_id, servings)) => {
+                println!("{} {} → {:.2} serving(s) of {}", "✅".green(), found.ingredient.line, servings, food_id);
+                components.push((food_id, servings));
+            },
+            None => println!("Skipped '{}'.", found.ingredient.line),
+        }
+    }
+    if components.is_empty() {
+        println!("{}", "❌ No ingredients were matched to foods.".red());
+        return None;
+    }
+    if !prompt_line(&format!("Use these {} component(s)? (y/n): ", components.len())).eq_ignore_ascii_case("y") {
+        println!("Recipe discarded.");
+        return None;
+    }
+    Some(components)
+}
+
+// Asks which food an ingredient is and, if its amount doesn't convert, how much of it.
+// Returns `None` when the ingredient is skipped.
+fn review_ingredient(db: &Database, found: &IngredientMatch) -> Option<(String, f32)> {
+    println!("\n❓ {}", found.ingredient.line.bold());
+    if found.candidates.is_empty() {
+        println!("No food matched '{}'.", found.ingredient.name);
+    }
+    for (i, candidate) in found.candidates.iter().enumerate() {
+        println!("{}. {}", i + 1, candidate);
+    }
+    println!("s. Search for another food");
+    println!("k. Skip this ingredient");
+    let food_id = loop {
+        let prompt = if found.candidates.is_empty() { "Enter choice: " } else { "Enter choice (blank for 1): " };
+        let choice = prompt_line(prompt).to_lowercase();
+        match choice.as_str() {
+            "" if !found.candidates.is_empty() => break found.candidates[0].clone(),
+            "k" => return None,
+            "s" => {
+                let food_id = select_food_component(db);
+                if !food_id.is_empty() {
+                    break food_id;
+                }
+            },
+            _ => match choice.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= found.candidates.len() => break found.candidates[n - 1].clone(),
+                _ => println!("{}", "❌ Invalid choice.".red()),
+            },
+        }
     };
+
+    let food = &db.foods[&food_id];
+    let written = found.ingredient.quantity().map(|quantity| (food.servings_for(&quantity), quantity));
+    let servings = match written {
+        Some((Ok(servings), _)) => servings,
+        other => {
+            match other {
+                Some((Err(e), _)) => println!("{} {}", "❌".red(), e),
+                _ => println!("'{}' gives no amount.", found.ingredient.line),
+            }
+            loop {
+                let quantity = prompt_quantity(food);
+                match food.servings_for(&quantity) {
+                    Ok(servings) => break servings,
+                    Err(e) => println!("{} {}", "❌".red(), e),
+                }
+            }
+        },
+    };
+    Some((food_id, servings))
+}
+
+// Prints why a food can't be added to the database. Returns true if the food is valid.
+fn report_invalid_food(db: &Database, food: &Food) -> bool {
+    let errors = db.validate_food(food);
+    for error in &errors {
+        println!("{} {}", "❌".red(), error);
+    }
+    errors.is_empty()
+}
+
+// Asks how much a recipe makes: a number of servings or the cooked weight or volume.
+// A blank answer means the components are one serving.
+fn prompt_yield() -> Option<RecipeYield> {
+    loop {
+        let input = prompt_line("How much does the recipe make? (e.g. 6 servings or 1.8 kg; blank if it is one serving): ");
+        if input.is_empty() {
+            return None;
+        }
+        match RecipeYield::parse(&input) {
+            Some(recipe_yield) => return Some(recipe_yield),
+            None => println!("{}", "❌ Could not read that yield.".red()),
+        }
+    }
+}
+
+// Reads comma-separated barcodes, asking again until all of them are valid. A blank answer
+// gives `None`.
+fn prompt_barcodes() -> Option<Vec<String>> {
+    loop {
+        let input = prompt_line("Enter barcodes (UPC/EAN, comma separated, blank for none): 🏷️ ");
+        if input.is_empty() {
+            return None;
+        }
+        let barcodes: Result<Vec<String>, _> = input.split(',')
+            .map(|code| code.trim())
+            .filter(|code| !code.is_empty())
+            .map(parse_barcode)
+            .collect();
+        match barcodes {
+            Ok(barcodes) => return Some(barcodes),
+            Err(e) => println!("{} {}", "❌".red(), e),
+        }
+    }
+}
+
+// Asks for an optional serving size in grams or millilitres plus food-specific household units
+fn prompt_serving_size() -> Option<ServingSize> {
+    print!("Enter serving size (e.g. 100 g or 240 ml, blank to skip): ");
+    io::stdout().flush().unwrap();
+    let mut size_str = String::new();
+    io::stdin().read_line(&mut size_str).unwrap();
+    if size_str.trim().is_empty() {
+        return None;
+    }
     
-    let food = Food::Composite(composite);
-    let command = Box::new(AddFoodCommand::new(food, &mut state.db));
+    let mut serving = match ServingSize::parse(&size_str) {
+        Some(serving) => serving,
+        None => {
+            println!("{}", "❌ Unrecognised serving size, skipping.".red());
+            return None;
+        }
+    };
     
-    if state.command_manager.execute_command(command) {
-        println!("{}", "✅ Composite food added successfully!".green().bold());
-    } else {
-        println!("{}", "❌ Failed to add food. Please try again.".red().bold());
+    print!("Enter household units as name={} (e.g. cup=240, slice=30; blank for none): ", serving.base_unit());
+    io::stdout().flush().unwrap();
+    let mut units_str = String::new();
+    io::stdin().read_line(&mut units_str).unwrap();
+    for pair in units_str.trim().split(',').filter(|p| !p.trim().is_empty()) {
+        match pair.split_once('=') {
+            Some((name, amount)) => match amount.trim().parse::<f32>() {
+                Ok(amount) if amount > 0.0 => {
+                    serving.household_units.insert(name.trim().to_lowercase(), amount);
+                },
+                _ => println!("Ignoring invalid amount for unit '{}'.", name.trim()),
+            },
+            None => println!("Ignoring '{}': expected name=amount.", pair.trim()),
+        }
+    }
+    Some(serving)
+}
+
+// Asks how much of a food was used. Accepts a plain number of servings or an amount with a unit.
+fn prompt_quantity(food: &Food) -> Quantity {
+    if let Some(serving) = food.serving() {
+        let mut units: Vec<&str> = vec!["serving", serving.base_unit()];
+        units.extend(serving.household_units.keys().map(|u| u.as_str()));
+        println!("1 serving = {} {} | Units: {}", serving.amount, serving.base_unit(), units.join(", "));
+    }
+    loop {
+        print!("Enter quantity (e.g. 2, 150 g, 1 cup; blank for 1 serving): ");
+        io::stdout().flush().unwrap();
+        let mut quantity_str = String::new();
+        io::stdin().read_line(&mut quantity_str).unwrap();
+        if quantity_str.trim().is_empty() {
+            return Quantity::servings(1.0);
+        }
+        match Quantity::parse(&quantity_str) {
+            Some(quantity) => return quantity,
+            None => println!("{}", "❌ Could not read that quantity.".red()),
+        }
     }
 }
 
@@ -256,7 +974,10 @@ fn select_food_component(db: &Database) -> String {
                 print!("Enter keyword to search: ");
                 io::stdout().flush().unwrap();
                 io::stdin().read_line(&mut keyword).unwrap();
-                let results = db.search_by_keyword(keyword.trim());
+                let results: Vec<&Food> = db.search(keyword.trim(), MatchMode::Any)
+                    .into_iter()
+                    .map(|hit| hit.food)
+                    .collect();
                 
                 if results.is_empty() {
                     println!("{}", "No foods found with that keyword. ❌".red());
@@ -284,7 +1005,7 @@ fn display_food_selection(foods: Vec<&Food>) -> String {
     for (i, food) in foods.iter().enumerate() {
         match food {
             Food::Basic(b) => {
-                println!("{}. Basic: {} - {:.1} calories/serving", i+1, b.id, b.calories);
+                println!("{}. Basic: {} - {:.1} calories/serving", i+1, b.id, b.nutrients.calories);
             },
             Food::Composite(c) => {
                 println!("{}. Composite: {}", i+1, c.id);
@@ -298,13 +1019,178 @@ fn display_food_selection(foods: Vec<&Food>) -> String {
     io::stdin().read_line(&mut selection).unwrap();
     let selection: usize = selection.trim().parse().unwrap_or(0);
     
-    if selection == 0 || selection > foods.len() {
-        return String::new();
+    if selection == 0 || selection > foods.len() {
+        return String::new();
+    }
+    
+    match foods[selection-1] {
+        Food::Basic(b) => b.id.clone(),
+        Food::Composite(c) => c.id.clone()
+    }
+}
+
+fn edit_food(state: &mut AppState) {
+    println!("\nChoose the food to edit: ✏️");
+    let food_id = select_food_component(&state.db);
+    if food_id.is_empty() {
+        return;
+    }
+    
+    let mut food = state.db.foods[&food_id].clone();
+    let keywords = match &food {
+        Food::Basic(b) => b.keywords.join(", "),
+        Food::Composite(c) => c.keywords.join(", "),
+    };
+    print!("Enter keywords (comma separated) [{}]: ", keywords);
+    io::stdout().flush().unwrap();
+    let mut keywords_input = String::new();
+    io::stdin().read_line(&mut keywords_input).unwrap();
+    if !keywords_input.trim().is_empty() {
+        let mut keywords_vec: Vec<String> = keywords_input
+            .trim()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if !keywords_vec.contains(&food_id) {
+            keywords_vec.push(food_id.clone());
+        }
+        match &mut food {
+            Food::Basic(b) => b.keywords = keywords_vec,
+            Food::Composite(c) => c.keywords = keywords_vec,
+        }
+    }
+    
+    match &mut food {
+        Food::Basic(b) => {
+            // Blank answers keep the current value
+            for nutrient in Nutrient::ALL {
+                print!("Enter {} per serving ({}) [{:.1}]: ", nutrient.name().to_lowercase(), nutrient.unit(), b.nutrients.get(nutrient));
+                io::stdout().flush().unwrap();
+                let mut value_str = String::new();
+                io::stdin().read_line(&mut value_str).unwrap();
+                if let Ok(value) = value_str.trim().parse() {
+                    b.nutrients.set(nutrient, value);
+                }
+            }
+            let current = if b.barcodes.is_empty() { "none".to_string() } else { b.barcodes.join(", ") };
+            if prompt_line(&format!("Change barcodes [{}]? (y/n): ", current)).eq_ignore_ascii_case("y") {
+                b.barcodes = prompt_barcodes().unwrap_or_default();
+            }
+        },
+        Food::Composite(c) => {
+            print!("Re-enter components? (y/n): ");
+            io::stdout().flush().unwrap();
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer).unwrap();
+            if answer.trim().eq_ignore_ascii_case("y") {
+                c.components = prompt_components(&state.db);
+            }
+            let current = c.recipe_yield.map_or("one serving".to_string(), |recipe_yield| recipe_yield.to_string());
+            if prompt_line(&format!("Change yield [{}]? (y/n): ", current)).eq_ignore_ascii_case("y") {
+                c.recipe_yield = prompt_yield();
+            }
+        },
+    }
+    
+    print!("Change serving size? (y/n): ");
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).unwrap();
+    if answer.trim().eq_ignore_ascii_case("y") {
+        let serving = prompt_serving_size();
+        match &mut food {
+            Food::Basic(b) => b.serving = serving,
+            Food::Composite(c) => c.serving = serving,
+        }
+    }
+    
+    if !report_invalid_food(&state.db, &food) {
+        return;
+    }
+    let frozen = state.daily_log.entries_for_food(&food_id).len();
+    let command = EditFoodCommand::new(food);
+    if state.execute_command(command) {
+        println!("{}", "✅ Food updated successfully!".green().bold());
+        if frozen > 0 {
+            println!("ℹ️ Past log entries keep the nutrition they were logged with.");
+        }
+    } else {
+        println!("{}", "❌ Failed to update food.".red().bold());
+    }
+}
+
+fn rename_food(state: &mut AppState) {
+    println!("\nChoose the food to rename: 🏷️");
+    let old_id = select_food_component(&state.db);
+    if old_id.is_empty() {
+        return;
+    }
+    
+    print!("Enter new identifier for '{}': ", old_id);
+    io::stdout().flush().unwrap();
+    let mut new_id = String::new();
+    io::stdin().read_line(&mut new_id).unwrap();
+    let new_id = new_id.trim().to_string();
+    if new_id.is_empty() || new_id == old_id {
+        println!("Rename cancelled.");
+        return;
+    }
+    if state.db.foods.contains_key(&new_id) || state.db.is_quarantined(&new_id) {
+        println!("{}", format!("❌ A food named '{}' already exists.", new_id).red().bold());
+        return;
+    }
+    
+    let composites = state.db.referencing_composites(&old_id).len();
+    let entries = state.daily_log.entries_for_food(&old_id).len();
+    let rules = state.schedule.rules_for_food(&old_id).len();
+    let command = RenameFoodCommand::new(&old_id, &new_id);
+    if state.execute_command(command) {
+        println!("✅ Renamed '{}' to '{}' ({} composite(s), {} log entr(ies) and {} scheduled rule(s) updated).",
+                 old_id, new_id, composites, entries, rules);
+    } else {
+        println!("{}", "❌ Failed to rename food.".red().bold());
+    }
+}
+
+fn remove_food(state: &mut AppState) {
+    println!("\nChoose the food to remove: 🗑️");
+    let food_id = select_food_component(&state.db);
+    if food_id.is_empty() {
+        return;
+    }
+    
+    let used_by = state.db.referencing_composites(&food_id);
+    if !used_by.is_empty() {
+        println!("{}", format!("❌ '{}' is used by: {}. Remove or edit those composites first.",
+                              food_id, used_by.join(", ")).red().bold());
+        return;
+    }
+    let rules = state.schedule.rules_for_food(&food_id);
+    if !rules.is_empty() {
+        let rules: Vec<String> = rules.iter().map(|id| format!("#{}", id)).collect();
+        println!("{}", format!("❌ '{}' is logged by scheduled rule(s) {}. Remove those rules first.",
+                              food_id, rules.join(", ")).red().bold());
+        return;
+    }
+    
+    let entries = state.daily_log.entries_for_food(&food_id).len();
+    if entries > 0 {
+        println!("ℹ️ {} log entr(ies) use '{}'; they will keep their current nutrition.", entries, food_id);
+    }
+    print!("Remove '{}'? (y/n): ", food_id);
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).unwrap();
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return;
     }
     
-    match foods[selection-1] {
-        Food::Basic(b) => b.id.clone(),
-        Food::Composite(c) => c.id.clone()
+    let command = RemoveFoodCommand::new(&food_id);
+    if state.execute_command(command) {
+        println!("{}", "✅ Food removed.".green().bold());
+    } else {
+        println!("{}", "❌ Failed to remove food.".red().bold());
     }
 }
 
@@ -318,37 +1204,50 @@ fn list_foods(state: &AppState) {
     for food in state.db.foods.values() {
         match food {
             Food::Basic(b) => {
-                println!("Basic Food: {} | Calories: {:.1}", b.id, b.calories);
+                println!("Basic Food: {} | Calories: {:.1} | {}", b.id, b.nutrients.calories, format_macros(&b.nutrients));
             },
             Food::Composite(c) => {
-                let total_cal = compute_calories(food, &state.db.foods);
-                println!("Composite Food: {} | Calories (computed): {:.1}", c.id, total_cal);
+                let total = compute_nutrients(food, &state.db.foods);
+                let makes = c.recipe_yield.map(|recipe_yield| format!(" | Makes {}", recipe_yield)).unwrap_or_default();
+                println!("Composite Food: {} | Calories (computed): {:.1} | {}{}", c.id, total.calories, format_macros(&total), makes);
             }
         }
     }
 }
 
+// Compact one-line rendering of the macronutrients in a nutrient profile
+fn format_macros(nutrients: &Nutrients) -> String {
+    Nutrient::ALL.iter()
+        .skip(1)
+        .map(|n| format!("{}: {:.1}{}", n.name(), nutrients.get(*n), n.unit()))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
 fn search_foods(state: &AppState) {
     let mut keyword = String::new();
     print!("Enter keyword to search: ");
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut keyword).unwrap();
-    let results = state.db.search_by_keyword(keyword.trim());
+    let results = state.db.search(keyword.trim(), MatchMode::Any);
     
     if results.is_empty() {
         println!("No food found with the provided keyword.");
         return;
     }
     
+    // Best matches first
     println!("Search results:");
-    for food in &results {
+    for hit in &results {
+        let food = hit.food;
         match food {
             Food::Basic(b) => {
-                println!("Basic Food: {} | Calories: {:.1}", b.id, b.calories);
+                println!("Basic Food: {} | Calories: {:.1} | {}", b.id, b.nutrients.calories, format_macros(&b.nutrients));
             },
             Food::Composite(c) => {
-                let total_cal = compute_calories(food, &state.db.foods);
-                println!("Composite Food: {} | Calories (computed): {:.1}", c.id, total_cal);
+                let total = compute_nutrients(food, &state.db.foods);
+                let makes = c.recipe_yield.map(|recipe_yield| format!(" | Makes {}", recipe_yield)).unwrap_or_default();
+                println!("Composite Food: {} | Calories (computed): {:.1} | {}{}", c.id, total.calories, format_macros(&total), makes);
             }
         }
     }
@@ -361,23 +1260,253 @@ fn view_daily_log(state: &AppState) {
     }
     
     println!("🍽️ Food log for {}: 📅", state.current_date);
-    print_daily_log_entries(state);
+    print_entries_by_meal(state);
+    println!();
     
-    let total_calories = state.daily_log.get_total_calories(&state.current_date, &state.db.foods);
-    println!("{} {:.1} kcal", "📊 Total calories for the day: 🔥".bold().yellow(), total_calories);
+    let totals = state.daily_log.get_total_nutrients(&state.current_date, &state.db.foods, state.report_mode);
+    println!("{} {:.1} kcal", "📊 Total calories for the day: 🔥".bold().yellow(), totals.calories);
+    println!("{} {}", "🥩 Macros:".bold().yellow(), format_macros(&totals));
+}
+
+fn copy_entries(state: &mut AppState) {
+    let yesterday = offset_date(&state.current_date, -1).unwrap_or_default();
+    let source = prompt_line(&format!("Copy entries from which date? (YYYY-MM-DD, blank for {}): ", yesterday));
+    let source = if source.is_empty() { yesterday } else { source };
+    if NaiveDate::parse_from_str(&source, "%Y-%m-%d").is_err() {
+        println!("{}", "❌ Invalid date format.".red());
+        return;
+    }
+    if !state.daily_log.has_entries_for_date(&source) {
+        println!("No food entries for {} 📅", source);
+        return;
+    }
+
+    let meal = prompt_line("Which meal? (name, blank for all): ");
+    let meal = if meal.is_empty() {
+        None
+    } else {
+        match state.profile.find_meal(&meal) {
+            Some(meal) => Some(meal.name.clone()),
+            None => {
+                println!("{}", "❌ No such meal.".red());
+                return;
+            }
+        }
+    };
+
+    let target = prompt_line(&format!("Copy to (YYYY-MM-DD or YYYY-MM-DD..YYYY-MM-DD, blank for {}): ", state.current_date));
+    let targets = match target.split_once("..") {
+        _ if target.is_empty() => Some(vec![state.current_date.clone()]),
+        Some((start, end)) => date_range(start.trim(), end.trim()),
+        None => date_range(&target, &target),
+    };
+    match targets {
+        Some(targets) if targets.len() <= MAX_COPY_DAYS => run_copy(state, CopyEntriesCommand::new(&source, meal, targets)),
+        Some(_) => println!("{}", format!("❌ Copy to at most {} days at a time.", MAX_COPY_DAYS).red()),
+        None => println!("{}", "❌ Invalid date or range.".red()),
+    }
+}
+
+fn run_copy(state: &mut AppState, command: CopyEntriesCommand) {
+    let description = command.description();
+    if state.execute_command(command) {
+        println!("{} {}", "✅".green(), description);
+    } else {
+        println!("{}", "❌ Nothing to copy.".red());
+    }
+}
+
+// Generates the scheduled entries due on the current date, the first time it is opened
+fn open_date(state: &mut AppState) {
+    let date = state.current_date.clone();
+    let added = state.apply_schedule(std::slice::from_ref(&date));
+    if added > 0 {
+        println!("🔁 Logged {} scheduled entr(ies) for {}.", added, state.current_date);
+    }
+}
+
+fn manage_schedule(state: &mut AppState) {
+    println!("\n{}", "🔁 Scheduled Entries".bold().bright_cyan());
+    if state.schedule.rules().is_empty() {
+        println!("{}", "No scheduled entries yet.".bright_black());
+    }
+    for rule in state.schedule.rules() {
+        println!("  #{} {}", rule.id, rule.describe());
+    }
+    println!("\n1. Add Rule");
+    println!("2. Remove Rule");
+    println!("3. Skip a Rule on {}", state.current_date);
+    println!("4. Generate Entries Up To a Date");
+    println!("5. Cancel");
+    let choice = prompt_line("Enter choice: ");
+    match choice.as_str() {
+        "1" => add_schedule_rule(state),
+        "2" => {
+            let Some(id) = prompt_rule_id(state) else { return };
+            let description = state.schedule.rules().iter().find(|rule| rule.id == id)
+                .map(|rule| rule.describe()).unwrap_or_default();
+            if state.execute_command(RemoveScheduleRuleCommand::new(id)) {
+                println!("{} {}", "✅ Removed rule:".green(), description);
+                println!("   Entries it already logged are kept.");
+            }
+        },
+        "3" => {
+            let Some(id) = prompt_rule_id(state) else { return };
+            let date = state.current_date.clone();
+            let removed = state.daily_log.entries_for_rule(&date, id).len();
+            if state.execute_command(SkipScheduledCommand::new(id, &date)) {
+                println!("✅ Rule #{} skipped on {}{}.", id, date,
+                         if removed > 0 { " and its entry removed" } else { "" });
+            }
+        },
+        "4" => {
+            let until = prompt_line("Generate entries up to (YYYY-MM-DD): ");
+            match date_range(&state.current_date, &until) {
+                Some(dates) if dates.len() <= MAX_COPY_DAYS => {
+                    let added = state.apply_schedule(&dates);
+                    println!("🔁 Logged {} scheduled entr(ies) from {} to {}.", added, state.current_date, until);
+                },
+                Some(_) => println!("{}", format!("❌ Generate at most {} days at a time.", MAX_COPY_DAYS).red()),
+                None => println!("{}", format!("❌ Enter a date on or after {}.", state.current_date).red()),
+            }
+        },
+        _ => {},
+    }
+}
+
+fn prompt_rule_id(state: &AppState) -> Option<u64> {
+    let input = prompt_line("Enter rule number: #");
+    match input.trim_start_matches('#').parse::<u64>() {
+        Ok(id) if state.schedule.rules().iter().any(|rule| rule.id == id) => Some(id),
+        _ => {
+            println!("{}", "❌ No such rule.".red());
+            None
+        }
+    }
+}
+
+fn add_schedule_rule(state: &mut AppState) {
+    let all_foods: Vec<&Food> = state.db.foods.values().collect();
+    if all_foods.is_empty() {
+        println!("No foods in database.");
+        return;
+    }
+    let food_id = display_food_selection(all_foods);
+    if food_id.is_empty() {
+        println!("Food selection cancelled.");
+        return;
+    }
+    let food = &state.db.foods[&food_id];
+    let quantity = loop {
+        let quantity = prompt_quantity(food);
+        match food.servings_for(&quantity) {
+            Ok(_) => break quantity,
+            Err(e) => println!("{} {}", "❌".red(), e),
+        }
+    };
+    let days = loop {
+        let input = prompt_line("Which days? (daily, weekdays, weekends or e.g. mon,wed,fri): ");
+        match parse_days(&input) {
+            Some(days) => break days,
+            None => println!("{}", "❌ Unrecognised days. Please try again.".red()),
+        }
+    };
+    let meal = prompt_meal(&state.profile, &state.current_date);
+    let end = prompt_line("Last date (YYYY-MM-DD, blank for no end): ");
+    let end = if end.is_empty() {
+        None
+    } else if NaiveDate::parse_from_str(&end, "%Y-%m-%d").is_ok() && end >= state.current_date {
+        Some(end)
+    } else {
+        println!("{}", "❌ Invalid end date.".red());
+        return;
+    };
+
+    let rule = ScheduleRule::new(&food_id, quantity, meal, days, &state.current_date, end);
+    let description = rule.describe();
+    if state.execute_command(AddScheduleRuleCommand::new(rule)) {
+        // New rules go at the end of the list
+        let id = state.schedule.rules().last().map(|rule| rule.id).unwrap_or_default();
+        println!("{} #{} {}", "✅ Scheduled".green().bold(), id, description);
+        open_date(state);
+    }
 }
 
 fn print_daily_log_entries(state: &AppState) {
     let entries = state.daily_log.get_log_entries(&state.current_date);
     
     for (i, entry) in entries.iter().enumerate() {
-        let food_name = &entry.food_id;
-        let calories = match state.db.foods.get(food_name) {
-            Some(food) => compute_calories(food, &state.db.foods) * entry.servings,
-            None => 0.0,
+        let meal = entry.meal.as_deref().map(|meal| format!(" ({})", meal)).unwrap_or_default();
+        println!("{}. {}{}", i+1, format_log_entry(state, entry), meal);
+    }
+}
+
+// Entries filed under their meals, each meal with its calorie subtotal and budget
+fn print_entries_by_meal(state: &AppState) {
+    let groups = state.daily_log.entries_by_meal(&state.current_date, &state.profile.meal_names());
+    for (meal, entries) in groups {
+        let subtotal: f32 = entries.iter()
+            .map(|(_, entry)| entry.nutrients(&state.db.foods, state.report_mode).calories)
+            .sum();
+        let budget = state.profile.find_meal(&meal).and_then(|meal| meal.calorie_budget)
+            .map(|budget| format!(" / {:.0} kcal budget", budget))
+            .unwrap_or_default();
+        println!("\n{} {}", format!("🍴 {}:", meal).bold().bright_magenta(), format!("{:.1} kcal{}", subtotal, budget).bright_black());
+        for (i, entry) in entries {
+            println!("  {}. {}", i + 1, format_log_entry(state, entry));
+        }
+    }
+}
+
+fn format_log_entry(state: &AppState, entry: &LogEntry) -> String {
+    let calories = entry.nutrients(&state.db.foods, state.report_mode).calories;
+    let time = entry.logged_time().map(|time| format!("[{}] ", time)).unwrap_or_default();
+    let scheduled = if entry.rule.is_some() { " 🔁" } else { "" };
+    match &entry.quantity {
+        Some(quantity) => format!("{}{} - {} ({:.1} serving(s)), {:.1} calories{}",
+                                  time, entry.food_id, quantity, entry.servings, calories, scheduled),
+        None => format!("{}{} - {:.1} serving(s), {:.1} calories{}",
+                        time, entry.food_id, entry.servings, calories, scheduled),
+    }
+}
+
+// The meal an entry on `date` goes to when none is chosen: the one for the current time of
+// day, but only for today. The clock says nothing about a day being backfilled, so entries
+// for other days are left without a meal.
+pub fn default_meal(profile: &Profile, date: &str) -> Option<String> {
+    if date != Local::now().format("%Y-%m-%d").to_string() {
+        return None;
+    }
+    profile.meal_for_hour(Local::now().hour()).map(|meal| meal.name.clone())
+}
+
+// Asks which meal an entry on `date` belongs to
+fn prompt_meal(profile: &Profile, date: &str) -> Option<String> {
+    if profile.meals.is_empty() {
+        return None;
+    }
+    let default = default_meal(profile, date);
+    let names: Vec<String> = profile.meals.iter().enumerate()
+        .map(|(i, meal)| format!("{}. {}", i + 1, meal.name))
+        .collect();
+    println!("Meals: {}", names.join("  "));
+    loop {
+        print!("Enter meal number or name (blank for {}): ", default.as_deref().unwrap_or("none"));
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        let input = input.trim();
+        if input.is_empty() {
+            return default;
+        }
+        let chosen = match input.parse::<usize>() {
+            Ok(n) => profile.meals.get(n.wrapping_sub(1)),
+            Err(_) => profile.find_meal(input),
         };
-        println!("{}. {} - {:.1} serving(s), {:.1} calories", 
-                 i+1, food_name, entry.servings, calories);
+        match chosen {
+            Some(meal) => return Some(meal.name.clone()),
+            None => println!("{}", "❌ No such meal. Please try again.".red()),
+        }
     }
 }
 
@@ -385,7 +1514,8 @@ fn log_food_entry(state: &mut AppState) {
     println!("\nAdd food to log for {}: 📝", state.current_date);
     println!("1. Search by keyword");
     println!("2. List all foods");
-    println!("3. Cancel");
+    println!("3. Enter a barcode");
+    println!("4. Cancel");
     print!("Enter choice: ");
     io::stdout().flush().unwrap();
     
@@ -400,13 +1530,7 @@ fn log_food_entry(state: &mut AppState) {
             print!("{}", "Enter keywords (comma separated): 🔍 ".magenta());
             io::stdout().flush().unwrap();
             io::stdin().read_line(&mut keywords_input).unwrap();
-            let keywords: Vec<String> = keywords_input
-                .trim()
-                .split(',')
-                .map(|s| s.trim().to_lowercase())
-                .filter(|s| !s.is_empty())
-                .collect();
-            if keywords.is_empty() {
+            if keywords_input.trim().is_empty() {
                 println!("No keywords entered.");
                 return;
             }
@@ -415,20 +1539,12 @@ fn log_food_entry(state: &mut AppState) {
             io::stdout().flush().unwrap();
             let mut mode_input = String::new();
             io::stdin().read_line(&mut mode_input).unwrap();
-            let match_all = mode_input.trim().eq_ignore_ascii_case("y");
+            let mode = if mode_input.trim().eq_ignore_ascii_case("y") { MatchMode::All } else { MatchMode::Any };
             
-            // Manual filtering of foods based on keywords and matching mode
-            let results: Vec<&Food> = state.db.foods.values().filter(|food| {
-                let food_keywords: Vec<String> = match food {
-                    Food::Basic(b) => b.keywords.iter().map(|s| s.to_lowercase()).collect(),
-                    Food::Composite(c) => c.keywords.iter().map(|s| s.to_lowercase()).collect(),
-                };
-                if match_all {
-                    keywords.iter().all(|kw| food_keywords.contains(kw))
-                } else {
-                    keywords.iter().any(|kw| food_keywords.contains(kw))
-                }
-            }).collect();
+            let results: Vec<&Food> = state.db.search(&keywords_input, mode)
+                .into_iter()
+                .map(|hit| hit.food)
+                .collect();
             
             if results.is_empty() {
                 println!("No foods found with the provided keywords.");
@@ -444,6 +1560,24 @@ fn log_food_entry(state: &mut AppState) {
             }
             display_food_selection(all_foods)
         },
+        3 => {
+            let code = prompt_line("Enter barcode (UPC/EAN): 🏷️ ");
+            if code.is_empty() {
+                return;
+            }
+            match state.food_for_barcode(&code) {
+                Ok((food_id, added)) => {
+                    if added {
+                        println!("{} Added '{}' from Open Food Facts", "✅".green(), food_id);
+                    }
+                    food_id
+                },
+                Err(e) => {
+                    println!("{} {}", "❌".red(), e);
+                    return;
+                }
+            }
+        },
         _ => return,
     };
     
@@ -452,28 +1586,28 @@ fn log_food_entry(state: &mut AppState) {
         return;
     }
     
-    let mut servings_str = String::new();
-    print!("Enter number of servings: ");
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut servings_str).unwrap();
-    let servings: f32 = servings_str.trim().parse().unwrap_or(1.0);
-    
-    let command = Box::new(LogFoodCommand::new(
-        &state.current_date,
-        &food_id,
-        servings,
-        &mut state.daily_log
-    ));
-    
-    if state.command_manager.execute_command(command) {
-        let calories = match state.db.foods.get(&food_id) {
-            Some(food) => compute_calories(food, &state.db.foods) * servings,
-            None => 0.0,
-        };
-        
+    let food = &state.db.foods[&food_id];
+    let quantity = loop {
+        let quantity = prompt_quantity(food);
+        match food.servings_for(&quantity) {
+            Ok(_) => break quantity,
+            Err(e) => println!("{} {}", "❌".red(), e),
+        }
+    };
+    let meal = prompt_meal(&state.profile, &state.current_date);
+    let command = match LogFoodCommand::new(&state.current_date, food, quantity, meal, &state.db.foods) {
+        Ok(command) => command,
+        Err(e) => {
+            println!("{} {}", "❌".red(), e);
+            return;
+        }
+    };
+    let servings = command.servings();
+    let calories = compute_calories(food, &state.db.foods) * servings;
+    
+    if state.execute_command(command) {
         println!("✅ Logged {:.1} serving(s) of {} ({:.1} calories) for {}", 
             servings, food_id, calories, state.current_date);
-        state.daily_log.save();
     } else {
         println!("❌ Failed to log food.");
     }
@@ -500,21 +1634,12 @@ fn remove_log_entry(state: &mut AppState) {
     
     let entries = state.daily_log.get_log_entries(&state.current_date);
     if index <= entries.len() {
-        let entry = entries[index - 1];
-        let food_id = entry.food_id.clone();
-        let servings = entry.servings;
+        let entry = entries[index - 1].clone();
         
-        let command = Box::new(RemoveLogEntryCommand::new(
-            &state.current_date,
-            index - 1,
-            &food_id,
-            servings,
-            &mut state.daily_log
-        ));
+        let command = RemoveLogEntryCommand::new(&state.current_date, entry);
         
-        if state.command_manager.execute_command(command) {
+        if state.execute_command(command) {
             println!("✅ Entry removed successfully.");
-            state.daily_log.save();
         } else {
             println!("❌ Failed to remove entry.");
         }
@@ -523,6 +1648,70 @@ fn remove_log_entry(state: &mut AppState) {
     }
 }
 
+// Changes an entry's amount or meal in place. Scheduled entries keep their rule, so the
+// schedule still counts the day as done.
+fn edit_log_entry(state: &mut AppState) {
+    if !state.daily_log.has_entries_for_date(&state.current_date) {
+        println!("No entries found for date: {}", state.current_date);
+        return;
+    }
+
+    println!("Entries for {}:", state.current_date);
+    print_daily_log_entries(state);
+
+    let index: usize = prompt_line("Enter number to edit (or 0 to cancel): ").parse().unwrap_or(0);
+    if index == 0 {
+        return;
+    }
+    let entries = state.daily_log.get_log_entries(&state.current_date);
+    let Some(entry) = entries.get(index - 1).map(|entry| (*entry).clone()) else {
+        println!("{}", "❌ Invalid selection. Please try again.".red());
+        return;
+    };
+    let Some(food) = state.db.foods.get(&entry.food_id) else {
+        println!("{}", format!("❌ Food '{}' is no longer in the database.", entry.food_id).red());
+        return;
+    };
+
+    let current = entry.quantity.clone().unwrap_or(Quantity::servings(entry.servings));
+    let quantity = loop {
+        let input = prompt_line(&format!("Enter new quantity (e.g. 2, 150 g; blank to keep {}): ", current));
+        if input.is_empty() {
+            break current.clone();
+        }
+        match Quantity::parse(&input).map(|quantity| food.servings_for(&quantity).map(|_| quantity)) {
+            Some(Ok(quantity)) => break quantity,
+            Some(Err(e)) => println!("{} {}", "❌".red(), e),
+            None => println!("{}", "❌ Could not read that quantity.".red()),
+        }
+    };
+    let meal = if state.profile.meals.is_empty() {
+        entry.meal.clone()
+    } else {
+        loop {
+            let input = prompt_line(&format!("Enter meal name (blank to keep {}): ", entry.meal.as_deref().unwrap_or("none")));
+            if input.is_empty() {
+                break entry.meal.clone();
+            }
+            match state.profile.find_meal(&input) {
+                Some(meal) => break Some(meal.name.clone()),
+                None => println!("{}", "❌ No such meal. Please try again.".red()),
+            }
+        }
+    };
+
+    match EditLogEntryCommand::new(&state.current_date, &entry, food, quantity, meal) {
+        Ok(command) => {
+            if state.execute_command(command) {
+                println!("✅ Entry updated successfully.");
+            } else {
+                println!("❌ Failed to update entry.");
+            }
+        },
+        Err(e) => println!("{} {}", "❌".red(), e),
+    }
+}
+
 fn select_date(state: &mut AppState) {
     let today = Local::now().naive_local().date();
     let mut date_str = String::new();
@@ -543,6 +1732,7 @@ fn select_date(state: &mut AppState) {
         }
     }
     println!("📆 Date changed to: {}", state.current_date);
+    open_date(state);
 }
 
 fn edit_profile(state: &mut AppState) {
@@ -550,10 +1740,11 @@ fn edit_profile(state: &mut AppState) {
     println!("1. Gender ({:?})", state.profile.gender);
     println!("2. Age ({})", state.profile.age);
     println!("3. Height ({} cm)", state.profile.height_cm);
-    println!("4. Weight ({} kg)", state.profile.weight_kg);
+    println!("4. Weight ({} kg, used before the first weigh-in)", state.profile.weight_kg);
     println!("5. Activity Level ({:?})", state.profile.activity_level);
     println!("6. Target Formula ({:?})", state.profile.target_formula);
-    println!("7. Cancel");
+    println!("7. Meals & Budgets ({})", state.profile.meal_names().join(", "));
+    println!("8. Cancel");
     print!("Choose setting to edit: ");
     io::stdout().flush().unwrap();
     
@@ -561,7 +1752,7 @@ fn edit_profile(state: &mut AppState) {
     io::stdin().read_line(&mut choice).unwrap();
     let choice: u32 = choice.trim().parse().unwrap_or(0);
     
-    match choice {
+    let setting = match choice {
         1 => {
             println!("Select gender:");
             println!("1. Male");
@@ -570,18 +1761,22 @@ fn edit_profile(state: &mut AppState) {
             io::stdout().flush().unwrap();
             let mut gender_choice = String::new();
             io::stdin().read_line(&mut gender_choice).unwrap();
-            state.profile.gender = match gender_choice.trim() {
+            ProfileSetting::Gender(match gender_choice.trim() {
                 "2" => Gender::Female,
                 _ => Gender::Male,
-            };
+            })
         },
         2 => {
             print!("Enter age: ");
             io::stdout().flush().unwrap();
             let mut age = String::new();
             io::stdin().read_line(&mut age).unwrap();
-            if let Ok(age) = age.trim().parse() {
-                state.profile.age = age;
+            match age.trim().parse() {
+                Ok(age) => ProfileSetting::Age(age),
+                Err(_) => {
+                    println!("{}", "❌ Invalid number. Profile unchanged.".red());
+                    return;
+                }
             }
         },
         3 => {
@@ -589,8 +1784,12 @@ fn edit_profile(state: &mut AppState) {
             io::stdout().flush().unwrap();
             let mut height = String::new();
             io::stdin().read_line(&mut height).unwrap();
-            if let Ok(height) = height.trim().parse() {
-                state.profile.height_cm = height;
+            match height.trim().parse() {
+                Ok(height) => ProfileSetting::HeightCm(height),
+                Err(_) => {
+                    println!("{}", "❌ Invalid number. Profile unchanged.".red());
+                    return;
+                }
             }
         },
         4 => {
@@ -598,8 +1797,12 @@ fn edit_profile(state: &mut AppState) {
             io::stdout().flush().unwrap();
             let mut weight = String::new();
             io::stdin().read_line(&mut weight).unwrap();
-            if let Ok(weight) = weight.trim().parse() {
-                state.profile.weight_kg = weight;
+            match weight.trim().parse() {
+                Ok(weight) => ProfileSetting::WeightKg(weight),
+                Err(_) => {
+                    println!("{}", "❌ Invalid number. Profile unchanged.".red());
+                    return;
+                }
             }
         },
         5 => {
@@ -613,14 +1816,14 @@ fn edit_profile(state: &mut AppState) {
             io::stdout().flush().unwrap();
             let mut level = String::new();
             io::stdin().read_line(&mut level).unwrap();
-            state.profile.activity_level = match level.trim() {
+            ProfileSetting::ActivityLevel(match level.trim() {
                 "1" => ActivityLevel::Sedentary,
                 "2" => ActivityLevel::LightlyActive,
                 "3" => ActivityLevel::ModeratelyActive,
                 "4" => ActivityLevel::VeryActive,
                 "5" => ActivityLevel::ExtremelyActive,
                 _ => ActivityLevel::ModeratelyActive,
-            };
+            })
         },
         6 => {
             println!("Select target formula:");
@@ -630,24 +1833,123 @@ fn edit_profile(state: &mut AppState) {
             io::stdout().flush().unwrap();
             let mut formula = String::new();
             io::stdin().read_line(&mut formula).unwrap();
-            state.profile.target_formula = match formula.trim() {
+            ProfileSetting::TargetFormula(match formula.trim() {
                 "2" => TargetFormula::HarrisBenedict,
                 _ => TargetFormula::MifflinStJeor,
-            };
+            })
+        },
+        7 => match edit_meals(&state.profile) {
+            Some(meals) => ProfileSetting::Meals(meals),
+            None => return,
         },
         _ => return,
+    };
+    if let Err(e) = setting.validate() {
+        println!("{}", format!("❌ Invalid value: {}. Profile unchanged.", e).red());
+        return;
     }
     
-    state.profile.save();
-    println!("{}", "✅ Profile updated successfully! 🎉".green().bold());
+    if state.execute_command(UpdateProfileCommand::new(setting)) {
+        println!("{}", "✅ Profile updated successfully! 🎉".green().bold());
+    }
+}
+
+// Builds an edited copy of the meal list; the caller applies it as one undoable change
+fn edit_meals(profile: &Profile) -> Option<Vec<Meal>> {
+    let mut meals = profile.meals.clone();
+    println!("\nMeals:");
+    for (i, meal) in meals.iter().enumerate() {
+        let hours = meal.hours.map(|(start, end)| format!("{:02}:00-{:02}:00", start, end)).unwrap_or_else(|| "any time".to_string());
+        let budget = meal.calorie_budget.map(|budget| format!("{:.0} kcal", budget)).unwrap_or_else(|| "no budget".to_string());
+        println!("{}. {} ({}, {})", i + 1, meal.name, hours, budget);
+    }
+    println!("1. Set a meal's calorie budget");
+    println!("2. Add a meal");
+    println!("3. Remove a meal");
+    println!("4. Reset to Breakfast, Lunch, Dinner and Snack");
+    println!("5. Cancel");
+    let choice = prompt_line("Choose option: ");
+
+    match choice.as_str() {
+        "1" => {
+            let index = prompt_meal_index(&meals)?;
+            let budget = prompt_line("Enter calorie budget (blank to remove): ");
+            meals[index].calorie_budget = if budget.is_empty() {
+                None
+            } else {
+                match budget.parse::<f32>() {
+                    Ok(budget) if budget > 0.0 => Some(budget),
+                    _ => {
+                        println!("{}", "❌ Budget must be a positive number.".red());
+                        return None;
+                    }
+                }
+            };
+        },
+        "2" => {
+            let name = prompt_line("Enter meal name: ");
+            if name.is_empty() || profile.find_meal(&name).is_some() {
+                println!("{}", "❌ Meal names must be new and not empty.".red());
+                return None;
+            }
+            let hours = prompt_line("Default from which hours? (e.g. 15-17, blank for none): ");
+            let hours = if hours.is_empty() {
+                None
+            } else {
+                let parsed = hours.split_once('-')
+                    .and_then(|(start, end)| Some((start.trim().parse::<u32>().ok()?, end.trim().parse::<u32>().ok()?)))
+                    .filter(|(start, end)| start < end && *end <= 24);
+                if parsed.is_none() {
+                    println!("{}", "❌ Hours must look like 15-17.".red());
+                    return None;
+                }
+                parsed
+            };
+            meals.push(Meal::new(&name, hours));
+        },
+        "3" => {
+            let index = prompt_meal_index(&meals)?;
+            meals.remove(index);
+        },
+        "4" => meals = default_meals(),
+        _ => return None,
+    }
+    Some(meals)
+}
+
+fn prompt_meal_index(meals: &[Meal]) -> Option<usize> {
+    let input = prompt_line("Enter meal number: ");
+    match input.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= meals.len() => Some(n - 1),
+        _ => {
+            println!("{}", "❌ Invalid selection.".red());
+            None
+        }
+    }
+}
+
+fn prompt_line(prompt: &str) -> String {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    input.trim().to_string()
 }
 
 fn set_daily_target(state: &mut AppState) {
     let current_target = state.profile.get_daily_target(&state.current_date);
     println!("\nCurrent target for {}: {:.0} calories", state.current_date, current_target);
+    for nutrient in [Nutrient::Protein, Nutrient::Carbs, Nutrient::Fat] {
+        if let Some(target) = state.profile.get_nutrient_target(&state.current_date, nutrient) {
+            println!("  {} target: {:.1}{}", nutrient.name(), target, nutrient.unit());
+        }
+    }
     println!("1. Set custom target");
     println!("2. Remove custom target (use calculated target)");
-    println!("3. Cancel");
+    println!("3. Set default macro targets");
+    println!("4. Set macro targets for {}", state.current_date);
+    println!("5. Remove macro targets for {} (use default macro targets)", state.current_date);
+    println!("6. Cancel");
     print!("Choose option: ");
     io::stdout().flush().unwrap();
     
@@ -661,28 +1963,268 @@ fn set_daily_target(state: &mut AppState) {
             let mut target = String::new();
             io::stdin().read_line(&mut target).unwrap();
             if let Ok(calories) = target.trim().parse() {
-                state.profile.set_daily_override(&state.current_date, calories);
-                state.profile.save();
-                println!("{}", "✅ Daily target set successfully! 🎯".green().bold());
+                let date = state.current_date.clone();
+                if state.execute_command(SetDailyTargetCommand::new(&date, Some(calories))) {
+                    println!("{}", "✅ Daily target set successfully! 🎯".green().bold());
+                }
             }
         },
         "2" => {
-            state.profile.remove_daily_override(&state.current_date);
-            state.profile.save();
-            let new_target = state.profile.calculate_target_calories();
-            println!("{}", "✅ Custom target removed. Using calculated target. 🎯".green().bold());
+            let date = state.current_date.clone();
+            if state.execute_command(SetDailyTargetCommand::new(&date, None)) {
+                println!("{}", "✅ Custom target removed. Using calculated target. 🎯".green().bold());
+            }
+        },
+        "3" => {
+            let targets = prompt_macro_targets();
+            if state.execute_command(UpdateProfileCommand::new(ProfileSetting::MacroTargets(targets))) {
+                println!("{}", "✅ Default macro targets set successfully! 🎯".green().bold());
+            }
+        },
+        "4" => {
+            let targets = prompt_macro_targets();
+            let date = state.current_date.clone();
+            if state.execute_command(SetMacroTargetsCommand::new(&date, Some(targets))) {
+                println!("{}", "✅ Macro targets set successfully! 🎯".green().bold());
+            }
+        },
+        "5" => {
+            let date = state.current_date.clone();
+            if state.execute_command(SetMacroTargetsCommand::new(&date, None)) {
+                println!("{}", "✅ Macro override removed. Using default macro targets. 🎯".green().bold());
+            }
+        },
+        _ => {},
+    }
+}
+
+// Asks for protein, carbs and fat goals. Accepts grams ("150" or "150g") or a share of
+// the calorie target ("30%"); a blank answer leaves that macro without a target.
+fn prompt_macro_targets() -> MacroTargets {
+    let mut targets = MacroTargets::default();
+    for nutrient in [Nutrient::Protein, Nutrient::Carbs, Nutrient::Fat] {
+        print!("Enter {} target (e.g. 120g or 30%, blank for none): ", nutrient.name().to_lowercase());
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        targets.set(nutrient, input.trim().parse::<MacroTarget>().ok());
+    }
+    targets
+}
+
+fn view_range_report(state: &AppState) {
+    println!("\n📊 Nutrition report for:");
+    println!("1. The week of {}", state.current_date);
+    println!("2. The month of {}", state.current_date);
+    println!("3. A custom date range");
+    let range = match prompt_line("Enter choice: ").as_str() {
+        "1" => week_of(&state.current_date),
+        "2" => month_of(&state.current_date),
+        "3" => {
+            let start = prompt_line("Start date (YYYY-MM-DD): ");
+            let end = prompt_line(&format!("End date (YYYY-MM-DD, blank for {}): ", state.current_date));
+            Some((start, if end.is_empty() { state.current_date.clone() } else { end }))
         },
         _ => return,
+    };
+    let report = range.and_then(|(start, end)| {
+        RangeReport::build(&start, &end, &state.daily_log, &state.profile, &state.db.foods, state.report_mode)
+    });
+    match report {
+        Some(report) => print_range_report(&report, DEFAULT_TOP_FOODS),
+        None => println!("{}", "❌ Invalid date or range.".red()),
+    }
+}
+
+// Colored table of a range report, shared by the menu and the `report` subcommand
+fn print_range_report(report: &RangeReport, top: usize) {
+    println!("\n{} {} to {} ({} values)", "📊 Nutrition Report:".bold().bright_cyan(), report.start, report.end, report.mode.name());
+    println!("Logged {} of {} day(s).", report.days_logged, report.days_in_range);
+    if report.days.is_empty() {
+        println!("{}", "No food entries in this range.".bright_black());
+        return;
+    }
+
+    println!("\n{}", format!("{:<12} {:>10} {:>10} {:>8}  {}", "Date", "Calories", "Target", "%", "Status").bold().underline());
+    for day in &report.days {
+        let status = match day.status {
+            TargetStatus::OnTarget => "On target".green(),
+            TargetStatus::Under => "Under".blue(),
+            TargetStatus::Over => "Over".red(),
+        };
+        println!("{:<12} {:>10.1} {:>10.1} {:>7.1}%  {}", day.date, day.calories, day.target, day.percentage, status);
+    }
+    println!("{}", format!("{:<12} {:>10.1} {:>10.1}", "Average", report.average.calories, report.average_target).bold());
+
+    println!("\n{} {}  {} {}  {} {}",
+             "✅ On target:".green().bold(), report.on_target,
+             "ℹ️ Under:".blue().bold(), report.under,
+             "⚠️ Over:".red().bold(), report.over);
+    if let (Some(best), Some(worst)) = (&report.best_day, &report.worst_day) {
+        println!("{} {} ({:.1} kcal, {:.1}% of target)", "🏆 Best day:".bold(), best.date, best.calories, best.percentage);
+        println!("{} {} ({:.1} kcal, {:.1}% of target)", "📉 Worst day:".bold(), worst.date, worst.calories, worst.percentage);
+    }
+    println!("{} {}", "🥩 Average macros:".bold().yellow(), format_macros(&report.average));
+
+    println!("\n{}", "🍽️ Top foods by calories:".bold().bright_magenta());
+    for (i, food) in report.foods.iter().take(top).enumerate() {
+        println!("{:>3}. {:<20} {:>9.1} kcal {:>6.1}%  {:.1} serving(s) in {} entr(ies)",
+                 i + 1, food.food_id, food.calories, food.share, food.servings, food.entries);
+    }
+}
+
+fn view_calorie_chart(state: &AppState) {
+    let input = prompt_line(&format!("Chart how many days up to {}? (blank for {}): ", state.current_date, DEFAULT_CHART_DAYS));
+    let days = match input.parse::<usize>() {
+        _ if input.is_empty() => DEFAULT_CHART_DAYS,
+        Ok(days) if (1..=MAX_CHART_DAYS).contains(&days) => days,
+        _ => {
+            println!("{}", format!("❌ Enter a number of days from 1 to {}.", MAX_CHART_DAYS).red());
+            return;
+        }
+    };
+    let line = prompt_line("1. Bar chart  2. Line chart (blank for bars): ") == "2";
+    let Some(points) = calorie_series(&state.daily_log, &state.profile, &state.db.foods, state.report_mode, &state.current_date, days) else {
+        return;
+    };
+
+    println!("\n{} last {} day(s) to {} ({} values)", "📈 Calories vs. target:".bold().bright_cyan(), days, state.current_date, state.report_mode.name());
+    let lines = if line { line_chart(&points) } else { bar_chart(&points) };
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+fn manage_weight(state: &mut AppState) {
+    println!("\n{}", "⚖️ Weight Log".bold().bright_cyan());
+    match state.profile.weights.get(&state.current_date) {
+        Some(entry) => println!("Weigh-in on {}: {}", state.current_date, entry),
+        None => println!("No weigh-in on {}.", state.current_date),
+    }
+    println!("Targets use the {} weight ({:.1} kg on {}).",
+             state.profile.weight_source.name(), state.profile.weight_on(&state.current_date), state.current_date);
+    println!("\n1. Record Weight for {}", state.current_date);
+    println!("2. Remove Weigh-in for {}", state.current_date);
+    println!("3. Weight Report");
+    println!("4. Weight Used for Targets");
+    println!("5. Cancel");
+    match prompt_line("Enter choice: ").as_str() {
+        "1" => {
+            let Ok(weight) = prompt_line("Weight (kg): ").parse::<f32>() else {
+                println!("{}", "❌ Invalid number.".red());
+                return;
+            };
+            let mut entry = WeightEntry::new(weight);
+            if let Err(e) = entry.validate() {
+                println!("{}", format!("❌ Invalid weigh-in: {}.", e).red());
+                return;
+            }
+            let body_fat = prompt_line("Body fat % (blank to skip): ");
+            if !body_fat.is_empty() {
+                match body_fat.trim_end_matches('%').parse::<f32>() {
+                    Ok(body_fat) => entry.body_fat_pct = Some(body_fat),
+                    Err(_) => {
+                        println!("{}", "❌ Invalid number.".red());
+                        return;
+                    }
+                }
+                if let Err(e) = entry.validate() {
+                    println!("{}", format!("❌ Invalid weigh-in: {}.", e).red());
+                    return;
+                }
+            }
+            match parse_measurements(&prompt_line("Measurements in cm, e.g. waist=82, hips=96 (blank to skip): ")) {
+                Some(measurements) => entry.measurements = measurements,
+                None => {
+                    println!("{}", "❌ Enter measurements as name=cm with positive lengths, separated by commas.".red());
+                    return;
+                }
+            }
+            run_weight_command(state, RecordWeightCommand::new(&state.current_date.clone(), Some(entry)));
+        },
+        "2" => {
+            if state.profile.weights.contains_key(&state.current_date) {
+                run_weight_command(state, RecordWeightCommand::new(&state.current_date.clone(), None));
+            } else {
+                println!("No weigh-in on {}.", state.current_date);
+            }
+        },
+        "3" => {
+            let default_start = offset_date(&state.current_date, 1 - WEIGHT_REPORT_DAYS).unwrap_or_default();
+            let start = prompt_line(&format!("Start date (YYYY-MM-DD, blank for {}): ", default_start));
+            let start = if start.is_empty() { default_start } else { start };
+            match WeightReport::build(&start, &state.current_date, &state.daily_log, &state.profile, &state.db.foods, state.report_mode) {
+                Some(report) => print_weight_report(&report),
+                None => println!("{}", format!("❌ Enter a date on or before {}.", state.current_date).red()),
+            }
+        },
+        "4" => {
+            println!("1. The weight in the profile ({} kg)", state.profile.weight_kg);
+            println!("2. The latest weigh-in");
+            println!("3. The smoothed weight trend");
+            let source = match prompt_line("Enter choice: ").as_str() {
+                "1" => WeightSource::Profile,
+                "2" => WeightSource::Latest,
+                "3" => WeightSource::Smoothed,
+                _ => return,
+            };
+            if state.execute_command(UpdateProfileCommand::new(ProfileSetting::WeightSource(source))) {
+                println!("{} Targets now use the {} weight.", "✅".green(), source.name());
+            }
+        },
+        _ => {},
+    }
+}
+
+fn run_weight_command(state: &mut AppState, command: RecordWeightCommand) {
+    let description = command.description();
+    if state.execute_command(command) {
+        println!("{} {}", "✅".green(), description);
+    }
+}
+
+// Weigh-ins with their trend, the trend chart and the energy balance estimates
+fn print_weight_report(report: &WeightReport) {
+    println!("\n{} {} to {}", "⚖️ Weight Report:".bold().bright_cyan(), report.start, report.end);
+    if report.points.is_empty() {
+        println!("{}", "No weigh-ins in this range.".bright_black());
+    } else {
+        println!("\n{}", format!("{:<12} {:>8} {:>8} {:>9}", "Date", "Weight", "Trend", "Body fat").bold().underline());
+        for point in &report.points {
+            let body_fat = point.body_fat_pct.map(|pct| format!("{:.1}%", pct)).unwrap_or_default();
+            println!("{:<12} {:>8.1} {:>8.1} {:>9}", point.date, point.weight_kg, point.trend_kg, body_fat);
+        }
+        println!();
+        for line in weight_chart(&report.points) {
+            println!("{}", line);
+        }
+    }
+
+    if let (Some(change), Some(weekly)) = (report.trend_change_kg, report.weekly_rate_kg) {
+        let text = format!("{:+.2} kg ({:+.2} kg/week)", change, weekly);
+        println!("\n{} {}", "📉 Trend change:".bold(), if change > 0.0 { text.red() } else { text.green() });
+    }
+    println!("{} {:.0} kcal/day (from the target formula)", "🔥 Estimated expenditure:".bold(), report.estimated_expenditure);
+    match (report.average_intake, report.balance_from_intake) {
+        (Some(intake), Some(balance)) => println!("{} {:.0} kcal over {} logged day(s), a balance of {:+.0} kcal/day",
+                                                  "🍽️ Average intake:".bold(), intake, report.days_logged, balance),
+        _ => println!("{}", "No food logged in this range.".bright_black()),
+    }
+    if let Some(balance) = report.balance_from_trend {
+        println!("{} {:+.0} kcal/day", "⚖️ Balance implied by the weight trend:".bold(), balance);
+    }
+    if let Some(expenditure) = report.implied_expenditure {
+        println!("{} {:.0} kcal/day", "💡 Expenditure implied by intake and trend:".bold(), expenditure);
     }
 }
 
 fn view_daily_summary(state: &AppState) {
     let target = state.profile.get_daily_target(&state.current_date);
-    let consumed = state.daily_log.get_total_calories(&state.current_date, &state.db.foods);
+    let consumed = state.daily_log.get_total_calories(&state.current_date, &state.db.foods, state.report_mode);
     let difference = consumed - target;
     let percentage = if target > 0.0 { (consumed / target) * 100.0 } else { 0.0 };
     
-    println!("\n📊 Daily Summary for {}:", state.current_date);
+    println!("\n📊 Daily Summary for {} ({} values):", state.current_date, state.report_mode.name());
     println!("{} {:.1} kcal", "🎯 Target Calories:".bold().blue(), target);
     println!("{} {:.1} kcal", "🍽️ Consumed Calories:".bold().green(), consumed);
     
@@ -694,17 +2236,54 @@ fn view_daily_summary(state: &AppState) {
     }
     
     // Show nutrition status based on percentage
-    if percentage >= 95.0 && percentage <= 105.0 {
-        println!("{}", "✅ Status: On target!".bold().green());
-    } else if percentage < 95.0 {
-        println!("{}", "ℹ️ Status: Under daily target".bold().blue());
-    } else {
-        println!("{}", "⚠️ Status: Over daily target".bold().red());
+    match TargetStatus::evaluate(consumed, target) {
+        TargetStatus::OnTarget => println!("{}", "✅ Status: On target!".bold().green()),
+        TargetStatus::Under => println!("{}", "ℹ️ Status: Under daily target".bold().blue()),
+        TargetStatus::Over => println!("{}", "⚠️ Status: Over daily target".bold().red()),
+    }
+    
+    let totals = state.daily_log.get_total_nutrients(&state.current_date, &state.db.foods, state.report_mode);
+    println!("\n{}", "Nutrients:".bold());
+    for nutrient in Nutrient::ALL.iter().skip(1) {
+        let consumed = totals.get(*nutrient);
+        match state.profile.get_nutrient_target(&state.current_date, *nutrient) {
+            Some(target) => {
+                let percentage = if target > 0.0 { (consumed / target) * 100.0 } else { 0.0 };
+                let status = match TargetStatus::evaluate(consumed, target) {
+                    TargetStatus::OnTarget => "On target".green(),
+                    TargetStatus::Under => "Under".blue(),
+                    TargetStatus::Over => "Over".red(),
+                };
+                println!("  {:<8} {:>7.1} / {:.1} {} ({:.1}%) - {}",
+                         nutrient.name(), consumed, target, nutrient.unit(), percentage, status);
+            },
+            None => println!("  {:<8} {:>7.1} {} (no target)", nutrient.name(), consumed, nutrient.unit()),
+        }
+    }
+    
+    let groups = state.daily_log.entries_by_meal(&state.current_date, &state.profile.meal_names());
+    let budgeted = state.profile.meals.iter().filter(|meal| meal.calorie_budget.is_some());
+    if budgeted.clone().next().is_some() {
+        println!("\n{}", "Meal Budgets:".bold());
+        for meal in budgeted {
+            let budget = meal.calorie_budget.unwrap_or_default();
+            let consumed: f32 = groups.iter()
+                .filter(|(name, _)| name.eq_ignore_ascii_case(&meal.name))
+                .flat_map(|(_, entries)| entries.iter())
+                .map(|(_, entry)| entry.nutrients(&state.db.foods, state.report_mode).calories)
+                .sum();
+            let status = match TargetStatus::evaluate(consumed, budget) {
+                TargetStatus::OnTarget => "On target".green(),
+                TargetStatus::Under => "Under".blue(),
+                TargetStatus::Over => "Over".red(),
+            };
+            println!("  {:<10} {:>7.1} / {:.0} kcal - {}", meal.name, consumed, budget, status);
+        }
     }
     
     if state.daily_log.has_entries_for_date(&state.current_date) {
         println!("\nFood entries:");
-        print_daily_log_entries(state);
+        print_entries_by_meal(state);
     } else {
         println!("\nNo food entries for today.");
     }
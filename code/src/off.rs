@@ -0,0 +1,284 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::barcode::same_product;
+use crate::csv::{CsvReader, column};
+use crate::food::{BasicFood, Food, MeasureKind, Nutrient, Nutrients, ServingSize, keywords_from};
+
+// Open Food Facts fields read for a product. Nutrient values are per 100 g (or 100 ml);
+// sodium and salt are in grams.
+const FIELDS: [&str; 15] = [
+    "code", "product_name", "brands", "serving_size", "serving_quantity",
+    "energy-kcal_100g", "energy-kj_100g", "energy_100g",
+    "proteins_100g", "carbohydrates_100g", "fat_100g", "fiber_100g", "sugars_100g", "sodium_100g", "salt_100g",
+];
+const NUTRIMENTS: [(&str, Nutrient); 5] = [
+    ("proteins_100g", Nutrient::Protein),
+    ("carbohydrates_100g", Nutrient::Carbs),
+    ("fat_100g", Nutrient::Fat),
+    ("fiber_100g", Nutrient::Fiber),
+    ("sugars_100g", Nutrient::Sugar),
+];
+const KJ_PER_KCAL: f32 = 4.184;
+const SALT_PER_SODIUM: f32 = 2.5;
+
+#[derive(Debug)]
+pub enum LookupError {
+    Io { path: PathBuf, source: io::Error },
+    MissingColumn { path: PathBuf, column: String },
+    Compressed(PathBuf),
+    Unrecognized(PathBuf), // Not a .jsonl, .csv or .tsv export
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LookupError::Io { path, source } =>
+                write!(f, "cannot read {}: {}", path.display(), source),
+            LookupError::MissingColumn { path, column } =>
+                write!(f, "{} has no '{}' column", path.display(), column),
+            LookupError::Compressed(path) =>
+                write!(f, "{} is compressed; decompress it first", path.display()),
+            LookupError::Unrecognized(path) =>
+                write!(f, "{} is not an Open Food Facts export (expected a .jsonl, .csv or .tsv file)", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LookupError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// A product found in the export
+#[derive(Debug, Clone)]
+pub struct OffProduct {
+    pub code: String,
+    pub name: String,
+    pub brand: Option<String>, // The first brand listed
+    pub serving: Option<ServingSize>,
+    pub per_100g: Option<Nutrients>, // None when the product has no energy value
+}
+
+impl OffProduct {
+    // Builds a product from its fields, looked up by Open Food Facts name
+    fn from_fields(code: &str, field: impl Fn(&str) -> Option<String>) -> OffProduct {
+        let number = |name: &str| field(name).and_then(|value| value.trim().parse::<f32>().ok());
+        let calories = number("energy-kcal_100g")
+            .or_else(|| number("energy-kj_100g").or_else(|| number("energy_100g")).map(|kj| kj / KJ_PER_KCAL));
+        let per_100g = calories.map(|calories| {
+            let mut nutrients = Nutrients { calories, ..Nutrients::default() };
+            for (name, nutrient) in NUTRIMENTS {
+                if let Some(value) = number(name) {
+                    nutrients.set(nutrient, value);
+                }
+            }
+            if let Some(sodium) = number("sodium_100g").or_else(|| number("salt_100g").map(|salt| salt / SALT_PER_SODIUM)) {
+                nutrients.sodium = sodium * 1000.0;
+            }
+            nutrients
+        });
+
+        // Serving sizes are free text such as "30 g" or "1 bar (40 g)"
+        let serving = field("serving_size")
+            .and_then(|size| {
+                let size = size.trim();
+                ServingSize::parse(size).or_else(|| {
+                    let (_, inner) = size.rsplit_once('(')?;
+                    ServingSize::parse(inner.trim_end_matches(')'))
+                })
+            })
+            .or_else(|| number("serving_quantity").map(|grams| ServingSize {
+                amount: grams,
+                kind: MeasureKind::Mass,
+                household_units: Default::default(),
+            }))
+            .filter(|serving| serving.amount > 0.0);
+
+        OffProduct {
+            code: code.to_string(),
+            name: field("product_name").map(|name| name.trim().to_string()).unwrap_or_default(),
+            brand: field("brands")
+                .and_then(|brands| brands.split(',').next().map(|brand| brand.trim().to_string()))
+                .filter(|brand| !brand.is_empty()),
+            serving,
+            per_100g,
+        }
+    }
+
+    // A name for the database, e.g. "Hazelnut spread (Nutella)"
+    pub fn food_id(&self) -> String {
+        let name = if self.name.is_empty() { format!("Product {}", self.code) } else { self.name.clone() };
+        match &self.brand {
+            Some(brand) if !name.to_lowercase().contains(&brand.to_lowercase()) => format!("{} ({})", name, brand),
+            _ => name,
+        }
+    }
+
+    // The product as a basic food with the label's serving size (100 g when it has none), or
+    // `None` when it has no energy value
+    pub fn to_food(&self, id: &str) -> Option<Food> {
+        let per_100g = self.per_100g?;
+        let serving = self.serving.clone().unwrap_or(ServingSize {
+            amount: 100.0,
+            kind: MeasureKind::Mass,
+            household_units: Default::default(),
+        });
+        let mut keywords = keywords_from(&self.name);
+        for word in keywords_from(self.brand.as_deref().unwrap_or_default()) {
+            if !keywords.contains(&word) {
+                keywords.push(word);
+            }
+        }
+        Some(Food::Basic(BasicFood {
+            id: id.to_string(),
+            keywords,
+            nutrients: per_100g * (serving.amount / 100.0),
+            serving: Some(serving),
+            barcodes: vec![self.code.clone()],
+        }))
+    }
+}
+
+// Looks a barcode up in an Open Food Facts export: the JSONL dump (one product per line) or
+// the CSV export, which is tab-separated. Exports run to several gigabytes, so the file is
+// scanned line by line.
+pub fn find_product(path: &Path, barcode: &str) -> Result<Option<OffProduct>, LookupError> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase());
+    match extension.as_deref() {
+        Some("jsonl") | Some("ndjson") | Some("json") => find_in_jsonl(path, barcode),
+        Some("csv") | Some("tsv") => find_in_csv(path, barcode),
+        Some("gz") | Some("zip") | Some("zst") => Err(LookupError::Compressed(path.to_path_buf())),
+        _ => Err(LookupError::Unrecognized(path.to_path_buf())),
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, LookupError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|source| LookupError::Io { path: path.to_path_buf(), source })
+}
+
+fn find_in_jsonl(path: &Path, barcode: &str) -> Result<Option<OffProduct>, LookupError> {
+    let mut reader = open(path)?;
+    // Most lines can be skipped without parsing them; the export may drop leading zeros
+    let needle = barcode.trim_start_matches('0');
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)
+            .map_err(|source| LookupError::Io { path: path.to_path_buf(), source })?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let text = String::from_utf8_lossy(&line);
+        if !text.contains(needle) {
+            continue;
+        }
+        // Lines that aren't valid JSON are skipped like any other non-matching product
+        let Ok(item) = serde_json::from_str::<Value>(&text) else {
+            continue;
+        };
+        let code = match &item["code"] {
+            Value::String(code) => code.clone(),
+            Value::Number(code) => code.to_string(),
+            _ => continue,
+        };
+        if same_product(&code, barcode) {
+            return Ok(Some(OffProduct::from_fields(&code, |name| json_field(&item, name))));
+        }
+    }
+}
+
+// A top-level field, or one under "nutriments", as text
+fn json_field(item: &Value, name: &str) -> Option<String> {
+    let value = match &item[name] {
+        Value::Null => &item["nutriments"][name],
+        value => value,
+    };
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+fn find_in_csv(path: &Path, barcode: &str) -> Result<Option<OffProduct>, LookupError> {
+    let io_error = |source| LookupError::Io { path: path.to_path_buf(), source };
+    // The official export is tab-separated with quotes used as ordinary characters; a
+    // header without tabs means a comma-separated file
+    let mut reader = CsvReader::new(open(path)?, '\t').without_quotes();
+    let mut header = reader.next_record().map_err(io_error)?.unwrap_or_default();
+    if header.len() == 1 && header[0].contains(',') {
+        reader = CsvReader::new(open(path)?, ',');
+        header = reader.next_record().map_err(io_error)?.unwrap_or_default();
+    }
+    let code_column = column(&header, "code")
+        .ok_or_else(|| LookupError::MissingColumn { path: path.to_path_buf(), column: "code".to_string() })?;
+    let columns: Vec<(&str, usize)> = FIELDS.iter()
+        .filter_map(|name| column(&header, name).map(|i| (*name, i)))
+        .collect();
+
+    while let Some(record) = reader.next_record().map_err(io_error)? {
+        let Some(code) = record.get(code_column).map(|code| code.trim()) else {
+            continue;
+        };
+        if code.is_empty() || !same_product(code, barcode) {
+            continue;
+        }
+        return Ok(Some(OffProduct::from_fields(code, |name| {
+            let (_, i) = columns.iter().find(|(field, _)| *field == name)?;
+            record.get(*i).map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+        })));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn product(fields: &[(&str, &str)]) -> OffProduct {
+        let fields: HashMap<&str, &str> = fields.iter().copied().collect();
+        OffProduct::from_fields("3017620422003", |name| fields.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn scales_nutrients_to_the_label_serving() {
+        let spread = product(&[
+            ("product_name", "Hazelnut spread"),
+            ("brands", "Nutella, Ferrero"),
+            ("serving_size", "1 portion (15 g)"),
+            ("energy-kcal_100g", "539"),
+            ("fat_100g", "30.9"),
+            ("sodium_100g", "0.041"),
+        ]);
+        assert_eq!(spread.food_id(), "Hazelnut spread (Nutella)");
+        let Some(Food::Basic(food)) = spread.to_food(&spread.food_id()) else {
+            panic!("expected a basic food");
+        };
+        assert_eq!(food.serving.as_ref().map(|serving| serving.amount), Some(15.0));
+        assert!((food.nutrients.calories - 80.85).abs() < 0.01);
+        assert!((food.nutrients.sodium - 6.15).abs() < 0.01); // 41 mg per 100 g
+        assert_eq!(food.barcodes, ["3017620422003"]);
+    }
+
+    #[test]
+    fn falls_back_to_kilojoules_salt_and_100_g() {
+        let crackers = product(&[("product_name", "Crackers"), ("energy_100g", "2092"), ("salt_100g", "2.5")]);
+        let Some(Food::Basic(food)) = crackers.to_food("Crackers") else {
+            panic!("expected a basic food");
+        };
+        assert_eq!(food.serving.as_ref().map(|serving| serving.amount), Some(100.0));
+        assert!((food.nutrients.calories - 500.0).abs() < 0.01);
+        assert!((food.nutrients.sodium - 1000.0).abs() < 0.01);
+        assert!(product(&[("product_name", "Water")]).to_food("Water").is_none());
+    }
+}
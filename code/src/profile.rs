@@ -1,6 +1,10 @@
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use crate::food::Nutrient;
+use crate::weight::{WeightEntry, WeightSource, weight_trend};
+use crate::storage::{self, StoreError};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Gender {
@@ -26,7 +30,51 @@ pub struct Profile {
     pub activity_level: ActivityLevel,
     pub target_formula: TargetFormula,
     pub daily_overrides: HashMap<String, f32>, // Date -> calorie target override
-    file_path: String,
+    #[serde(default)]
+    pub macro_targets: MacroTargets,
+    #[serde(default)]
+    pub macro_overrides: HashMap<String, MacroTargets>, // Date -> macro target override
+    #[serde(default = "default_meals")]
+    pub meals: Vec<Meal>, // Meal slots in the order they are shown
+    #[serde(default)]
+    pub weights: BTreeMap<String, WeightEntry>, // Date -> weigh-in
+    #[serde(default)]
+    pub weight_source: WeightSource,
+    #[serde(skip)]
+    file_path: String, // Not stored in the file, so the profile can be moved between data directories
+    #[serde(skip)]
+    read_only: bool, // Set when the file failed to load, so it is never overwritten
+    #[serde(skip)]
+    backup_count: usize,
+}
+
+// A meal slot that log entries can be filed under
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Meal {
+    pub name: String,
+    #[serde(default)]
+    pub hours: Option<(u32, u32)>, // Hours of the day [start, end) when this is the default meal
+    #[serde(default)]
+    pub calorie_budget: Option<f32>,
+}
+
+impl Meal {
+    pub fn new(name: &str, hours: Option<(u32, u32)>) -> Self {
+        Meal { name: name.to_string(), hours, calorie_budget: None }
+    }
+
+    fn covers_hour(&self, hour: u32) -> bool {
+        self.hours.is_some_and(|(start, end)| hour >= start && hour < end)
+    }
+}
+
+pub fn default_meals() -> Vec<Meal> {
+    vec![
+        Meal::new("Breakfast", Some((4, 11))),
+        Meal::new("Lunch", Some((11, 16))),
+        Meal::new("Dinner", Some((17, 22))),
+        Meal::new("Snack", None),
+    ]
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -35,8 +83,199 @@ pub enum TargetFormula {
     HarrisBenedict
 }
 
+impl FromStr for Gender {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "male" | "m" => Ok(Gender::Male),
+            "female" | "f" => Ok(Gender::Female),
+            other => Err(format!("unknown gender '{}' (expected male or female)", other)),
+        }
+    }
+}
+
+impl FromStr for ActivityLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "sedentary" => Ok(ActivityLevel::Sedentary),
+            "light" | "lightlyactive" => Ok(ActivityLevel::LightlyActive),
+            "moderate" | "moderatelyactive" => Ok(ActivityLevel::ModeratelyActive),
+            "very" | "veryactive" => Ok(ActivityLevel::VeryActive),
+            "extreme" | "extremelyactive" => Ok(ActivityLevel::ExtremelyActive),
+            other => Err(format!("unknown activity level '{}' (expected sedentary, light, moderate, very or extreme)", other)),
+        }
+    }
+}
+
+impl FromStr for TargetFormula {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "mifflin" | "mifflinstjeor" => Ok(TargetFormula::MifflinStJeor),
+            "harris" | "harrisbenedict" => Ok(TargetFormula::HarrisBenedict),
+            other => Err(format!("unknown target formula '{}' (expected mifflin or harris)", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MacroTarget {
+    Grams(f32),
+    Percent(f32), // Share of the day's calorie target
+}
+
+// Goals for the energy-providing macronutrients. `None` means no goal is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct MacroTargets {
+    pub protein: Option<MacroTarget>,
+    pub carbs: Option<MacroTarget>,
+    pub fat: Option<MacroTarget>,
+}
+
+impl FromStr for MacroTarget {
+    type Err = String;
+
+    // "30%" is a share of the calorie target; "120" or "120g" is grams
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let parsed = match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse().map(MacroTarget::Percent),
+            None => s.trim_end_matches('g').trim().parse().map(MacroTarget::Grams),
+        };
+        parsed.map_err(|_| format!("invalid macro target '{}' (expected e.g. 120g or 30%)", s))
+    }
+}
+
+impl fmt::Display for MacroTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacroTarget::Grams(grams) => write!(f, "{}g", grams),
+            MacroTarget::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl fmt::Display for MacroTargets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let set: Vec<String> = [Nutrient::Protein, Nutrient::Carbs, Nutrient::Fat].iter()
+            .filter_map(|n| self.get(*n).map(|target| format!("{} {}", n.name().to_lowercase(), target)))
+            .collect();
+        if set.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", set.join(", "))
+        }
+    }
+}
+
+impl MacroTargets {
+    pub fn get(&self, nutrient: Nutrient) -> Option<MacroTarget> {
+        match nutrient {
+            Nutrient::Protein => self.protein,
+            Nutrient::Carbs => self.carbs,
+            Nutrient::Fat => self.fat,
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, nutrient: Nutrient, target: Option<MacroTarget>) {
+        match nutrient {
+            Nutrient::Protein => self.protein = target,
+            Nutrient::Carbs => self.carbs = target,
+            Nutrient::Fat => self.fat = target,
+            _ => {},
+        }
+    }
+}
+
+// One profile field together with a value for it, so a change can be applied and undone
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ProfileSetting {
+    Gender(Gender),
+    Age(u32),
+    HeightCm(f32),
+    WeightKg(f32),
+    ActivityLevel(ActivityLevel),
+    TargetFormula(TargetFormula),
+    MacroTargets(MacroTargets), // Default macro targets
+    Meals(Vec<Meal>),
+    WeightSource(WeightSource),
+}
+
+impl ProfileSetting {
+    // Rejects body measurements that give nonsense targets. NaN and infinity would also be
+    // saved as null and stop the profile from loading.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ProfileSetting::Age(0) => Err("age must be at least 1".to_string()),
+            ProfileSetting::HeightCm(height) if !height.is_finite() || *height <= 0.0 =>
+                Err(format!("height must be a positive number of cm, got {}", height)),
+            ProfileSetting::WeightKg(weight) if !weight.is_finite() || *weight <= 0.0 =>
+                Err(format!("weight must be a positive number of kg, got {}", weight)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ProfileSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileSetting::Gender(gender) => write!(f, "gender to {:?}", gender),
+            ProfileSetting::Age(age) => write!(f, "age to {}", age),
+            ProfileSetting::HeightCm(height) => write!(f, "height to {} cm", height),
+            ProfileSetting::WeightKg(weight) => write!(f, "weight to {} kg", weight),
+            ProfileSetting::ActivityLevel(level) => write!(f, "activity level to {:?}", level),
+            ProfileSetting::TargetFormula(formula) => write!(f, "target formula to {:?}", formula),
+            ProfileSetting::MacroTargets(targets) => write!(f, "default macro targets to {}", targets),
+            ProfileSetting::WeightSource(source) => write!(f, "weight used for targets to {}", source.name()),
+            ProfileSetting::Meals(meals) => {
+                let names: Vec<String> = meals.iter().map(|meal| match meal.calorie_budget {
+                    Some(budget) => format!("{} ({:.0} kcal)", meal.name, budget),
+                    None => meal.name.clone(),
+                }).collect();
+                write!(f, "meals to {}", names.join(", "))
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetStatus {
+    Under,
+    OnTarget,
+    Over,
+}
+
+impl TargetStatus {
+    // Anything within 95-105% of the target counts as on target
+    pub fn evaluate(consumed: f32, target: f32) -> TargetStatus {
+        let percentage = if target > 0.0 { (consumed / target) * 100.0 } else { 0.0 };
+        if (95.0..=105.0).contains(&percentage) {
+            TargetStatus::OnTarget
+        } else if percentage < 95.0 {
+            TargetStatus::Under
+        } else {
+            TargetStatus::Over
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TargetStatus::Under => "under",
+            TargetStatus::OnTarget => "on target",
+            TargetStatus::Over => "over",
+        }
+    }
+}
+
 impl Profile {
-    pub fn new(file_path: &str) -> Self {
+    pub fn new(file_path: &str, backup_count: usize) -> Self {
         Profile {
             gender: Gender::Male,
             age: 30,
@@ -45,28 +284,81 @@ impl Profile {
             activity_level: ActivityLevel::ModeratelyActive,
             target_formula: TargetFormula::MifflinStJeor,
             daily_overrides: HashMap::new(),
+            macro_targets: MacroTargets::default(),
+            macro_overrides: HashMap::new(),
+            meals: default_meals(),
+            weights: BTreeMap::new(),
+            weight_source: WeightSource::default(),
             file_path: file_path.to_string(),
+            read_only: false,
+            backup_count,
         }
     }
 
-    pub fn load(&mut self) {
-        if let Ok(data) = fs::read_to_string(&self.file_path) {
-            if let Ok(loaded_profile) = serde_json::from_str(&data) {
-                *self = loaded_profile;
+    pub fn load(&mut self) -> Result<(), StoreError> {
+        let path = self.file_path.clone();
+        self.load_from(&path)
+    }
+
+    // Loads the most recent backup instead; saving afterwards replaces the main file
+    pub fn load_backup(&mut self) -> Result<(), StoreError> {
+        match storage::latest_backup(&self.file_path) {
+            Some(backup) => self.load_from(&backup.path.to_string_lossy()),
+            None => Err(StoreError::NoBackup(self.file_path.clone())),
+        }
+    }
+
+    fn load_from(&mut self, path: &str) -> Result<(), StoreError> {
+        match storage::read_json::<Profile>(path) {
+            Ok(loaded_profile) => {
+                if let Some(loaded_profile) = loaded_profile {
+                    *self = Profile {
+                        file_path: self.file_path.clone(),
+                        backup_count: self.backup_count,
+                        ..loaded_profile
+                    };
+                }
+                self.read_only = false;
+                Ok(())
+            },
+            Err(e) => {
+                self.read_only = true;
+                Err(e)
             }
         }
     }
 
-    pub fn save(&self) {
-        if let Ok(data) = serde_json::to_string_pretty(&self) {
-            let _ = fs::write(&self.file_path, data);
+    pub fn save(&self) -> Result<(), StoreError> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly(self.file_path.clone()));
         }
+        storage::write_json(&self.file_path, &self, self.backup_count)
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    // Weight the target for a date is calculated from. Dates before the first weigh-in, and
+    // the `Profile` source, use the weight stored in the profile.
+    pub fn weight_on(&self, date: &str) -> f32 {
+        match self.weight_source {
+            WeightSource::Profile => None,
+            WeightSource::Latest => self.weights.range(..=date.to_string()).next_back()
+                .map(|(_, entry)| entry.weight_kg),
+            WeightSource::Smoothed => weight_trend(&self.weights).into_iter()
+                .take_while(|point| point.date.as_str() <= date)
+                .last()
+                .map(|point| point.trend_kg),
+        }.unwrap_or(self.weight_kg)
     }
 
-    pub fn calculate_target_calories(&self) -> f32 {
+    // Estimated energy expenditure on a date, ignoring any override
+    pub fn calculate_target_calories(&self, date: &str) -> f32 {
+        let weight_kg = self.weight_on(date);
         let bmr = match self.target_formula {
-            TargetFormula::MifflinStJeor => self.calculate_mifflin_st_jeor(),
-            TargetFormula::HarrisBenedict => self.calculate_harris_benedict(),
+            TargetFormula::MifflinStJeor => self.calculate_mifflin_st_jeor(weight_kg),
+            TargetFormula::HarrisBenedict => self.calculate_harris_benedict(weight_kg),
         };
 
         // Apply activity multiplier
@@ -85,33 +377,102 @@ impl Profile {
             *target
         } else {
             // Otherwise, calculate the target based on profile data
-            self.calculate_target_calories()
+            self.calculate_target_calories(date)
+        }
+    }
+
+    // Finds a meal slot by name, ignoring case
+    pub fn find_meal(&self, name: &str) -> Option<&Meal> {
+        self.meals.iter().find(|meal| meal.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    // The meal to file an entry under when none is chosen: the one whose hours cover the
+    // given hour, otherwise the first meal without hours (a catch-all such as Snack)
+    pub fn meal_for_hour(&self, hour: u32) -> Option<&Meal> {
+        self.meals.iter().find(|meal| meal.covers_hour(hour))
+            .or_else(|| self.meals.iter().find(|meal| meal.hours.is_none()))
+            .or_else(|| self.meals.first())
+    }
+
+    // Meal names in display order
+    pub fn meal_names(&self) -> Vec<String> {
+        self.meals.iter().map(|meal| meal.name.clone()).collect()
+    }
+
+    // Stores a value in the matching field and returns the value it replaced
+    pub fn apply_setting(&mut self, setting: ProfileSetting) -> ProfileSetting {
+        match setting {
+            ProfileSetting::Gender(gender) => ProfileSetting::Gender(std::mem::replace(&mut self.gender, gender)),
+            ProfileSetting::Age(age) => ProfileSetting::Age(std::mem::replace(&mut self.age, age)),
+            ProfileSetting::HeightCm(height) => ProfileSetting::HeightCm(std::mem::replace(&mut self.height_cm, height)),
+            ProfileSetting::WeightKg(weight) => ProfileSetting::WeightKg(std::mem::replace(&mut self.weight_kg, weight)),
+            ProfileSetting::ActivityLevel(level) => ProfileSetting::ActivityLevel(std::mem::replace(&mut self.activity_level, level)),
+            ProfileSetting::TargetFormula(formula) => ProfileSetting::TargetFormula(std::mem::replace(&mut self.target_formula, formula)),
+            ProfileSetting::MacroTargets(targets) => ProfileSetting::MacroTargets(std::mem::replace(&mut self.macro_targets, targets)),
+            ProfileSetting::Meals(meals) => ProfileSetting::Meals(std::mem::replace(&mut self.meals, meals)),
+            ProfileSetting::WeightSource(source) => ProfileSetting::WeightSource(std::mem::replace(&mut self.weight_source, source)),
+        }
+    }
+
+    // The override setters return the previous override, if any, so changes can be undone
+    pub fn set_daily_override(&mut self, date: &str, target: f32) -> Option<f32> {
+        self.daily_overrides.insert(date.to_string(), target)
+    }
+
+    pub fn remove_daily_override(&mut self, date: &str) -> Option<f32> {
+        self.daily_overrides.remove(date)
+    }
+
+    // Target for any nutrient on a date, in the nutrient's own unit. Per-date macro overrides
+    // take precedence over the default macro targets; percentages are converted to grams
+    // using the calorie target for that date.
+    pub fn get_nutrient_target(&self, date: &str, nutrient: Nutrient) -> Option<f32> {
+        if nutrient == Nutrient::Calories {
+            return Some(self.get_daily_target(date));
         }
+
+        let target = self.macro_overrides.get(date)
+            .and_then(|overrides| overrides.get(nutrient))
+            .or_else(|| self.macro_targets.get(nutrient))?;
+
+        match target {
+            MacroTarget::Grams(grams) => Some(grams),
+            MacroTarget::Percent(percent) => nutrient.kcal_per_gram()
+                .map(|kcal| self.get_daily_target(date) * percent / 100.0 / kcal),
+        }
+    }
+
+    pub fn set_weight(&mut self, date: &str, entry: WeightEntry) -> Option<WeightEntry> {
+        self.weights.insert(date.to_string(), entry)
+    }
+
+    pub fn remove_weight(&mut self, date: &str) -> Option<WeightEntry> {
+        self.weights.remove(date)
     }
 
-    pub fn set_daily_override(&mut self, date: &str, target: f32) {
-        self.daily_overrides.insert(date.to_string(), target);
+    pub fn set_macro_override(&mut self, date: &str, targets: MacroTargets) -> Option<MacroTargets> {
+        self.macro_overrides.insert(date.to_string(), targets)
     }
 
-    pub fn remove_daily_override(&mut self, date: &str) {
-        self.daily_overrides.remove(date);
+    pub fn remove_macro_override(&mut self, date: &str) -> Option<MacroTargets> {
+        self.macro_overrides.remove(date)
     }
 
-    fn calculate_mifflin_st_jeor(&self) -> f32 {
+    fn calculate_mifflin_st_jeor(&self, weight_kg: f32) -> f32 {
         let s = match self.gender {
             Gender::Male => 5.0,
             Gender::Female => -161.0,
         };
-        (10.0 * self.weight_kg) + (6.25 * self.height_cm) - (5.0 * self.age as f32) + s
+        (10.0 * weight_kg) + (6.25 * self.height_cm) - (5.0 * self.age as f32) + s
     }
 
-    fn calculate_harris_benedict(&self) -> f32 {
+    fn calculate_harris_benedict(&self, weight_kg: f32) -> f32 {
         match self.gender {
             Gender::Male => {
-                66.47 + (13.75 * self.weight_kg) + (5.003 * self.height_cm) - (6.755 * self.age as f32)
+                66.47 + (13.75 * weight_kg) + (5.003 * self.height_cm) - (6.755 * self.age as f32)
             },
             Gender::Female => {
-                655.1 + (9.563 * self.weight_kg) + (1.850 * self.height_cm) - (4.676 * self.age as f32)
+                655.1 + (9.563 * weight_kg) + (1.850 * self.height_cm) - (4.676 * self.age as f32)
             }
         }
     }
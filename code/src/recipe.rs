@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use crate::food::{Food, Quantity, is_standard_unit, keywords_from};
+use crate::search::{MatchMode, SearchHit, search, search_words, singular};
+
+// Most candidate foods offered for one ingredient
+pub const MAX_CANDIDATES: usize = 5;
+
+// Measures that count pieces rather than weigh or hold something. They convert when the
+// matched food defines them as household units (e.g. slice=30).
+const COUNT_UNITS: [&str; 16] = [
+    "slice", "clove", "can", "piece", "pinch", "handful", "scoop", "bar",
+    "packet", "stick", "sprig", "bunch", "head", "fillet", "leaf", "dash",
+];
+
+// Words about preparation rather than the food, left out when matching
+const PREPARATION_WORDS: [&str; 18] = [
+    "chopped", "diced", "sliced", "minced", "grated", "shredded", "crushed", "peeled", "fresh",
+    "large", "small", "medium", "finely", "roughly", "thinly", "taste", "optional", "about",
+];
+
+const NUMBER_WORDS: [(&str, f32); 14] = [
+    ("a", 1.0), ("an", 1.0), ("one", 1.0), ("two", 2.0), ("three", 3.0), ("four", 4.0),
+    ("five", 5.0), ("six", 6.0), ("seven", 7.0), ("eight", 8.0), ("nine", 9.0), ("ten", 10.0),
+    ("half", 0.5), ("dozen", 12.0),
+];
+
+const UNICODE_FRACTIONS: [(char, f32); 9] = [
+    ('½', 0.5), ('⅓', 1.0 / 3.0), ('⅔', 2.0 / 3.0), ('¼', 0.25), ('¾', 0.75),
+    ('⅕', 0.2), ('⅛', 0.125), ('⅜', 0.375), ('⅝', 0.625),
+];
+
+// One line of an ingredient list, e.g. "1 1/2 cups rice" or "2 slices bread"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ingredient {
+    pub line: String, // As written
+    pub amount: Option<f32>, // None when the line gives no amount, as in "salt to taste"
+    pub unit: Option<String>, // As written; None means servings of the food
+    pub name: String,
+}
+
+impl Ingredient {
+    // Parses one ingredient. Notes in parentheses are ignored. Returns `None` for lines with
+    // nothing to match, such as an empty line or an "Ingredients:" heading.
+    pub fn parse(line: &str) -> Option<Ingredient> {
+        let line = line.trim().trim_start_matches(['-', '*', '•', '·']).trim();
+        if line.is_empty() || line.ends_with(':') {
+            return None;
+        }
+        let tokens = tokenize(line);
+        let (amount, mut used) = match parse_amount(&tokens) {
+            Some((amount, used)) => (Some(amount), used),
+            None => (None, 0),
+        };
+
+        let mut unit = None;
+        if amount.is_some() {
+            let word = tokens.get(used).map(|word| word.to_lowercase()).unwrap_or_default();
+            if word == "fl" && tokens.get(used + 1).is_some_and(|next| next.eq_ignore_ascii_case("oz")) {
+                unit = Some("fl oz".to_string());
+                used += 2;
+            } else if !word.is_empty() && (is_standard_unit(&word) || COUNT_UNITS.contains(&singular(&word).as_str())) {
+                unit = Some(word.trim_end_matches('.').to_string());
+                used += 1;
+            }
+            if tokens.get(used).is_some_and(|word| word.eq_ignore_ascii_case("of")) {
+                used += 1;
+            }
+        }
+
+        let name = tokens[used..].join(" ").trim_matches(|c: char| c == ',' || c == '.' || c.is_whitespace()).to_string();
+        if name.is_empty() {
+            return None;
+        }
+        Some(Ingredient { line: line.to_string(), amount, unit, name })
+    }
+
+    // The amount as a quantity, or `None` when the line gives no amount
+    pub fn quantity(&self) -> Option<Quantity> {
+        let amount = self.amount?;
+        Some(match &self.unit {
+            Some(unit) => Quantity { amount, unit: unit.clone() },
+            None => Quantity::servings(amount),
+        })
+    }
+}
+
+// Parses a pasted recipe or ingredient file. Ingredients go one per line or are separated by
+// commas; a comma only starts a new ingredient when an amount follows it, so
+// "150 g salmon, skinless" stays one ingredient.
+pub fn parse_recipe(text: &str) -> Vec<Ingredient> {
+    let mut ingredients = Vec::new();
+    for line in text.lines().flat_map(|line| line.split(';')) {
+        let mut current = String::new();
+        for part in line.split(',') {
+            if !current.is_empty() {
+                if part.trim_start().starts_with(|c: char| c.is_ascii_digit() || fraction_char(c).is_some()) {
+                    ingredients.extend(Ingredient::parse(&current));
+                    current.clear();
+                } else {
+                    current.push(',');
+                }
+            }
+            current.push_str(part);
+        }
+        ingredients.extend(Ingredient::parse(&current));
+    }
+    ingredients
+}
+
+// Splits a line into words, dropping notes in parentheses and separating amounts written
+// against their unit ("150g", "1½")
+fn tokenize(line: &str) -> Vec<String> {
+    let mut text = String::new();
+    let mut depth = 0;
+    let mut previous = ' ';
+    for c in line.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth > 0 => {},
+            _ => {
+                let glued = previous.is_ascii_digit() && (c.is_alphabetic() || fraction_char(c).is_some());
+                if glued {
+                    text.push(' ');
+                }
+                text.push(c);
+                previous = c;
+            },
+        }
+    }
+    text.split_whitespace().map(|word| word.to_string()).collect()
+}
+
+fn fraction_char(c: char) -> Option<f32> {
+    UNICODE_FRACTIONS.iter().find(|(fraction, _)| *fraction == c).map(|(_, value)| *value)
+}
+
+// A number such as "2", "1.5", "1/2", "½", "2-3" (the midpoint) or "two"
+fn parse_number(word: &str) -> Option<f32> {
+    let word = word.to_lowercase();
+    if let Some((low, high)) = word.split_once('-') {
+        return Some((parse_number(low)? + parse_number(high)?) / 2.0);
+    }
+    if let Some((numerator, denominator)) = word.split_once('/') {
+        let denominator: f32 = denominator.parse().ok()?;
+        return (denominator > 0.0).then_some(numerator.parse::<f32>().ok()? / denominator);
+    }
+    let mut chars = word.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if let Some(value) = fraction_char(c) {
+            return Some(value);
+        }
+    }
+    word.parse::<f32>().ok()
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .or_else(|| NUMBER_WORDS.iter().find(|(name, _)| *name == word).map(|(_, value)| *value))
+}
+
+// The amount at the start of a line and how many words it took: handles mixed numbers
+// ("1 1/2", "1 ½") and ranges ("2 - 3", "2 to 3"), which count as their midpoint
+fn parse_amount(tokens: &[String]) -> Option<(f32, usize)> {
+    let mut amount = parse_number(tokens.first()?)?;
+    let mut used = 1;
+    let is_fraction = |word: &String| word.contains('/') || word.chars().any(|c| fraction_char(c).is_some());
+    if amount.fract() == 0.0 {
+        if let Some(fraction) = tokens.get(1).filter(|word| is_fraction(word)).and_then(|word| parse_number(word)) {
+            amount += fraction;
+            used = 2;
+        }
+    }
+    if let (Some(separator), Some(high)) = (tokens.get(used), tokens.get(used + 1).and_then(|word| parse_number(word))) {
+        if ["-", "to", "or"].contains(&separator.to_lowercase().as_str()) {
+            amount = (amount + high) / 2.0;
+            used += 2;
+        }
+    }
+    Some((amount, used))
+}
+
+// The words of a name used for matching
+fn match_words(name: &str) -> Vec<String> {
+    keywords_from(name).into_iter()
+        .filter(|word| !PREPARATION_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+// An ingredient with the foods it may be. `food_id` is set when one food is clearly meant;
+// the match can be used without asking when `servings` is set too.
+#[derive(Debug, Clone)]
+pub struct IngredientMatch {
+    pub ingredient: Ingredient,
+    pub candidates: Vec<String>, // Food ids, best first
+    pub food_id: Option<String>,
+    pub servings: Option<f32>, // Of `food_id`, when the quantity converts
+}
+
+// Matches an ingredient with the food search. A food is clearly meant when it is the only one
+// named like the ingredient, or the only one whose name words all appear in the ingredient and
+// whose name and keywords hold every word of it. Notes after a comma, as in "carrots, chopped",
+// are left out.
+pub fn match_ingredient(ingredient: Ingredient, foods: &HashMap<String, Food>) -> IngredientMatch {
+    let name = ingredient.name.split(',').next().unwrap_or_default();
+    let query = match_words(name).join(" ");
+    let words = search_words(&query);
+    let hits = search(foods, &query, MatchMode::Any);
+
+    let complete = |hit: &&SearchHit| hit.exact_terms == words.len()
+        && search_words(hit.food.id()).iter().all(|word| words.contains(word));
+    let exact: Vec<&SearchHit> = hits.iter().filter(|hit| hit.exact).collect();
+    let complete: Vec<&SearchHit> = hits.iter().filter(complete).collect();
+    let food_id = match (exact.as_slice(), complete.as_slice()) {
+        ([hit], _) | ([], [hit]) => Some(hit.food.id()),
+        _ => None,
+    };
+    let servings = food_id
+        .and_then(|id| foods.get(id)?.servings_for(&ingredient.quantity()?).ok());
+    IngredientMatch {
+        candidates: hits.iter().take(MAX_CANDIDATES).map(|hit| hit.food.id().to_string()).collect(),
+        food_id: food_id.map(|id| id.to_string()),
+        servings,
+        ingredient,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Ingredient {
+        Ingredient::parse(line).unwrap()
+    }
+
+    #[test]
+    fn parses_mixed_numbers_and_fractions() {
+        let rice = parse("1 1/2 cups rice");
+        assert_eq!((rice.amount, rice.unit.as_deref(), rice.name.as_str()), (Some(1.5), Some("cups"), "rice"));
+        assert_eq!(parse("1 ½ cups rice").amount, Some(1.5));
+        assert_eq!(parse("1½ cups rice").amount, Some(1.5));
+        assert_eq!(parse("3/4 tsp salt").amount, Some(0.75));
+    }
+
+    #[test]
+    fn ranges_count_as_their_midpoint() {
+        let garlic = parse("2-3 cloves garlic");
+        assert_eq!((garlic.amount, garlic.unit.as_deref(), garlic.name.as_str()), (Some(2.5), Some("cloves"), "garlic"));
+        let eggs = parse("2 to 3 eggs");
+        assert_eq!((eggs.amount, eggs.unit, eggs.name.as_str()), (Some(2.5), None, "eggs"));
+    }
+
+    #[test]
+    fn reads_units_words_and_missing_amounts() {
+        let salmon = parse("150g salmon (skin on)");
+        assert_eq!((salmon.amount, salmon.unit.as_deref(), salmon.name.as_str()), (Some(150.0), Some("g"), "salmon"));
+        assert_eq!(parse("two slices of bread").quantity(), Some(Quantity { amount: 2.0, unit: "slices".to_string() }));
+        let salt = parse("salt to taste");
+        assert_eq!((salt.amount, salt.quantity()), (None, None));
+        assert!(Ingredient::parse("Ingredients:").is_none());
+    }
+
+    #[test]
+    fn commas_split_only_before_an_amount() {
+        let ingredients = parse_recipe("150 g salmon, skinless");
+        assert_eq!(ingredients.len(), 1);
+        assert_eq!(ingredients[0].name, "salmon, skinless");
+
+        let names: Vec<String> = parse_recipe("2 cups rice, 150 g salmon, skinless; 1 tbsp olive oil\n- 1 lemon")
+            .into_iter()
+            .map(|ingredient| ingredient.name)
+            .collect();
+        assert_eq!(names, ["rice", "salmon, skinless", "olive oil", "lemon"]);
+    }
+}
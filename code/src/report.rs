@@ -0,0 +1,206 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use chrono::{Datelike, Duration, NaiveDate};
+use crate::food::{Food, Nutrients};
+use crate::log::{DailyLog, ReportMode, date_range};
+use crate::profile::{Profile, TargetStatus};
+use crate::weight::{KCAL_PER_KG, TrendPoint, weight_trend};
+
+// Food rankings list this many foods unless asked for more
+pub const DEFAULT_TOP_FOODS: usize = 10;
+
+// Days covered by a weight report when no start date is given
+pub const WEIGHT_REPORT_DAYS: i64 = 30;
+
+// One logged day in a range report
+#[derive(Serialize, Debug, Clone)]
+pub struct DayTotal {
+    pub date: String,
+    pub calories: f32,
+    pub target: f32,
+    pub percentage: f32, // Calories as a percentage of the target
+    pub status: TargetStatus,
+}
+
+// How much one food added to a range
+#[derive(Serialize, Debug, Clone)]
+pub struct FoodContribution {
+    pub food_id: String,
+    pub calories: f32,
+    pub servings: f32,
+    pub entries: usize,
+    pub share: f32, // Percentage of all calories in the range
+}
+
+// Intake over a date range. Averages and counts only cover days with log entries, so a
+// day that simply wasn't logged doesn't count as a day under target.
+#[derive(Serialize, Debug, Clone)]
+pub struct RangeReport {
+    pub start: String,
+    pub end: String,
+    pub mode: ReportMode,
+    pub days_in_range: usize,
+    pub days_logged: usize,
+    pub average: Nutrients, // Per logged day
+    pub average_target: f32,
+    pub on_target: usize,
+    pub under: usize,
+    pub over: usize,
+    pub best_day: Option<DayTotal>, // Closest to its target
+    pub worst_day: Option<DayTotal>, // Furthest from its target
+    pub days: Vec<DayTotal>,
+    pub foods: Vec<FoodContribution>, // Most calories first
+}
+
+impl RangeReport {
+    // Builds the report for `start` to `end` inclusive, or `None` if either date is invalid
+    // or the range runs backwards
+    pub fn build(start: &str, end: &str, log: &DailyLog, profile: &Profile, foods: &HashMap<String, Food>, mode: ReportMode) -> Option<RangeReport> {
+        let first = NaiveDate::parse_from_str(start, "%Y-%m-%d").ok()?;
+        let last = NaiveDate::parse_from_str(end, "%Y-%m-%d").ok()?;
+        if last < first {
+            return None;
+        }
+
+        let mut dates: Vec<&String> = log.get_all_dates().into_iter()
+            .filter(|date| date.as_str() >= start && date.as_str() <= end && log.has_entries_for_date(date))
+            .collect();
+        dates.sort();
+
+        let days: Vec<DayTotal> = dates.iter().map(|date| {
+            let calories = log.get_total_calories(date, foods, mode);
+            let target = profile.get_daily_target(date);
+            DayTotal {
+                date: date.to_string(),
+                calories,
+                target,
+                percentage: if target > 0.0 { calories / target * 100.0 } else { 0.0 },
+                status: TargetStatus::evaluate(calories, target),
+            }
+        }).collect();
+
+        let mut totals = Nutrients::default();
+        let mut contributions: HashMap<&str, FoodContribution> = HashMap::new();
+        for date in &dates {
+            for entry in log.get_log_entries(date) {
+                let nutrients = entry.nutrients(foods, mode);
+                totals += nutrients;
+                let contribution = contributions.entry(&entry.food_id).or_insert_with(|| FoodContribution {
+                    food_id: entry.food_id.clone(),
+                    calories: 0.0,
+                    servings: 0.0,
+                    entries: 0,
+                    share: 0.0,
+                });
+                contribution.calories += nutrients.calories;
+                contribution.servings += entry.servings;
+                contribution.entries += 1;
+            }
+        }
+        let mut foods: Vec<FoodContribution> = contributions.into_values()
+            .map(|food| FoodContribution {
+                share: if totals.calories > 0.0 { food.calories / totals.calories * 100.0 } else { 0.0 },
+                ..food
+            })
+            .collect();
+        foods.sort_by(|a, b| b.calories.total_cmp(&a.calories).then_with(|| a.food_id.cmp(&b.food_id)));
+
+        let logged = days.len();
+        let per_day = if logged > 0 { 1.0 / logged as f32 } else { 0.0 };
+        let count = |status: TargetStatus| days.iter().filter(|day| day.status == status).count();
+        let deviation = |day: &&DayTotal| (day.percentage - 100.0).abs();
+        Some(RangeReport {
+            start: start.to_string(),
+            end: end.to_string(),
+            mode,
+            days_in_range: (last - first).num_days() as usize + 1,
+            days_logged: logged,
+            average: totals * per_day,
+            average_target: days.iter().map(|day| day.target).sum::<f32>() * per_day,
+            on_target: count(TargetStatus::OnTarget),
+            under: count(TargetStatus::Under),
+            over: count(TargetStatus::Over),
+            best_day: days.iter().min_by(|a, b| deviation(a).total_cmp(&deviation(b))).cloned(),
+            worst_day: days.iter().max_by(|a, b| deviation(a).total_cmp(&deviation(b))).cloned(),
+            days,
+            foods,
+        })
+    }
+}
+
+// Weight over a date range next to the intake logged in it. Energy balance is estimated two
+// ways: logged intake minus the calculated expenditure, and from the change in the smoothed
+// trend. When they disagree, `implied_expenditure` is what the trend says was actually burned.
+#[derive(Serialize, Debug, Clone)]
+pub struct WeightReport {
+    pub start: String,
+    pub end: String,
+    pub points: Vec<TrendPoint>, // Weigh-ins in the range, smoothed over the whole history
+    pub trend_change_kg: Option<f32>, // From the first to the last weigh-in in the range
+    pub weekly_rate_kg: Option<f32>,
+    pub days_logged: usize,
+    pub average_intake: Option<f32>, // kcal per logged day
+    pub estimated_expenditure: f32, // kcal per day from the target formula, without overrides
+    pub balance_from_intake: Option<f32>, // kcal per day, negative for a deficit
+    pub balance_from_trend: Option<f32>,
+    pub implied_expenditure: Option<f32>,
+}
+
+impl WeightReport {
+    pub fn build(start: &str, end: &str, log: &DailyLog, profile: &Profile, foods: &HashMap<String, Food>, mode: ReportMode) -> Option<WeightReport> {
+        let dates = date_range(start, end)?;
+        let points: Vec<TrendPoint> = weight_trend(&profile.weights).into_iter()
+            .filter(|point| point.date.as_str() >= start && point.date.as_str() <= end)
+            .collect();
+
+        let (trend_change_kg, balance_from_trend, weekly_rate_kg) = match (points.first(), points.last()) {
+            (Some(first), Some(last)) if first.date != last.date => {
+                let days = date_range(&first.date, &last.date).map_or(1, |days| days.len() - 1) as f32;
+                let change = last.trend_kg - first.trend_kg;
+                (Some(change), Some(change * KCAL_PER_KG / days), Some(change / days * 7.0))
+            },
+            _ => (None, None, None),
+        };
+
+        let logged: Vec<&String> = dates.iter().filter(|date| log.has_entries_for_date(date)).collect();
+        let average_intake = (!logged.is_empty()).then(|| {
+            logged.iter().map(|date| log.get_total_calories(date, foods, mode)).sum::<f32>() / logged.len() as f32
+        });
+        let estimated_expenditure = dates.iter()
+            .map(|date| profile.calculate_target_calories(date))
+            .sum::<f32>() / dates.len() as f32;
+
+        Some(WeightReport {
+            start: start.to_string(),
+            end: end.to_string(),
+            points,
+            trend_change_kg,
+            weekly_rate_kg,
+            days_logged: logged.len(),
+            average_intake,
+            estimated_expenditure,
+            balance_from_intake: average_intake.map(|intake| intake - estimated_expenditure),
+            balance_from_trend,
+            implied_expenditure: average_intake.zip(balance_from_trend).map(|(intake, balance)| intake - balance),
+        })
+    }
+}
+
+// Monday to Sunday of the week containing the date
+pub fn week_of(date: &str) -> Option<(String, String)> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+    Some((monday.format("%Y-%m-%d").to_string(), (monday + Duration::days(6)).format("%Y-%m-%d").to_string()))
+}
+
+// First to last day of the calendar month containing the date
+pub fn month_of(date: &str) -> Option<(String, String)> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let first = date.with_day(1)?;
+    let next = if first.month() == 12 {
+        NaiveDate::from_ymd_opt(first.year() + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(first.year(), first.month() + 1, 1)?
+    };
+    Some((first.format("%Y-%m-%d").to_string(), (next - Duration::days(1)).format("%Y-%m-%d").to_string()))
+}
@@ -0,0 +1,241 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeSet, HashMap};
+use chrono::{Datelike, Local, NaiveDate, Weekday};
+use crate::food::{Food, Quantity, compute_nutrients};
+use crate::log::{DailyLog, LogEntry};
+use crate::storage::{self, StoreError};
+
+const WEEK: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+// A food that is logged automatically on certain days of the week, e.g. "1 serving of Milk
+// every weekday". Entries are generated the first time each due date is opened.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduleRule {
+    pub id: u64,
+    pub food_id: String,
+    pub quantity: Quantity,
+    #[serde(default)]
+    pub meal: Option<String>,
+    pub days: Vec<String>, // Weekday abbreviations, "Mon" to "Sun"
+    pub start: String, // First date the rule applies to
+    #[serde(default)]
+    pub end: Option<String>, // Last date, if the rule runs out
+    #[serde(default)]
+    pub handled: BTreeSet<String>, // Dates already generated or skipped; they are never generated again
+}
+
+impl ScheduleRule {
+    pub fn new(food_id: &str, quantity: Quantity, meal: Option<String>, days: Vec<Weekday>, start: &str, end: Option<String>) -> Self {
+        ScheduleRule {
+            id: 0,
+            food_id: food_id.to_string(),
+            quantity,
+            meal,
+            days: WEEK.iter().filter(|day| days.contains(day)).map(|day| day.to_string()).collect(),
+            start: start.to_string(),
+            end,
+            handled: BTreeSet::new(),
+        }
+    }
+
+    // Whether the rule falls on the date, whether or not it has been handled yet
+    pub fn falls_on(&self, date: &str) -> bool {
+        let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") else {
+            return false;
+        };
+        let weekday = day.weekday().to_string();
+        date >= self.start.as_str()
+            && self.end.as_deref().is_none_or(|end| date <= end)
+            && self.days.contains(&weekday)
+    }
+
+    // "every weekday", "every day", "Mon/Wed/Fri", ...
+    pub fn days_label(&self) -> String {
+        let weekdays = WEEK[..5].iter().map(|day| day.to_string()).collect::<Vec<_>>();
+        let weekend = WEEK[5..].iter().map(|day| day.to_string()).collect::<Vec<_>>();
+        if self.days.len() == 7 {
+            "every day".to_string()
+        } else if self.days == weekdays {
+            "every weekday".to_string()
+        } else if self.days == weekend {
+            "every weekend day".to_string()
+        } else {
+            self.days.join("/")
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let meal = self.meal.as_deref().map(|meal| format!(" for {}", meal.to_lowercase())).unwrap_or_default();
+        let until = self.end.as_deref().map(|end| format!(" until {}", end)).unwrap_or_default();
+        format!("{} of {} {}{} from {}{}", self.quantity, self.food_id, self.days_label(), meal, self.start, until)
+    }
+}
+
+// Parses "daily", "weekdays", "weekends" or a list of days such as "mon,wed,fri" or "Mon/Thu"
+pub fn parse_days(input: &str) -> Option<Vec<Weekday>> {
+    match input.trim().to_lowercase().as_str() {
+        "daily" | "every day" | "everyday" => Some(WEEK.to_vec()),
+        "weekdays" => Some(WEEK[..5].to_vec()),
+        "weekends" => Some(WEEK[5..].to_vec()),
+        list => {
+            let days: Vec<Weekday> = list.split([',', '/', ' '])
+                .filter(|day| !day.is_empty())
+                .map(|day| day.parse::<Weekday>().ok())
+                .collect::<Option<_>>()?;
+            if days.is_empty() { None } else { Some(days) }
+        }
+    }
+}
+
+// What applying the schedule to some dates did
+#[derive(Debug, Default)]
+pub struct Applied {
+    pub added: usize, // Entries generated
+    pub failures: Vec<String>, // Rules that could not be applied, e.g. because their food is gone
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Schedule {
+    rules: Vec<ScheduleRule>,
+    file_path: String,
+    read_only: bool, // Set when the file failed to load, so it is never overwritten
+    backup_count: usize,
+}
+
+impl Schedule {
+    pub fn new(file_path: &str, backup_count: usize) -> Self {
+        Schedule {
+            rules: Vec::new(),
+            file_path: file_path.to_string(),
+            read_only: false,
+            backup_count,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<(), StoreError> {
+        let path = self.file_path.clone();
+        self.load_from(&path)
+    }
+
+    // Loads the most recent backup instead; saving afterwards replaces the main file
+    pub fn load_backup(&mut self) -> Result<(), StoreError> {
+        match storage::latest_backup(&self.file_path) {
+            Some(backup) => self.load_from(&backup.path.to_string_lossy()),
+            None => Err(StoreError::NoBackup(self.file_path.clone())),
+        }
+    }
+
+    fn load_from(&mut self, path: &str) -> Result<(), StoreError> {
+        match storage::read_json::<Vec<ScheduleRule>>(path) {
+            Ok(loaded_rules) => {
+                self.rules = loaded_rules.unwrap_or_default();
+                self.read_only = false;
+                Ok(())
+            },
+            Err(e) => {
+                self.read_only = true;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), StoreError> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly(self.file_path.clone()));
+        }
+        storage::write_json(&self.file_path, &self.rules, self.backup_count)
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn rules(&self) -> &[ScheduleRule] {
+        &self.rules
+    }
+
+    // Adds a rule and returns the id it was given
+    pub fn add_rule(&mut self, mut rule: ScheduleRule) -> u64 {
+        rule.id = self.rules.iter().map(|rule| rule.id + 1).max().unwrap_or(1);
+        let id = rule.id;
+        self.rules.push(rule);
+        id
+    }
+
+    // Ids of the rules that log a food
+    pub fn rules_for_food(&self, food_id: &str) -> Vec<u64> {
+        self.rules.iter().filter(|rule| rule.food_id == food_id).map(|rule| rule.id).collect()
+    }
+
+    // Points the given rules at another food, used when a food is renamed
+    pub fn rename_rules(&mut self, ids: &[u64], food_id: &str) {
+        for rule in self.rules.iter_mut().filter(|rule| ids.contains(&rule.id)) {
+            rule.food_id = food_id.to_string();
+        }
+    }
+
+    // Entries the rule already generated stay in the log
+    pub fn remove_rule(&mut self, id: u64) -> Option<ScheduleRule> {
+        let index = self.rules.iter().position(|rule| rule.id == id)?;
+        Some(self.rules.remove(index))
+    }
+
+    // Puts back a rule that was taken out, keeping its id. Refuses if the id has been reused.
+    pub fn restore_rule(&mut self, rule: ScheduleRule) -> bool {
+        if self.rules.iter().any(|existing| existing.id == rule.id) {
+            return false;
+        }
+        let index = self.rules.partition_point(|existing| existing.id < rule.id);
+        self.rules.insert(index, rule);
+        true
+    }
+
+    // Marks a date as handled for a rule, so nothing is generated there, or clears the mark.
+    // Returns whether the date was handled before, or `None` if there is no such rule.
+    pub fn set_handled(&mut self, id: u64, date: &str, handled: bool) -> Option<bool> {
+        let rule = self.rules.iter_mut().find(|rule| rule.id == id)?;
+        Some(if handled { !rule.handled.insert(date.to_string()) } else { rule.handled.remove(date) })
+    }
+
+    // Logs the entries that are due on the given dates and haven't been generated or skipped
+    // yet. A rule whose food can't be logged is reported and tried again next time.
+    pub fn apply(&mut self, dates: &[String], log: &mut DailyLog, foods: &HashMap<String, Food>) -> Applied {
+        let mut applied = Applied::default();
+        let logged_at = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        for rule in self.rules.iter_mut() {
+            for date in dates {
+                if !rule.falls_on(date) || rule.handled.contains(date) {
+                    continue;
+                }
+                let Some(food) = foods.get(&rule.food_id) else {
+                    applied.failures.push(format!("Scheduled rule #{}: food '{}' no longer exists", rule.id, rule.food_id));
+                    break;
+                };
+                let servings = match food.servings_for(&rule.quantity) {
+                    Ok(servings) => servings,
+                    Err(e) => {
+                        applied.failures.push(format!("Scheduled rule #{}: {}", rule.id, e));
+                        break;
+                    }
+                };
+                log.add_entry(date, LogEntry {
+                    id: 0,
+                    logged_at: Some(logged_at.clone()),
+                    food_id: rule.food_id.clone(),
+                    servings,
+                    quantity: if rule.quantity.is_servings() { None } else { Some(rule.quantity.clone()) },
+                    snapshot: Some(compute_nutrients(food, foods)),
+                    meal: rule.meal.clone(),
+                    rule: Some(rule.id),
+                });
+                rule.handled.insert(date.clone());
+                applied.added += 1;
+            }
+        }
+        applied
+    }
+}
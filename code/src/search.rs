@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use crate::food::{Food, keywords_from};
+
+// A keyword match counts for less than the same match in the food's name
+const KEYWORD_WEIGHT: f32 = 0.8;
+
+// Extra score for foods whose name is mostly the query's words, so "rice" ranks "Rice" above
+// "Rice Pudding", and for a food named exactly like the query
+const COVERAGE_BONUS: f32 = 0.2;
+const EXACT_BONUS: f32 = 0.5;
+
+// Whether a food must match every word of the query or just one of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchMode {
+    Any,
+    All,
+}
+
+// A food found by a search
+#[derive(Debug, Clone)]
+pub struct SearchHit<'a> {
+    pub food: &'a Food,
+    pub score: f32, // Higher is better
+    pub exact: bool, // The food's name is the query, ignoring case, word order and plurals
+    pub exact_terms: usize, // Query words found whole in the name or keywords, not as a typo or prefix
+}
+
+// Drops a plural ending so "eggs", "tomatoes" and "slices" match "egg", "tomato" and "slice"
+pub fn singular(word: &str) -> String {
+    if let Some(stem) = word.strip_suffix("es").filter(|stem| stem.ends_with(['s', 'x', 'h', 'o'])) {
+        return stem.to_string();
+    }
+    match word.strip_suffix('s') {
+        Some(stem) if stem.len() >= 3 && !stem.ends_with(['s', 'u', 'i']) => stem.to_string(),
+        _ => word.to_string(),
+    }
+}
+
+// The words searched on: lowercase, without plural endings or filler words such as "with"
+pub fn search_words(text: &str) -> Vec<String> {
+    keywords_from(text).iter().map(|word| singular(word)).collect()
+}
+
+// Searches foods by id and keywords. Matching ignores case and plurals, accepts the start of
+// a word ("banan") and tolerates a typo or two in longer words ("bananna"). Results are
+// ranked best first; equal scores are ordered by id.
+pub fn search<'a>(foods: &'a HashMap<String, Food>, query: &str, mode: MatchMode) -> Vec<SearchHit<'a>> {
+    let terms = search_words(query);
+    if terms.is_empty() {
+        return Vec::new();
+    }
+    let mut hits: Vec<SearchHit> = foods.values()
+        .filter_map(|food| score_food(food, &terms, mode))
+        .collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.food.id().cmp(b.food.id())));
+    hits
+}
+
+fn score_food<'a>(food: &'a Food, terms: &[String], mode: MatchMode) -> Option<SearchHit<'a>> {
+    let keywords = match food {
+        Food::Basic(basic) => &basic.keywords,
+        Food::Composite(composite) => &composite.keywords,
+    };
+    let name_words = search_words(food.id());
+    let keyword_words: Vec<String> = keywords.iter().flat_map(|keyword| search_words(keyword)).collect();
+
+    let mut total = 0.0;
+    let mut matched = 0;
+    let mut exact_terms = 0;
+    for term in terms {
+        let best = |words: &[String]| words.iter().map(|word| word_score(term, word)).fold(0.0, f32::max);
+        let score = best(&name_words).max(best(&keyword_words) * KEYWORD_WEIGHT);
+        if score > 0.0 {
+            matched += 1;
+        }
+        if name_words.contains(term) || keyword_words.contains(term) {
+            exact_terms += 1;
+        }
+        total += score;
+    }
+    if matched == 0 || (mode == MatchMode::All && matched < terms.len()) {
+        return None;
+    }
+
+    let covered = name_words.iter().filter(|word| terms.contains(word)).count();
+    let coverage = covered as f32 / name_words.len().max(1) as f32;
+    let exact = covered == name_words.len() && terms.iter().all(|term| name_words.contains(term));
+    let score = total / terms.len() as f32
+        + COVERAGE_BONUS * coverage
+        + if exact { EXACT_BONUS } else { 0.0 };
+    Some(SearchHit { food, score, exact, exact_terms })
+}
+
+// How well one query word matches one word of a food: 1.0 for the same word, less for the
+// start of a word, a word inside a longer one ("berry" in "strawberry") or a typo
+fn word_score(term: &str, word: &str) -> f32 {
+    if term == word {
+        return 1.0;
+    }
+    let (term_len, word_len) = (term.chars().count(), word.chars().count());
+    if term_len >= 2 && word.starts_with(term) {
+        return 0.5 + 0.4 * term_len as f32 / word_len as f32;
+    }
+    if term_len >= 3 && word.contains(term) {
+        return 0.5;
+    }
+    // Longer words are allowed more typos
+    let allowed = match term_len {
+        0..=3 => 0,
+        4..=6 => 1,
+        _ => 2,
+    };
+    if allowed > 0 && term_len.abs_diff(word_len) <= allowed {
+        let distance = edit_distance(term, word);
+        if distance <= allowed {
+            return 0.7 - 0.15 * distance as f32;
+        }
+    }
+    0.0
+}
+
+// Edits (insertions, deletions, substitutions and swaps of neighbouring letters) that turn
+// one word into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{BasicFood, Nutrients};
+
+    fn foods(ids: &[&str]) -> HashMap<String, Food> {
+        ids.iter().map(|id| {
+            let food = Food::Basic(BasicFood {
+                id: id.to_string(),
+                keywords: vec![id.to_string()],
+                nutrients: Nutrients::default(),
+                serving: None,
+                barcodes: Vec::new(),
+            });
+            (id.to_string(), food)
+        }).collect()
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<String> {
+        hits.iter().map(|hit| hit.food.id().to_string()).collect()
+    }
+
+    #[test]
+    fn counts_edits_and_swaps() {
+        assert_eq!(edit_distance("banana", "banana"), 0);
+        assert_eq!(edit_distance("bananna", "banana"), 1);
+        assert_eq!(edit_distance("chiken", "chicken"), 1);
+        assert_eq!(edit_distance("chikcen", "chicken"), 1);
+        assert_eq!(edit_distance("rice", "bread"), 4);
+    }
+
+    #[test]
+    fn scores_prefixes_and_typos_below_exact_words() {
+        assert_eq!(word_score("banana", "banana"), 1.0);
+        let prefix = word_score("banan", "banana");
+        let typo = word_score("bananna", "banana");
+        assert!(prefix > 0.0 && prefix < 1.0);
+        assert!(typo > 0.0 && typo < 1.0);
+        assert!(word_score("ric", "rice") > 0.0);
+        assert_eq!(word_score("egg", "ego"), 0.0); // Short words allow no typos
+    }
+
+    #[test]
+    fn plurals_match_their_singular() {
+        assert_eq!(singular("apples"), "apple");
+        assert_eq!(singular("tomatoes"), "tomato");
+        assert_eq!(singular("glass"), "glass");
+        assert_eq!(search_words("Apples with Cinnamon"), ["apple", "cinnamon"]);
+    }
+
+    #[test]
+    fn finds_foods_despite_typos_and_plurals() {
+        let foods = foods(&["Apple", "Banana", "Chicken Breast", "Rice", "Rice Pudding"]);
+        assert_eq!(ids(&search(&foods, "banan", MatchMode::Any)), ["Banana"]);
+        assert_eq!(ids(&search(&foods, "bananna", MatchMode::Any)), ["Banana"]);
+        assert_eq!(ids(&search(&foods, "chiken", MatchMode::Any)), ["Chicken Breast"]);
+
+        let apples = search(&foods, "apples", MatchMode::Any);
+        assert_eq!(ids(&apples), ["Apple"]);
+        assert!(apples[0].exact);
+    }
+
+    #[test]
+    fn ranks_exact_names_first_and_respects_match_mode() {
+        let foods = foods(&["Rice", "Rice Pudding", "Chicken Breast"]);
+        assert_eq!(ids(&search(&foods, "rice", MatchMode::Any)), ["Rice", "Rice Pudding"]);
+        assert_eq!(ids(&search(&foods, "rice chicken", MatchMode::All)), Vec::<String>::new());
+        assert_eq!(ids(&search(&foods, "chicken brest", MatchMode::All)), ["Chicken Breast"]);
+        assert!(search(&foods, "with", MatchMode::Any).is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use chrono::{Local, NaiveDateTime};
+
+pub const DEFAULT_BACKUP_COUNT: usize = 10;
+const BACKUP_DIR: &str = "backups";
+const BACKUP_TIMESTAMP: &str = "%Y%m%d-%H%M%S-%3f";
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io { path: String, source: io::Error },
+    Parse { path: String, line: usize, column: usize, message: String },
+    Serialize { path: String, source: serde_json::Error },
+    ReadOnly(String), // The file failed to load, so saving over it is refused
+    NoBackup(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io { path, source } =>
+                write!(f, "cannot access {}: {}", path, source),
+            StoreError::Parse { path, line, column, message } =>
+                write!(f, "{} is not valid (line {}, column {}): {}", path, line, column, message),
+            StoreError::Serialize { path, source } =>
+                write!(f, "cannot encode data for {}: {}", path, source),
+            StoreError::ReadOnly(path) =>
+                write!(f, "not saving {} because it failed to load; fix the file or restore a backup first", path),
+            StoreError::NoBackup(path) =>
+                write!(f, "there are no backups of {}", path),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io { source, .. } => Some(source),
+            StoreError::Serialize { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Timestamped copy of an earlier version of a data file
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub original: String, // The data file this is a backup of
+    pub created: NaiveDateTime,
+}
+
+// Reads and parses a JSON file. A missing file is not an error and gives `None`.
+pub fn read_json<T: DeserializeOwned>(path: &str) -> Result<Option<T>, StoreError> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => return Err(StoreError::Io { path: path.to_string(), source }),
+    };
+    parse_json(path, &data).map(Some)
+}
+
+fn parse_json<T: DeserializeOwned>(path: &str, data: &str) -> Result<T, StoreError> {
+    serde_json::from_str(data).map_err(|e| {
+        // serde_json appends the position to its message; it is reported separately here
+        let suffix = format!(" at line {} column {}", e.line(), e.column());
+        let message = e.to_string();
+        StoreError::Parse {
+            path: path.to_string(),
+            line: e.line(),
+            column: e.column(),
+            message: message.strip_suffix(&suffix).unwrap_or(&message).to_string(),
+        }
+    })
+}
+
+// Writes a value as pretty JSON, keeping up to `keep` backups of the versions it replaces
+pub fn write_json<T: Serialize>(path: &str, value: &T, keep: usize) -> Result<(), StoreError> {
+    let data = serde_json::to_string_pretty(value)
+        .map_err(|source| StoreError::Serialize { path: path.to_string(), source })?;
+    replace_file(path, &data, keep)
+}
+
+// Rolls a data file back to one of its backups. The version being replaced is backed up too,
+// so a restore can itself be undone by restoring again.
+pub fn restore_backup(backup: &Backup, keep: usize) -> Result<(), StoreError> {
+    let backup_path = backup.path.to_string_lossy().into_owned();
+    let data = fs::read_to_string(&backup.path)
+        .map_err(|source| StoreError::Io { path: backup_path.clone(), source })?;
+    parse_json::<serde_json::Value>(&backup_path, &data)?;
+    replace_file(&backup.original, &data, keep)
+}
+
+// Backups of a data file, newest first
+pub fn list_backups(path: &str) -> Vec<Backup> {
+    let (dir, stem, extension) = backup_name_parts(path);
+    let prefix = format!("{}-", stem);
+    let suffix = format!(".{}", extension);
+
+    let mut backups: Vec<Backup> = fs::read_dir(&dir).into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let timestamp = name.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+            let created = NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP).ok()?;
+            Some(Backup { path: entry.path(), original: path.to_string(), created })
+        })
+        .collect();
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created));
+    backups
+}
+
+pub fn latest_backup(path: &str) -> Option<Backup> {
+    list_backups(path).into_iter().next()
+}
+
+// Writes the new contents to a temporary file next to the target and renames it into place,
+// so a crash or a full disk leaves either the old file or the new one, never a truncated mix.
+// The old contents are backed up first unless they are unchanged or not valid JSON, so a
+// broken file never pushes a good backup out of the rotation.
+fn replace_file(path: &str, data: &str, keep: usize) -> Result<(), StoreError> {
+    let io_error = |path: &Path| {
+        let path = path.to_string_lossy().into_owned();
+        move |source| StoreError::Io { path, source }
+    };
+
+    if let Ok(previous) = fs::read_to_string(path) {
+        if previous == data {
+            return Ok(());
+        }
+        if keep > 0 && serde_json::from_str::<serde_json::Value>(&previous).is_ok() {
+            let (dir, stem, extension) = backup_name_parts(path);
+            fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+            let name = format!("{}-{}.{}", stem, Local::now().naive_local().format(BACKUP_TIMESTAMP), extension);
+            let backup = dir.join(name);
+            fs::write(&backup, &previous).map_err(io_error(&backup))?;
+            prune_backups(path, keep);
+        }
+    }
+
+    let target = Path::new(path);
+    let temp = target.with_file_name(format!(
+        "{}.tmp",
+        target.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+    ));
+    let mut file = fs::File::create(&temp).map_err(io_error(&temp))?;
+    let written = file.write_all(data.as_bytes()).and_then(|_| file.sync_all());
+    drop(file);
+    if let Err(source) = written.and_then(|_| fs::rename(&temp, target)) {
+        let _ = fs::remove_file(&temp);
+        return Err(StoreError::Io { path: path.to_string(), source });
+    }
+    // Make the rename itself durable where the platform allows syncing a directory
+    if let Some(dir) = target.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let _ = fs::File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+fn prune_backups(path: &str, keep: usize) {
+    for old in list_backups(path).into_iter().skip(keep) {
+        let _ = fs::remove_file(old.path);
+    }
+}
+
+// Backups live in a `backups` directory beside the data file and are named
+// `<stem>-<timestamp>.<extension>`, e.g. `backups/food_db-20250408-093015-123.json`
+fn backup_name_parts(path: &str) -> (PathBuf, String, String) {
+    let path = Path::new(path);
+    let dir = path.parent().unwrap_or_else(|| Path::new("")).join(BACKUP_DIR);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_else(|| "json".to_string());
+    (dir, stem, extension)
+}
@@ -0,0 +1,140 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use chrono::NaiveDate;
+
+// Share of the gap between the trend and a new weigh-in that is closed per day. Small values
+// iron out day-to-day water swings; 0.1 is the usual choice for daily weigh-ins.
+pub const SMOOTHING: f32 = 0.1;
+
+// Energy stored in a kilogram of body weight, used to turn a weight trend into kcal per day
+pub const KCAL_PER_KG: f32 = 7700.0;
+
+// One weigh-in. Measurements are free-form names such as "waist", in centimetres.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WeightEntry {
+    pub weight_kg: f32,
+    #[serde(default)]
+    pub body_fat_pct: Option<f32>,
+    #[serde(default)]
+    pub measurements: BTreeMap<String, f32>,
+}
+
+impl WeightEntry {
+    pub fn new(weight_kg: f32) -> Self {
+        WeightEntry { weight_kg, body_fat_pct: None, measurements: BTreeMap::new() }
+    }
+
+    // Rejects values no scale gives. NaN and infinity would also be saved as null and stop the
+    // profile from loading.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.weight_kg.is_finite() || self.weight_kg <= 0.0 {
+            return Err(format!("weight must be a positive number of kg, got {}", self.weight_kg));
+        }
+        if let Some(body_fat) = self.body_fat_pct.filter(|pct| !(0.0..=100.0).contains(pct)) {
+            return Err(format!("body fat must be between 0 and 100%, got {}", body_fat));
+        }
+        if let Some((name, cm)) = self.measurements.iter().find(|(_, cm)| !cm.is_finite() || **cm <= 0.0) {
+            return Err(format!("{} must be a positive number of cm, got {}", name, cm));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for WeightEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} kg", self.weight_kg)?;
+        if let Some(body_fat) = self.body_fat_pct {
+            write!(f, ", {:.1}% body fat", body_fat)?;
+        }
+        for (name, cm) in &self.measurements {
+            write!(f, ", {} {:.1} cm", name, cm)?;
+        }
+        Ok(())
+    }
+}
+
+// Parses measurements such as "waist=82, hips=96". Lengths must be positive.
+pub fn parse_measurements(input: &str) -> Option<BTreeMap<String, f32>> {
+    input.split(',')
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (name, cm) = part.split_once('=')?;
+            let cm: f32 = cm.trim().trim_end_matches("cm").trim().parse().ok()
+                .filter(|cm: &f32| cm.is_finite() && *cm > 0.0)?;
+            Some((name.trim().to_lowercase(), cm))
+        })
+        .collect()
+}
+
+// Which weight the calorie target is calculated from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightSource {
+    Profile, // The fixed weight in the profile
+    #[default]
+    Latest, // The most recent weigh-in on or before the date
+    Smoothed, // The smoothed trend as of the date
+}
+
+impl FromStr for WeightSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "profile" | "fixed" => Ok(WeightSource::Profile),
+            "latest" => Ok(WeightSource::Latest),
+            "smoothed" | "trend" => Ok(WeightSource::Smoothed),
+            other => Err(format!("unknown weight source '{}' (expected profile, latest or smoothed)", other)),
+        }
+    }
+}
+
+impl WeightSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WeightSource::Profile => "profile",
+            WeightSource::Latest => "latest",
+            WeightSource::Smoothed => "smoothed",
+        }
+    }
+}
+
+// A weigh-in together with the smoothed trend at that date
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TrendPoint {
+    pub date: String,
+    pub weight_kg: f32,
+    pub trend_kg: f32,
+    pub body_fat_pct: Option<f32>,
+}
+
+// Exponentially smoothed trend through the weigh-ins, in date order. Gaps between weigh-ins
+// count as several days of smoothing, so a reading after a week away moves the trend further.
+pub fn weight_trend(weights: &BTreeMap<String, WeightEntry>) -> Vec<TrendPoint> {
+    let mut points: Vec<TrendPoint> = Vec::new();
+    let mut previous: Option<(NaiveDate, f32)> = None;
+    for (date, entry) in weights {
+        let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") else {
+            continue;
+        };
+        let trend = match previous {
+            Some((last_day, trend)) => {
+                let days = (day - last_day).num_days().max(1) as i32;
+                let alpha = 1.0 - (1.0 - SMOOTHING).powi(days);
+                trend + alpha * (entry.weight_kg - trend)
+            },
+            None => entry.weight_kg,
+        };
+        previous = Some((day, trend));
+        points.push(TrendPoint {
+            date: date.clone(),
+            weight_kg: entry.weight_kg,
+            trend_kg: trend,
+            body_fat_pct: entry.body_fat_pct,
+        });
+    }
+    points
+}